@@ -1,50 +1,288 @@
-use std::collections::HashMap;
-
-#[derive(Clone)]
-pub enum Instruction {
-    And(String, String),
-    Or(String, String),
-    Not(String),
-    Lshift(String, u8),
-    Rshift(String, u8),
-    Equal(String),
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt::Display;
+use std::num::ParseIntError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CircuitError {
+    Cycle(Vec<String>),
+    LineMalformed(String),
+    MultipleDrivers(String),
+    ParseIntError(ParseIntError),
+    Undriven(String),
+    UnknownWire(String),
+}
+
+impl From<ParseIntError> for CircuitError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl Display for CircuitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle(wires) => write!(f, "Wires depend on each other in a cycle: {}", wires.join(" -> ")),
+            Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
+            Self::MultipleDrivers(w) => write!(f, "Wire {w} has more than one signal connected to it"),
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::Undriven(w) => write!(f, "Wire {w} is used, but no signal is connected to it"),
+            Self::UnknownWire(w) => write!(f, "There is no wire {w}"),
+        }
+    }
 }
 
-impl Instruction {
-    fn parse(line: &str) -> (String, Instruction) {
-        let components: Vec<&str> = line.split(' ').collect();
-        match components[1] {
-            "->" => (components[2].to_string(), Instruction::Equal(components[0].to_string())),
-            "AND" => (components[4].to_string(), Instruction::And(components[0].to_string(), components[2].to_string())),
-            "OR" => (components[4].to_string(), Instruction::Or(components[0].to_string(), components[2].to_string())),
-            "LSHIFT" => (components[4].to_string(), Instruction::Lshift(components[0].to_string(), components[2].parse().unwrap())),
-            "RSHIFT" => (components[4].to_string(), Instruction::Rshift(components[0].to_string(), components[2].parse().unwrap())),
-            _ => (components[3].to_string(), Instruction::Not(components[1].to_string())),
+pub type WireId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    Wire(WireId),
+    Value(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gate {
+    And(Signal, Signal),
+    Or(Signal, Signal),
+    Not(Signal),
+    Lshift(Signal, u8),
+    Rshift(Signal, u8),
+    Equal(Signal),
+}
+
+impl Gate {
+    fn inputs(&self) -> Vec<Signal> {
+        match *self {
+            Self::And(l, r) | Self::Or(l, r) => vec![l, r],
+            Self::Not(s) | Self::Lshift(s, _) | Self::Rshift(s, _) | Self::Equal(s) => vec![s],
         }
     }
 }
 
-pub fn eval_for(wire: String, circuit: &mut HashMap<String, Instruction>) -> u16 {
-    if let Ok(num) = wire.parse::<u16>() {
-        return num;
-    }
-    let instruction = Instruction::clone(circuit.get(&wire).unwrap_or_else(|| panic!("Wire not found: {wire}")));
-    let result = match instruction {
-        Instruction::Equal(val) => eval_for(val, circuit),
-        Instruction::Not(val) => !(eval_for(val, circuit)),
-        Instruction::And(l, r) => (eval_for(l, circuit)) & (eval_for(r, circuit)),
-        Instruction::Or(l, r) => (eval_for(l, circuit)) | (eval_for(r, circuit)),
-        Instruction::Lshift(val, bits) => eval_for(val, circuit) << bits,
-        Instruction::Rshift(val, bits) => eval_for(val, circuit) >> bits,
-    };
-    circuit.insert(wire, Instruction::Equal(result.to_string()));
-    result
+/// A netlist of named wires. Names are interned into `WireId`s, and wires are
+/// kept in topological order, so every wire comes after the ones it reads.
+#[derive(Clone, Debug)]
+pub struct Circuit {
+    names: Vec<String>,
+    ids: HashMap<String, WireId>,
+    gates: Vec<Gate>,
+    // The wires reading each wire.
+    dependents: Vec<Vec<WireId>>,
+    // Where each wire is in the topological order.
+    rank: Vec<usize>,
+    overrides: HashMap<WireId, u16>,
+    values: Vec<u16>,
+}
+
+fn intern(name: &str, names: &mut Vec<String>, ids: &mut HashMap<String, WireId>) -> WireId {
+    *ids.entry(name.to_string()).or_insert_with(|| {
+        names.push(name.to_string());
+        names.len() - 1
+    })
+}
+
+impl TryFrom<&str> for Circuit {
+    type Error = CircuitError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut names = Vec::new();
+        let mut ids = HashMap::new();
+        let mut drivers: Vec<(WireId, Gate)> = Vec::new();
+        for line in value.lines() {
+            let mut signal = |s: &str| match s.parse() {
+                Ok(value) => Signal::Value(value),
+                Err(_) => Signal::Wire(intern(s, &mut names, &mut ids)),
+            };
+            // Shifting a 16 bit signal by more than 15 bits isn't defined.
+            let shift = |bits: &str| match bits.parse::<u8>()? {
+                bits @ 0..=15 => Ok(bits),
+                _ => Err(CircuitError::LineMalformed(line.to_string())),
+            };
+            let components: Vec<_> = line.split(' ').collect();
+            let (gate, output) = match components[..] {
+                [input, "->", output] => (Gate::Equal(signal(input)), output),
+                ["NOT", input, "->", output] => (Gate::Not(signal(input)), output),
+                [l, "AND", r, "->", output] => (Gate::And(signal(l), signal(r)), output),
+                [l, "OR", r, "->", output] => (Gate::Or(signal(l), signal(r)), output),
+                [input, "LSHIFT", bits, "->", output] => (Gate::Lshift(signal(input), shift(bits)?), output),
+                [input, "RSHIFT", bits, "->", output] => (Gate::Rshift(signal(input), shift(bits)?), output),
+                _ => return Err(CircuitError::LineMalformed(line.to_string())),
+            };
+            drivers.push((intern(output, &mut names, &mut ids), gate));
+        }
+
+        let mut gates = vec![None; names.len()];
+        for (wire, gate) in drivers {
+            if gates[wire].replace(gate).is_some() {
+                return Err(CircuitError::MultipleDrivers(names[wire].clone()));
+            }
+        }
+        let gates = gates.into_iter().enumerate()
+            .map(|(wire, gate)| gate.ok_or_else(|| CircuitError::Undriven(names[wire].clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut dependents = vec![Vec::new(); names.len()];
+        for (wire, gate) in gates.iter().enumerate() {
+            for input in gate.inputs() {
+                if let Signal::Wire(input) = input {
+                    dependents[input].push(wire);
+                }
+            }
+        }
+        let mut circuit = Self {
+            names,
+            ids,
+            gates,
+            dependents,
+            rank: Vec::new(),
+            overrides: HashMap::new(),
+            values: Vec::new(),
+        };
+        let order = circuit.topological_order()?;
+        circuit.rank = vec![0; order.len()];
+        for (rank, &wire) in order.iter().enumerate() {
+            circuit.rank[wire] = rank;
+        }
+        circuit.values = vec![0; order.len()];
+        for wire in order {
+            circuit.values[wire] = circuit.compute(wire);
+        }
+        Ok(circuit)
+    }
 }
 
-pub fn assemble(input: &str) -> HashMap<String, Instruction> {
-    input.lines()
-        .map(Instruction::parse)
-        .collect()
+impl Circuit {
+    // Kahn's algorithm. Whatever can't be ordered is part of a cycle, or
+    // reads from one.
+    fn topological_order(&self) -> Result<Vec<WireId>, CircuitError> {
+        let wire_inputs = |wire: WireId| self.gates[wire].inputs().into_iter().filter_map(|s| match s {
+            Signal::Wire(w) => Some(w),
+            Signal::Value(_) => None,
+        });
+        let mut missing: Vec<usize> = (0..self.names.len()).map(|wire| wire_inputs(wire).count()).collect();
+        let mut ready: VecDeque<_> = (0..self.names.len()).filter(|&wire| missing[wire] == 0).collect();
+        let mut order = Vec::with_capacity(self.names.len());
+        while let Some(wire) = ready.pop_front() {
+            order.push(wire);
+            for &dependent in &self.dependents[wire] {
+                missing[dependent] -= 1;
+                if missing[dependent] == 0 {
+                    ready.push_back(dependent);
+                }
+            }
+        }
+        if order.len() == self.names.len() {
+            return Ok(order);
+        }
+        // Walk backwards through unordered inputs until we get somewhere we
+        // have been before. That is where the cycle closes.
+        let mut wire = (0..self.names.len()).find(|&wire| missing[wire] > 0).unwrap();
+        let mut path = Vec::new();
+        while !path.contains(&wire) {
+            path.push(wire);
+            wire = wire_inputs(wire).find(|&input| missing[input] > 0).unwrap();
+        }
+        // The path leads against the flow of signals, so turn it around.
+        let start = path.iter().position(|&w| w == wire).unwrap();
+        let cycle = [wire].into_iter().chain(path[start + 1..].iter().rev().copied()).chain([wire]);
+        Err(CircuitError::Cycle(cycle.map(|w| self.names[w].clone()).collect()))
+    }
+
+    fn signal(&self, signal: Signal) -> u16 {
+        match signal {
+            Signal::Wire(wire) => self.values[wire],
+            Signal::Value(value) => value,
+        }
+    }
+
+    fn compute(&self, wire: WireId) -> u16 {
+        if let Some(&value) = self.overrides.get(&wire) {
+            return value;
+        }
+        match self.gates[wire] {
+            Gate::And(l, r) => self.signal(l) & self.signal(r),
+            Gate::Or(l, r) => self.signal(l) | self.signal(r),
+            Gate::Not(s) => !self.signal(s),
+            Gate::Lshift(s, bits) => self.signal(s) << bits,
+            Gate::Rshift(s, bits) => self.signal(s) >> bits,
+            Gate::Equal(s) => self.signal(s),
+        }
+    }
+
+    pub fn id(&self, name: &str) -> Result<WireId, CircuitError> {
+        self.ids.get(name).copied().ok_or_else(|| CircuitError::UnknownWire(name.to_string()))
+    }
+
+    pub fn name(&self, wire: WireId) -> &str {
+        &self.names[wire]
+    }
+
+    pub fn value(&self, name: &str) -> Result<u16, CircuitError> {
+        Ok(self.values[self.id(name)?])
+    }
+
+    // Recomputes `wire`, and everything downstream of it whose inputs
+    // actually changed, in topological order.
+    fn propagate(&mut self, wire: WireId) {
+        let mut pending = BTreeSet::from([(self.rank[wire], wire)]);
+        while let Some((_, wire)) = pending.pop_first() {
+            let value = self.compute(wire);
+            if value != self.values[wire] {
+                self.values[wire] = value;
+                pending.extend(self.dependents[wire].iter().map(|&d| (self.rank[d], d)));
+            }
+        }
+    }
+
+    /// Forces `name` to carry `value`, no matter what is connected to it.
+    pub fn override_wire(&mut self, name: &str, value: u16) -> Result<(), CircuitError> {
+        let wire = self.id(name)?;
+        self.overrides.insert(wire, value);
+        self.propagate(wire);
+        Ok(())
+    }
+
+    /// Lets `name` carry its regular signal again.
+    pub fn restore(&mut self, name: &str) -> Result<(), CircuitError> {
+        let wire = self.id(name)?;
+        self.overrides.remove(&wire);
+        self.propagate(wire);
+        Ok(())
+    }
+
+    fn describe(&self, signal: Signal) -> String {
+        match signal {
+            Signal::Wire(wire) => self.names[wire].clone(),
+            Signal::Value(value) => value.to_string(),
+        }
+    }
+
+    /// The circuit in Graphviz DOT format. Every wire is a node showing its
+    /// gate and current value, and overridden wires are highlighted.
+    pub fn to_dot(&self) -> String {
+        let mut wires: Vec<_> = (0..self.names.len()).collect();
+        wires.sort_by_key(|&wire| self.rank[wire]);
+        let mut dot = String::from("digraph circuit {\n");
+        for &wire in &wires {
+            let gate = match self.gates[wire] {
+                Gate::And(l, r) => format!("{} AND {}", self.describe(l), self.describe(r)),
+                Gate::Or(l, r) => format!("{} OR {}", self.describe(l), self.describe(r)),
+                Gate::Not(s) => format!("NOT {}", self.describe(s)),
+                Gate::Lshift(s, bits) => format!("{} LSHIFT {bits}", self.describe(s)),
+                Gate::Rshift(s, bits) => format!("{} RSHIFT {bits}", self.describe(s)),
+                Gate::Equal(s) => self.describe(s),
+            };
+            let style = if self.overrides.contains_key(&wire) { ", style=filled, fillcolor=orange" } else { "" };
+            dot += &format!("    \"{0}\" [label=\"{0} = {gate}\\n{1}\"{style}];\n", self.names[wire], self.values[wire]);
+        }
+        for &wire in &wires {
+            for input in self.gates[wire].inputs() {
+                if let Signal::Wire(input) = input {
+                    dot += &format!("    \"{}\" -> \"{}\";\n", self.names[input], self.names[wire]);
+                }
+            }
+        }
+        dot + "}\n"
+    }
 }
 
 #[cfg(test)]
@@ -59,7 +297,7 @@ mod tests {
     #[test]
     fn test_sample() {
         let sample_input = read_file("tests/sample_input");
-        let mut circuit = assemble(&sample_input);
+        let circuit = Circuit::try_from(&sample_input[..]).unwrap();
         let expected = [
             ("d", 72),
             ("e", 507),
@@ -71,17 +309,53 @@ mod tests {
             ("y", 456),
         ];
         for (wire, output) in expected {
-            assert_eq!(eval_for(wire.to_string(), &mut circuit), output);
+            assert_eq!(circuit.value(wire), Ok(output));
         }
     }
 
+    #[test]
+    fn test_circuit() {
+        let sample_input = read_file("tests/sample_input");
+        let mut circuit = Circuit::try_from(&sample_input[..]).unwrap();
+        circuit.override_wire("x", 1).unwrap();
+        assert_eq!(circuit.value("d"), Ok(0));
+        assert_eq!(circuit.value("e"), Ok(457));
+        assert_eq!(circuit.value("f"), Ok(4));
+        assert_eq!(circuit.value("x"), Ok(1));
+        circuit.override_wire("e", 0).unwrap();
+        circuit.restore("x").unwrap();
+        assert_eq!(circuit.value("d"), Ok(72));
+        assert_eq!(circuit.value("e"), Ok(0));
+        assert_eq!(circuit.value("q"), Err(CircuitError::UnknownWire("q".to_string())));
+
+        let dot = circuit.to_dot();
+        assert!(dot.starts_with("digraph circuit {\n"));
+        assert!(dot.contains("    \"d\" [label=\"d = x AND y\\n72\"];\n"));
+        assert!(dot.contains("    \"e\" [label=\"e = x OR y\\n0\", style=filled, fillcolor=orange];\n"));
+        assert!(dot.contains("    \"x\" -> \"d\";\n"));
+
+        assert_eq!(Circuit::try_from("a -> b\nb AND 1 -> c\nc -> a\n3 -> d").err(), Some(CircuitError::Cycle(vec!["a".to_string(), "b".to_string(), "c".to_string(), "a".to_string()])));
+        assert_eq!(Circuit::try_from("a -> b").err(), Some(CircuitError::Undriven("a".to_string())));
+        assert_eq!(Circuit::try_from("1 -> a\n2 -> a").err(), Some(CircuitError::MultipleDrivers("a".to_string())));
+        assert_eq!(Circuit::try_from("a XOR b -> c").err(), Some(CircuitError::LineMalformed("a XOR b -> c".to_string())));
+        assert_eq!(Circuit::try_from("1 -> x\nx LSHIFT 16 -> y").err(), Some(CircuitError::LineMalformed("x LSHIFT 16 -> y".to_string())));
+        assert_eq!(Circuit::try_from("1 -> x\nx RSHIFT 15 -> y").unwrap().value("y"), Ok(0));
+
+        // A long chain doesn't need any recursion.
+        let chain: String = (0..100_000).map(|idx| format!("w{idx} -> w{}\n", idx + 1)).collect::<String>() + "7 -> w0";
+        let mut circuit = Circuit::try_from(&chain[..]).unwrap();
+        assert_eq!(circuit.value("w100000"), Ok(7));
+        circuit.override_wire("w50000", 3).unwrap();
+        assert_eq!(circuit.value("w100000"), Ok(3));
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
-        let mut circuit = assemble(&challenge_input);
-        let first_a = eval_for(String::from("a"), &mut circuit.clone());
+        let mut circuit = Circuit::try_from(&challenge_input[..]).unwrap();
+        let first_a = circuit.value("a").unwrap();
         assert_eq!(first_a, 46065);
-        circuit.insert(String::from("b"), Instruction::Equal(first_a.to_string()));
-        assert_eq!(eval_for(String::from("a"), &mut circuit), 14134);
+        circuit.override_wire("b", first_a).unwrap();
+        assert_eq!(circuit.value("a"), Ok(14134));
     }
 }
@@ -0,0 +1,405 @@
+// A small JSON document model. Nodes live in a flat arena and refer to their
+// children by index, and both parsing and walking use an explicit stack, so
+// neither is limited by how deeply a document is nested.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub type NodeId = usize;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<NodeId>),
+    Object(Vec<(String, NodeId)>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnexpectedChar(char),
+    UnexpectedEnd,
+    InvalidEscape,
+    InvalidNumber,
+    ControlCharacter,
+}
+
+/// Where and why parsing failed. Lines and columns start at 1, and columns
+/// count characters, not bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JsonError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ErrorKind,
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}, column {}: ", self.line, self.column)?;
+        match self.kind {
+            ErrorKind::UnexpectedChar(c) => write!(f, "Unexpected character {c:?}"),
+            ErrorKind::UnexpectedEnd => write!(f, "Unexpected end of input"),
+            ErrorKind::InvalidEscape => write!(f, "Invalid escape sequence"),
+            ErrorKind::InvalidNumber => write!(f, "Invalid number"),
+            ErrorKind::ControlCharacter => write!(f, "Unescaped control character in string"),
+        }
+    }
+}
+
+/// Gets called for every node of a `Document`, parents before their children.
+/// Returning `false` when entering an object or array skips everything in it.
+pub trait Visitor {
+    fn enter_object(&mut self, _document: &Document, _members: &[(String, NodeId)]) -> bool { true }
+    fn enter_array(&mut self, _document: &Document, _items: &[NodeId]) -> bool { true }
+    fn visit_null(&mut self) {}
+    fn visit_bool(&mut self, _value: bool) {}
+    fn visit_number(&mut self, _value: f64) {}
+    fn visit_string(&mut self, _value: &str) {}
+}
+
+/// Adds up every number it visits.
+#[derive(Debug, Default)]
+pub struct NumberSum {
+    pub total: f64,
+}
+
+impl Visitor for NumberSum {
+    fn visit_number(&mut self, value: f64) {
+        self.total += value;
+    }
+}
+
+/// Wraps another visitor, but hides every object that has a property for
+/// which `predicate` holds (along with everything inside it) from it.
+pub struct SkipObjects<V, P> {
+    pub inner: V,
+    predicate: P,
+}
+
+impl<V: Visitor, P: Fn(&Document, NodeId) -> bool> SkipObjects<V, P> {
+    pub fn new(inner: V, predicate: P) -> Self {
+        Self { inner, predicate }
+    }
+}
+
+impl<V: Visitor, P: Fn(&Document, NodeId) -> bool> Visitor for SkipObjects<V, P> {
+    fn enter_object(&mut self, document: &Document, members: &[(String, NodeId)]) -> bool {
+        !members.iter().any(|&(_, id)| (self.predicate)(document, id)) && self.inner.enter_object(document, members)
+    }
+
+    fn enter_array(&mut self, document: &Document, items: &[NodeId]) -> bool {
+        self.inner.enter_array(document, items)
+    }
+
+    fn visit_null(&mut self) {
+        self.inner.visit_null()
+    }
+
+    fn visit_bool(&mut self, value: bool) {
+        self.inner.visit_bool(value)
+    }
+
+    fn visit_number(&mut self, value: f64) {
+        self.inner.visit_number(value)
+    }
+
+    fn visit_string(&mut self, value: &str) {
+        self.inner.visit_string(value)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Document {
+    nodes: Vec<Node>,
+    root: NodeId,
+}
+
+impl Document {
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    /// Visits every node reachable from the root, in document order.
+    pub fn walk(&self, visitor: &mut impl Visitor) {
+        let mut pending = vec![self.root];
+        while let Some(id) = pending.pop() {
+            match &self.nodes[id] {
+                Node::Null => visitor.visit_null(),
+                Node::Bool(b) => visitor.visit_bool(*b),
+                Node::Number(n) => visitor.visit_number(*n),
+                Node::String(s) => visitor.visit_string(s),
+                Node::Array(items) => if visitor.enter_array(self, items) {
+                    pending.extend(items.iter().rev());
+                },
+                Node::Object(members) => if visitor.enter_object(self, members) {
+                    pending.extend(members.iter().rev().map(|&(_, id)| id));
+                },
+            }
+        }
+    }
+}
+
+impl FromStr for Document {
+    type Err = JsonError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser { input: s, pos: 0, nodes: Vec::new() }.parse()
+    }
+}
+
+// A container that is still waiting for its closing bracket.
+enum Open {
+    Array(Vec<NodeId>),
+    Object(Vec<(String, NodeId)>, String),
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    nodes: Vec<Node>,
+}
+
+impl Parser<'_> {
+    fn error_at(&self, pos: usize, kind: ErrorKind) -> JsonError {
+        let before = &self.input[..pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+        JsonError { line, column, kind }
+    }
+
+    // Complains about whatever is at the current position.
+    fn unexpected(&self) -> JsonError {
+        match self.input[self.pos..].chars().next() {
+            Some(c) => self.error_at(self.pos, ErrorKind::UnexpectedChar(c)),
+            None => self.error_at(self.pos, ErrorKind::UnexpectedEnd),
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.input.as_bytes().get(self.pos) {
+            self.pos += 1;
+        }
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn add(&mut self, node: Node) -> NodeId {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn parse(mut self) -> Result<Document, JsonError> {
+        let mut open: Vec<Open> = Vec::new();
+        loop {
+            let mut value = self.value(&mut open)?;
+            // Hand finished values to their parents until one of them
+            // expects another value.
+            while let Some(id) = value {
+                let close = match open.last_mut() {
+                    None => {
+                        if self.peek().is_some() {
+                            return Err(self.unexpected());
+                        }
+                        return Ok(Document { nodes: self.nodes, root: id });
+                    },
+                    Some(Open::Array(items)) => {
+                        items.push(id);
+                        b']'
+                    },
+                    Some(Open::Object(members, key)) => {
+                        members.push((std::mem::take(key), id));
+                        b'}'
+                    },
+                };
+                match self.peek() {
+                    Some(b',') => {
+                        self.pos += 1;
+                        if let Some(Open::Object(_, key)) = open.last_mut() {
+                            *key = self.key()?;
+                        }
+                        value = None;
+                    },
+                    Some(b) if b == close => {
+                        self.pos += 1;
+                        let node = match open.pop() {
+                            Some(Open::Array(items)) => Node::Array(items),
+                            Some(Open::Object(members, _)) => Node::Object(members),
+                            None => unreachable!(),
+                        };
+                        value = Some(self.add(node));
+                    },
+                    _ => return Err(self.unexpected()),
+                }
+            }
+        }
+    }
+
+    // Reads a value. Returns its node if it is complete, or `None` if it opened
+    // a container that still needs its contents.
+    fn value(&mut self, open: &mut Vec<Open>) -> Result<Option<NodeId>, JsonError> {
+        let node = match self.peek() {
+            Some(b'[') => {
+                self.pos += 1;
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    Node::Array(Vec::new())
+                } else {
+                    open.push(Open::Array(Vec::new()));
+                    return Ok(None);
+                }
+            },
+            Some(b'{') => {
+                self.pos += 1;
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    Node::Object(Vec::new())
+                } else {
+                    let key = self.key()?;
+                    open.push(Open::Object(Vec::new(), key));
+                    return Ok(None);
+                }
+            },
+            Some(b'"') => Node::String(self.string()?),
+            Some(b'-' | b'0'..=b'9') => Node::Number(self.number()?),
+            Some(b't') => self.literal("true", Node::Bool(true))?,
+            Some(b'f') => self.literal("false", Node::Bool(false))?,
+            Some(b'n') => self.literal("null", Node::Null)?,
+            _ => return Err(self.unexpected()),
+        };
+        Ok(Some(self.add(node)))
+    }
+
+    fn key(&mut self) -> Result<String, JsonError> {
+        if self.peek() != Some(b'"') {
+            return Err(self.unexpected());
+        }
+        let key = self.string()?;
+        self.expect(b':')?;
+        Ok(key)
+    }
+
+    fn literal(&mut self, word: &str, node: Node) -> Result<Node, JsonError> {
+        for expected in word.bytes() {
+            if self.input.as_bytes().get(self.pos) != Some(&expected) {
+                return Err(self.unexpected());
+            }
+            self.pos += 1;
+        }
+        Ok(node)
+    }
+
+    fn number(&mut self) -> Result<f64, JsonError> {
+        let start = self.pos;
+        let bytes = self.input.as_bytes();
+        let digits = |pos: &mut usize| {
+            let from = *pos;
+            while bytes.get(*pos).is_some_and(u8::is_ascii_digit) {
+                *pos += 1;
+            }
+            *pos > from
+        };
+        let mut pos = self.pos;
+        if bytes[pos] == b'-' {
+            pos += 1;
+        }
+        let valid = match bytes.get(pos) {
+            Some(b'0') => {
+                pos += 1;
+                true
+            },
+            _ => digits(&mut pos),
+        } && (bytes.get(pos) != Some(&b'.') || {
+            pos += 1;
+            digits(&mut pos)
+        }) && (!matches!(bytes.get(pos), Some(b'e' | b'E')) || {
+            pos += 1;
+            if matches!(bytes.get(pos), Some(b'+' | b'-')) {
+                pos += 1;
+            }
+            digits(&mut pos)
+        });
+        self.pos = pos;
+        match valid {
+            true => self.input[start..pos].parse().map_err(|_| self.error_at(start, ErrorKind::InvalidNumber)),
+            false => Err(self.error_at(start, ErrorKind::InvalidNumber)),
+        }
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, JsonError> {
+        let start = self.pos - 2;
+        let digits = self.input.get(self.pos..self.pos + 4).ok_or_else(|| self.error_at(start, ErrorKind::InvalidEscape))?;
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(self.error_at(start, ErrorKind::InvalidEscape));
+        }
+        self.pos += 4;
+        u32::from_str_radix(digits, 16).map_err(|_| self.error_at(start, ErrorKind::InvalidEscape))
+    }
+
+    // Reads a string, starting at its opening quote.
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;
+        let mut result = String::new();
+        loop {
+            let rest = &self.input[self.pos..];
+            let Some(end) = rest.find(['"', '\\']) else {
+                return Err(self.error_at(self.input.len(), ErrorKind::UnexpectedEnd));
+            };
+            if let Some(offset) = rest[..end].find(|c: char| c < ' ') {
+                return Err(self.error_at(self.pos + offset, ErrorKind::ControlCharacter));
+            }
+            result.push_str(&rest[..end]);
+            self.pos += end + 1;
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(result);
+            }
+            let start = self.pos - 1;
+            let escaped = match self.input.as_bytes().get(self.pos) {
+                Some(b'"') => '"',
+                Some(b'\\') => '\\',
+                Some(b'/') => '/',
+                Some(b'b') => '\u{8}',
+                Some(b'f') => '\u{c}',
+                Some(b'n') => '\n',
+                Some(b'r') => '\r',
+                Some(b't') => '\t',
+                Some(b'u') => {
+                    self.pos += 1;
+                    let mut code = self.hex_escape()?;
+                    if (0xD800..0xDC00).contains(&code) {
+                        // The high half of a surrogate pair, which must be
+                        // followed by the low half.
+                        if !self.input[self.pos..].starts_with("\\u") {
+                            return Err(self.error_at(start, ErrorKind::InvalidEscape));
+                        }
+                        self.pos += 2;
+                        let low = self.hex_escape()?;
+                        if !(0xDC00..0xE000).contains(&low) {
+                            return Err(self.error_at(start, ErrorKind::InvalidEscape));
+                        }
+                        code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                    }
+                    result.push(char::from_u32(code).ok_or_else(|| self.error_at(start, ErrorKind::InvalidEscape))?);
+                    continue;
+                },
+                None => return Err(self.error_at(self.pos, ErrorKind::UnexpectedEnd)),
+                Some(_) => return Err(self.error_at(start, ErrorKind::InvalidEscape)),
+            };
+            result.push(escaped);
+            self.pos += 1;
+        }
+    }
+}
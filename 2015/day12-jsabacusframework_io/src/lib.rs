@@ -1,65 +1,17 @@
-pub fn run(input: &str) -> (i32, i32) {
-    let first = input.lines().map(sumup_numbers).sum();
-    let second = input.lines().map(|line| sumup_numbers(&filter_red(line))).sum();
-    (first, second)
-}
-
-fn sumup_numbers(line: &str) -> i32 {
-    let mut sum = 0;
+mod json;
+pub use json::{Document, ErrorKind, JsonError, Node, NodeId, NumberSum, SkipObjects, Visitor};
 
-    let mut sign = 1_i32;
-    let mut this_number = 0_u32;
-    for c in line.chars() {
-        if c == '-' {
-            sign *= -1;
-        } else if let Some(d) = c.to_digit(10) {
-            this_number *= 10;
-            this_number += d;
-        } else {
-            sum += this_number as i32 * sign;
-            this_number = 0;
-            sign = 1
-        }
+/// Reads one JSON document per line, and adds up all the numbers in them,
+/// first all of them, then only those outside of objects with a "red" property.
+pub fn run(input: &str) -> Result<(i64, i64), JsonError> {
+    let mut first = NumberSum::default();
+    let mut second = SkipObjects::new(NumberSum::default(), |document: &Document, id| matches!(document.node(id), Node::String(s) if s == "red"));
+    for (idx, line) in input.lines().enumerate() {
+        let document: Document = line.parse().map_err(|e: JsonError| JsonError { line: idx + 1, ..e })?;
+        document.walk(&mut first);
+        document.walk(&mut second);
     }
-   // dbg!(sum)
-    sum
-}
-
-fn filter_red(line: &str) -> String {
-    let left_square: Vec<_> = line.match_indices('[').map(|(idx, _)| idx).collect();
-    let right_square: Vec<_> = line.match_indices(']').map(|(idx, _)| idx).collect();
-    let left_curly: Vec<_> = line.match_indices('{').map(|(idx, _)| idx).collect();
-    let right_curly: Vec<_> = line.match_indices('}').map(|(idx, _)| idx).collect();
-    let reds: Vec<_> = line.match_indices("red").map(|(idx, _)| idx).collect();
-
-    let mut filtered = String::from(line);
-
-    for red in &reds {
-        let left_count = left_curly.partition_point(|lc| lc < red);
-        let right_count = right_curly.partition_point(|rc| rc < red);
-        let curly_level = left_count - right_count;
-        let start = *left_curly.iter().take(left_count).rev().find(|lc| left_curly.partition_point(|left| left <= lc) - right_curly.partition_point(|right| right <= lc) == curly_level).unwrap();
-        
-        // skip reds that are in an array, unless they are part of an object inside the array
-        let left_square_count = left_square.partition_point(|ls| ls < red);
-        let square_level = left_square_count - right_square.partition_point(|rs| rs < red); 
-        if square_level > 0 {
-            let opening_square = *left_square.iter().take(left_square_count).rev().find(|ls| left_square.partition_point(|left| left <= ls) - right_square.partition_point(|right| right <= ls) == square_level).unwrap();
-            if opening_square > start {
-                continue;
-            }
-        }
-        let end = *right_curly.iter()
-            .enumerate()
-            .skip(right_count)
-            .find(|(idx, end)| left_curly.partition_point(|lc| lc < end) - idx == curly_level)
-            .unwrap_or_else(|| panic!("Unable to find a right brace after {red} in {line}"))
-            .1 + 1;
-
-        filtered.replace_range(start..end, &"x".repeat(end-start));
-    }
-    
-    filtered
+    Ok((first.total as i64, second.inner.total as i64))
 }
 
 #[cfg(test)]
@@ -74,12 +26,45 @@ mod tests {
     #[test]
     fn test_sample() {
         let sample_input = read_file("tests/sample_input");
-        assert_eq!(run(&sample_input), (39, 22));
+        assert_eq!(run(&sample_input), Ok((39, 22)));
+    }
+
+    #[test]
+    fn test_parser() {
+        let document: Document = r#" {"red": [1, -2.5e1, true, null], "s": "r\u00e9d \"red\" \ud83d\ude00\n"} "#.parse().unwrap();
+        let Node::Object(members) = document.node(document.root()) else { panic!("Root is not an object") };
+        assert_eq!(members[0].0, "red");
+        assert_eq!(document.node(members[1].1), &Node::String("réd \"red\" 😀\n".to_string()));
+        let mut sum = NumberSum::default();
+        document.walk(&mut sum);
+        assert_eq!(sum.total, -24.0);
+        // A key named "red", or a string that merely contains it, doesn't count.
+        assert_eq!(run(r#"{"red":1,"a":"\"red\"","b":["red",2]}"#), Ok((3, 3)));
+
+        let error = |line: usize, column: usize, kind: ErrorKind| JsonError { line, column, kind };
+        assert_eq!("{[1,\"red\",5]}".parse::<Document>(), Err(error(1, 2, ErrorKind::UnexpectedChar('['))));
+        assert_eq!("[1,\n  2,]".parse::<Document>(), Err(error(2, 5, ErrorKind::UnexpectedChar(']'))));
+        assert_eq!("[\"é\", 01]".parse::<Document>(), Err(error(1, 8, ErrorKind::UnexpectedChar('1'))));
+        assert_eq!("[-]".parse::<Document>(), Err(error(1, 2, ErrorKind::InvalidNumber)));
+        assert_eq!("{\"a\":tru}".parse::<Document>(), Err(error(1, 9, ErrorKind::UnexpectedChar('}'))));
+        assert_eq!("\"\\x\"".parse::<Document>(), Err(error(1, 2, ErrorKind::InvalidEscape)));
+        assert_eq!("\"\\ud83d\"".parse::<Document>(), Err(error(1, 2, ErrorKind::InvalidEscape)));
+        assert_eq!("[1, 2".parse::<Document>(), Err(error(1, 6, ErrorKind::UnexpectedEnd)));
+        assert_eq!("1 2".parse::<Document>(), Err(error(1, 3, ErrorKind::UnexpectedChar('2'))));
+        assert_eq!(run("[1]\n[1,]"), Err(error(2, 4, ErrorKind::UnexpectedChar(']'))));
+
+        // Nesting depth is only limited by memory.
+        let depth = 1_000_000;
+        let deep = format!("{}1{}", "[{\"a\":".repeat(depth), "}]".repeat(depth));
+        let document: Document = deep.parse().unwrap();
+        let mut sum = NumberSum::default();
+        document.walk(&mut sum);
+        assert_eq!(sum.total, 1.0);
     }
 
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
-        assert_eq!(run(&challenge_input), (119433, 68466));
+        assert_eq!(run(&challenge_input), Ok((119433, 68466)));
     }
 }
@@ -1,6 +1,5 @@
+use std::collections::VecDeque;
 use std::str::FromStr;
-use std::thread;
-use std::sync::mpsc::{channel, Receiver, Sender};
 
 type RegIdx = usize;
 type RegVal = isize;
@@ -29,121 +28,237 @@ impl FromStr for Operand {
     }
 }
 
+fn parse_reg(s: &str) -> Result<RegIdx, ParseError> {
+    match s.as_bytes() {
+        [c @ b'a'..=b'z'] => Ok((c - b'a') as RegIdx),
+        _ => Err(ParseError),
+    }
+}
+
+// The instructions both interpretations of the program agree on.
 #[derive(Debug, Clone)]
-enum Instruction {
-    Snd(Operand),
+enum Op {
     Set(RegIdx, Operand),
     Add(RegIdx, Operand),
     Mul(RegIdx, Operand),
     Mod(RegIdx, Operand),
-    Rcv(RegIdx),
     Jgz(Operand, Operand),
 }
 
-impl FromStr for Instruction {
+impl Op {
+    fn parse(components: &[&str]) -> Result<Option<Self>, ParseError> {
+        match components {
+            ["set", to, from] => Ok(Some(Self::Set(parse_reg(to)?, from.parse()?))),
+            ["add", to, from] => Ok(Some(Self::Add(parse_reg(to)?, from.parse()?))),
+            ["mul", to, from] => Ok(Some(Self::Mul(parse_reg(to)?, from.parse()?))),
+            ["mod", to, from] => Ok(Some(Self::Mod(parse_reg(to)?, from.parse()?))),
+            ["jgz", cond, offset] => Ok(Some(Self::Jgz(cond.parse()?, offset.parse()?))),
+            _ => Ok(None),
+        }
+    }
+}
+
+// The `snd` and `rcv` instructions as understood before reading the rest of
+// the tablet: playing and recovering sounds.
+#[derive(Debug, Clone)]
+enum SoundInstruction {
+    Play(Operand),
+    Recover(Operand),
+    Op(Op),
+}
+
+impl FromStr for SoundInstruction {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let components: Vec<_> = s.split_whitespace().collect();
-
-        match components[0] {
-            "snd" => Ok(Self::Snd(components[1].parse()?)),
-            "set" => Ok(Self::Set((components[1].as_bytes()[0] - b'a') as RegIdx, components[2].parse()?)),
-            "add" => Ok(Self::Add((components[1].as_bytes()[0] - b'a') as RegIdx, components[2].parse()?)), 
-            "mul" => Ok(Self::Mul((components[1].as_bytes()[0] - b'a') as RegIdx, components[2].parse()?)), 
-            "mod" => Ok(Self::Mod((components[1].as_bytes()[0] - b'a') as RegIdx, components[2].parse()?)), 
-            "rcv" => Ok(Self::Rcv((components[1].as_bytes()[0] - b'a') as RegIdx)),
-            "jgz" => Ok(Self::Jgz(components[1].parse()?, components[2].parse()?)),
-            _ => panic!("Instruction not recognized: {s}"),
+        match components[..] {
+            ["snd", freq] => Ok(Self::Play(freq.parse()?)),
+            ["rcv", cond] => Ok(Self::Recover(cond.parse()?)),
+            _ => Op::parse(&components)?.map(Self::Op).ok_or(ParseError),
         }
     }
 }
 
-impl From<&str> for Instruction {
-    fn from(value: &str) -> Self {
-        value.parse().expect("Unable to parse {value} into an instruction")
-    }
-}
-
-impl Instruction {
-    fn perform(&self, registers: &mut [RegVal], out: &mut RegVal, next_instr_ptr: &mut RegIdx, version: u8, tx: &mut Sender<RegVal>, rx: &mut Receiver<RegVal>) -> Option<RegVal> {
-        *next_instr_ptr += 1;
-        let mut recovered = None;
-        match self {
-            Self::Snd(Operand::Reg(reg_idx)) => if version == 1 {
-                                        *out = registers[*reg_idx];
-                                    } else if tx.send(registers[*reg_idx]).is_ok() {
-                                        *out += 1;
-                                    } else {
-                                        return Some(*out);
-                                    },
-            Self::Snd(Operand::Val(val)) => if version == 1 {
-                                        *out = *val;
-                                    } else if tx.send(*val).is_ok() {
-                                        *out += 1;
-                                    } else {
-                                        return Some(*out);
-                                    },
-            Self::Set(to, Operand::Reg(from)) => registers[*to] = registers[*from],
-            Self::Set(to, Operand::Val(val)) => registers[*to] = *val,
-            Self::Add(to, Operand::Reg(from)) => registers[*to] += registers[*from],
-            Self::Add(to, Operand::Val(val)) => registers[*to] += *val,
-            Self::Mul(to, Operand::Reg(from)) => registers[*to] *= registers[*from],
-            Self::Mul(to, Operand::Val(val)) => registers[*to] *= *val,
-            Self::Mod(to, Operand::Reg(from)) => registers[*to] %= registers[*from],
-            Self::Mod(to, Operand::Val(val)) => registers[*to] %= *val,
-            Self::Rcv(reg_idx) => if version == 1 {
-                                        if registers[*reg_idx] != 0 { recovered = Some(*out) }
-                                    } else if let Ok(val) = rx.recv_timeout(std::time::Duration::from_millis(50)) {
-                                        registers[*reg_idx] = val;
-                                    } else {
-                                        return Some(*out);
-                                    },
-            Self::Jgz(Operand::Reg(reg_idx), Operand::Val(offset)) => if registers[*reg_idx] > 0 { *next_instr_ptr = (*next_instr_ptr as isize + offset - 1) as usize },
-            Self::Jgz(Operand::Val(val), Operand::Val(offset)) => if *val > 0 { *next_instr_ptr = (*next_instr_ptr as isize + offset - 1) as usize },
-            Self::Jgz(Operand::Reg(reg_idx), Operand::Reg(offset_reg)) => if registers[*reg_idx] > 0 { *next_instr_ptr = (*next_instr_ptr as isize + registers[*offset_reg] - 1) as usize },
-            Self::Jgz(Operand::Val(val), Operand::Reg(offset_reg)) => if *val > 0 { *next_instr_ptr = (*next_instr_ptr as isize + registers[*offset_reg] - 1) as usize },
+// ... and as they were actually meant: sending to and receiving from another
+// program.
+#[derive(Debug, Clone)]
+enum DuetInstruction {
+    Send(Operand),
+    Receive(RegIdx),
+    Op(Op),
+}
+
+impl FromStr for DuetInstruction {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let components: Vec<_> = s.split_whitespace().collect();
+        match components[..] {
+            ["snd", val] => Ok(Self::Send(val.parse()?)),
+            ["rcv", reg] => Ok(Self::Receive(parse_reg(reg)?)),
+            _ => Op::parse(&components)?.map(Self::Op).ok_or(ParseError),
         }
-        recovered
     }
 }
 
+fn parse_program<T: FromStr<Err = ParseError>>(input: &str) -> Vec<T> {
+    input.lines().map(|line| line.parse().unwrap_or_else(|_| panic!("Unable to parse {line} into an instruction"))).collect()
+}
+
 #[derive(Clone, Default)]
 struct Cpu {
-    instructions: Vec<Instruction>,
     registers: [RegVal; 26],
-    next_instr_ptr: usize,
-    out: RegVal,
+    next_instr_ptr: isize,
 }
 
 impl Cpu {
-    fn run(&mut self, version: u8, mut tx: Sender<RegVal>, mut rx: Receiver<RegVal>) -> RegVal {
+    fn get(&self, operand: &Operand) -> RegVal {
+        match operand {
+            Operand::Reg(reg_idx) => self.registers[*reg_idx],
+            Operand::Val(val) => *val,
+        }
+    }
+
+    fn current<'a, T>(&self, instructions: &'a [T]) -> Option<&'a T> {
+        usize::try_from(self.next_instr_ptr).ok().and_then(|ip| instructions.get(ip))
+    }
+
+    fn perform(&mut self, op: &Op) {
+        self.next_instr_ptr += 1;
+        match op {
+            Op::Set(to, from) => self.registers[*to] = self.get(from),
+            Op::Add(to, from) => self.registers[*to] += self.get(from),
+            Op::Mul(to, from) => self.registers[*to] *= self.get(from),
+            Op::Mod(to, from) => self.registers[*to] %= self.get(from),
+            Op::Jgz(cond, offset) => if self.get(cond) > 0 { self.next_instr_ptr += self.get(offset) - 1 },
+        }
+    }
+
+    /// Runs the sound interpretation until the first `rcv` with a nonzero
+    /// argument and returns the last sound played by then.
+    fn recover(&mut self, instructions: &[SoundInstruction]) -> Option<RegVal> {
+        let mut last_played = None;
+        while let Some(instruction) = self.current(instructions) {
+            match instruction {
+                SoundInstruction::Play(freq) => {
+                    last_played = Some(self.get(freq));
+                    self.next_instr_ptr += 1;
+                },
+                SoundInstruction::Recover(cond) => {
+                    if self.get(cond) != 0 {
+                        return last_played;
+                    }
+                    self.next_instr_ptr += 1;
+                },
+                SoundInstruction::Op(op) => self.perform(op),
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub executed: usize,
+    pub sent: usize,
+    pub received: usize,
+    pub rounds_blocked: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every program ran past the end of its instructions.
+    Terminated,
+    /// At least one program waits for a message that can never arrive.
+    Deadlock,
+}
+
+/// Runs several copies of a program in lockstep on a single thread. Program
+/// `i` starts with register `p` set to `i` and sends its messages to program
+/// `(i + 1) % n`.
+pub struct Scheduler {
+    instructions: Vec<DuetInstruction>,
+    cpus: Vec<Cpu>,
+    queues: Vec<VecDeque<RegVal>>,
+    stats: Vec<Stats>,
+}
+
+impl Scheduler {
+    pub fn new(input: &str, program_count: usize) -> Self {
+        let cpus = (0..program_count).map(|id| {
+            let mut cpu = Cpu::default();
+            cpu.registers[(b'p' - b'a') as usize] = id as RegVal;
+            cpu
+        }).collect();
+        Self {
+            instructions: parse_program(input),
+            cpus,
+            queues: vec![VecDeque::new(); program_count],
+            stats: vec![Stats::default(); program_count],
+        }
+    }
+
+    // Executes one instruction of program `id` unless it has terminated or
+    // waits on an empty queue. Returns whether it made any progress.
+    fn step(&mut self, id: usize) -> bool {
+        let cpu = &mut self.cpus[id];
+        let Some(instruction) = cpu.current(&self.instructions) else {
+            return false;
+        };
+        match instruction {
+            DuetInstruction::Send(val) => {
+                let val = cpu.get(val);
+                let target = (id + 1) % self.queues.len();
+                self.queues[target].push_back(val);
+                self.stats[id].sent += 1;
+                cpu.next_instr_ptr += 1;
+            },
+            DuetInstruction::Receive(reg_idx) => match self.queues[id].pop_front() {
+                Some(val) => {
+                    cpu.registers[*reg_idx] = val;
+                    self.stats[id].received += 1;
+                    cpu.next_instr_ptr += 1;
+                },
+                None => {
+                    self.stats[id].rounds_blocked += 1;
+                    return false;
+                },
+            },
+            DuetInstruction::Op(op) => cpu.perform(op),
+        }
+        self.stats[id].executed += 1;
+        true
+    }
+
+    /// Runs until no program can make progress anymore. Note that this never
+    /// returns for programs that loop forever without communicating.
+    pub fn run(&mut self) -> Outcome {
         loop {
-            if self.next_instr_ptr >= self.instructions.len() {
-                return self.out;
+            let mut progress = false;
+            for id in 0..self.cpus.len() {
+                progress |= self.step(id);
             }
-            let next_instruction = &self.instructions[self.next_instr_ptr];
-            if let Some(freq) = next_instruction.perform(&mut self.registers, &mut self.out, &mut self.next_instr_ptr, version, &mut tx, &mut rx) {
-                return freq;
+            if !progress {
+                return if self.cpus.iter().all(|cpu| cpu.current(&self.instructions).is_none()) {
+                    Outcome::Terminated
+                } else {
+                    Outcome::Deadlock
+                };
             }
         }
     }
+
+    pub fn stats(&self) -> &[Stats] {
+        &self.stats
+    }
 }
 
 pub fn run(input: &str) -> (isize, isize) {
-    let mut cpu = Cpu {
-        instructions: input.lines().map(Instruction::from).collect(),
-        ..Default::default()
-    };
-    let mut cpu_0 = cpu.clone();
-    let mut cpu_1 = cpu.clone();
-    cpu_1.registers[15] = 1;
-    let (tx, rx) = channel();
-    let (tx_1, rx_0) = channel();
-    let (tx_0, rx_1) = channel();
-    let first = thread::spawn(move || cpu.run(1, tx, rx)).join().unwrap();
-    thread::spawn(move || cpu_0.run(2, tx_0, rx_0));
-    let second = thread::spawn(move || cpu_1.run(2, tx_1, rx_1)).join().unwrap();
+    let first = Cpu::default().recover(&parse_program(input)).expect("Program terminated without recovering a sound");
+    let mut scheduler = Scheduler::new(input, 2);
+    scheduler.run();
+    let second = scheduler.stats()[1].sent as isize;
     (first, second)
 }
 
@@ -167,4 +282,24 @@ mod tests {
         let challenge_input = read_file("tests/challenge_input");
         assert_eq!(run(&challenge_input), (1187, 5969));
     }
+
+    #[test]
+    fn test_scheduler() {
+        let sample_input = read_file("tests/sample_input_2");
+        let mut scheduler = Scheduler::new(&sample_input, 2);
+        assert_eq!(scheduler.run(), Outcome::Deadlock);
+        let stats = scheduler.stats();
+        assert_eq!(stats[0], Stats { executed: 6, sent: 3, received: 3, rounds_blocked: 1 });
+        assert_eq!(stats[1], stats[0]);
+
+        // With three programs in a ring, every one of them still gets three
+        // messages, so they all end up waiting on the fourth.
+        let mut scheduler = Scheduler::new(&sample_input, 3);
+        assert_eq!(scheduler.run(), Outcome::Deadlock);
+        assert!(scheduler.stats().iter().all(|s| s.sent == 3 && s.received == 3));
+
+        let mut scheduler = Scheduler::new("snd 1\nrcv a\nadd a 1", 2);
+        assert_eq!(scheduler.run(), Outcome::Terminated);
+        assert_eq!(scheduler.stats()[0], Stats { executed: 3, sent: 1, received: 1, rounds_blocked: 0 });
+    }
 }
@@ -0,0 +1,382 @@
+// Lifts the flat `set/sub/mul/jnz` program into structured control flow.
+//
+// Forward conditional jumps become `if` blocks (or `if/else` if the skipped
+// block ends in an unconditional jump past the else part), backward jumps
+// become loops, and unconditional jumps out of a loop become `break`. Loops
+// that step a register by a constant until it hits a bound are recognised as
+// counted loops. On top of that we look for the well-known shape of the
+// puzzle: counting the composite numbers in a stepped range.
+
+use core::fmt::Display;
+
+use crate::{Cpu, Instruction, Operand, RegIdx, RegVal};
+
+// How long the code before the composite loop may run to set up its constants.
+const MAX_SETUP_STEPS: usize = 100_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LiftError {
+    RegisterJump(usize),
+    Unrecognized,
+    UnstructuredJump(usize),
+}
+
+impl Display for LiftError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RegisterJump(ip) => write!(f, "Jump at position {ip} has an offset that is only known at runtime"),
+            Self::Unrecognized => write!(f, "Program doesn't count the composite numbers in a range"),
+            Self::UnstructuredJump(ip) => write!(f, "Jump at position {ip} doesn't fit into structured control flow"),
+        }
+    }
+}
+
+fn reg_name(reg: RegIdx) -> char {
+    (b'a' + reg as u8) as char
+}
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reg(reg) => write!(f, "{}", reg_name(*reg)),
+            Self::Val(val) => write!(f, "{val}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cond {
+    Always,
+    NonZero(Operand),
+    Zero(Operand),
+}
+
+impl Display for Cond {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Always => write!(f, "true"),
+            Self::NonZero(op) => write!(f, "{op} != 0"),
+            Self::Zero(op) => write!(f, "{op} == 0"),
+        }
+    }
+}
+
+// `var` runs from `init` (if set right before the loop) to `bound` in steps of
+// `step`, excluding or including `bound`.
+#[derive(Debug, Clone)]
+struct Counter {
+    var: RegIdx,
+    init: Option<Operand>,
+    step: RegVal,
+    bound: Operand,
+    inclusive: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Op(Instruction),
+    If { cond: Cond, then: Vec<Stmt> },
+    IfElse { cond: Cond, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    // For counted loops, `body` doesn't include the counter updates and checks.
+    Loop { start: usize, counter: Option<Counter>, cond: Cond, body: Vec<Stmt> },
+    Break,
+    Halt,
+}
+
+impl Stmt {
+    fn writes(stmts: &[Self], reg: RegIdx) -> bool {
+        stmts.iter().any(|stmt| match stmt {
+            Self::Op(Instruction::Set(to, _) | Instruction::Sub(to, _) | Instruction::Mul(to, _)) => *to == reg,
+            Self::Op(Instruction::Jnz(_, _)) | Self::Break | Self::Halt => false,
+            Self::If { then, .. } => Self::writes(then, reg),
+            Self::IfElse { then, otherwise, .. } => Self::writes(then, reg) || Self::writes(otherwise, reg),
+            Self::Loop { counter, body, .. } => counter.as_ref().is_some_and(|c| c.var == reg) || Self::writes(body, reg),
+        })
+    }
+
+    // Whether any of `stmts` breaks out of the loop they are in.
+    fn breaks(stmts: &[Self]) -> bool {
+        stmts.iter().any(|stmt| match stmt {
+            Self::Break => true,
+            Self::If { then, .. } => Self::breaks(then),
+            Self::IfElse { then, otherwise, .. } => Self::breaks(then) || Self::breaks(otherwise),
+            _ => false,
+        })
+    }
+
+    fn new_loop(start: usize, mut body: Vec<Self>, cond: Cond) -> Self {
+        let counter = match (&cond, &body[..]) {
+            // do { ...; x -= -k; g = x; g -= bound } while g != 0
+            (Cond::NonZero(Operand::Reg(g)), [.., Self::Op(Instruction::Sub(x, Operand::Val(neg_step))), Self::Op(Instruction::Set(g1, Operand::Reg(x1))), Self::Op(Instruction::Sub(g2, bound))])
+                if g == g1 && g == g2 && x == x1 && x != g => Some((Counter { var: *x, init: None, step: -neg_step, bound: bound.clone(), inclusive: false }, 3)),
+            // loop { ...; g = x; g -= bound; if g == 0 { break }; x -= -k }
+            (Cond::Always, [.., Self::Op(Instruction::Set(g, Operand::Reg(x))), Self::Op(Instruction::Sub(g1, bound)), Self::If { cond: Cond::Zero(Operand::Reg(g2)), then }, Self::Op(Instruction::Sub(x1, Operand::Val(neg_step)))])
+                if g == g1 && g == g2 && x == x1 && x != g && matches!(then[..], [Self::Break]) => Some((Counter { var: *x, init: None, step: -neg_step, bound: bound.clone(), inclusive: true }, 4)),
+            _ => None,
+        };
+        match counter {
+            Some((counter, control_len)) => {
+                let inner = &body[..body.len() - control_len];
+                let bound_fixed = match counter.bound {
+                    Operand::Reg(reg) => !Self::writes(inner, reg),
+                    Operand::Val(_) => true,
+                };
+                if counter.step != 0 && bound_fixed && !Self::writes(inner, counter.var) && !Self::breaks(inner) {
+                    body.truncate(body.len() - control_len);
+                    Self::Loop { start, counter: Some(counter), cond, body }
+                } else {
+                    Self::Loop { start, counter: None, cond, body }
+                }
+            },
+            None => Self::Loop { start, counter: None, cond, body },
+        }
+    }
+
+    fn fmt_block(stmts: &[Self], f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
+        let pad = "    ".repeat(indent);
+        for stmt in stmts {
+            match stmt {
+                Self::Op(Instruction::Set(to, from)) => writeln!(f, "{pad}{} = {from}", reg_name(*to))?,
+                Self::Op(Instruction::Sub(to, Operand::Val(val))) if *val < 0 => writeln!(f, "{pad}{} += {}", reg_name(*to), -val)?,
+                Self::Op(Instruction::Sub(to, from)) => writeln!(f, "{pad}{} -= {from}", reg_name(*to))?,
+                Self::Op(Instruction::Mul(to, from)) => writeln!(f, "{pad}{} *= {from}", reg_name(*to))?,
+                Self::Op(Instruction::Jnz(_, _)) => unreachable!("jumps are lifted into control flow"),
+                Self::If { cond, then } => {
+                    writeln!(f, "{pad}if {cond} {{")?;
+                    Self::fmt_block(then, f, indent + 1)?;
+                    writeln!(f, "{pad}}}")?;
+                },
+                Self::IfElse { cond, then, otherwise } => {
+                    writeln!(f, "{pad}if {cond} {{")?;
+                    Self::fmt_block(then, f, indent + 1)?;
+                    writeln!(f, "{pad}}} else {{")?;
+                    Self::fmt_block(otherwise, f, indent + 1)?;
+                    writeln!(f, "{pad}}}")?;
+                },
+                Self::Loop { counter: Some(c), body, .. } => {
+                    let var = reg_name(c.var);
+                    let init = c.init.as_ref().map(|i| i.to_string()).unwrap_or(var.to_string());
+                    let range = if c.inclusive { "..=" } else { ".." };
+                    let step = if c.step == 1 { String::new() } else { format!(" step {}", c.step) };
+                    writeln!(f, "{pad}for {var} in {init}{range}{}{step} {{", c.bound)?;
+                    Self::fmt_block(body, f, indent + 1)?;
+                    writeln!(f, "{pad}}}")?;
+                },
+                Self::Loop { counter: None, cond: Cond::Always, body, .. } => {
+                    writeln!(f, "{pad}loop {{")?;
+                    Self::fmt_block(body, f, indent + 1)?;
+                    writeln!(f, "{pad}}}")?;
+                },
+                Self::Loop { counter: None, cond, body, .. } => {
+                    writeln!(f, "{pad}do {{")?;
+                    Self::fmt_block(body, f, indent + 1)?;
+                    writeln!(f, "{pad}}} while {cond}")?;
+                },
+                Self::Break => writeln!(f, "{pad}break")?,
+                Self::Halt => writeln!(f, "{pad}halt")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn constant(operand: &Operand) -> Option<RegVal> {
+    match operand {
+        Operand::Val(val) => Some(*val),
+        Operand::Reg(_) => None,
+    }
+}
+
+struct Lifter<'a> {
+    instructions: &'a [Instruction],
+}
+
+impl Lifter<'_> {
+    fn jump(&self, ip: usize) -> Option<(&Operand, isize)> {
+        match &self.instructions[ip] {
+            Instruction::Jnz(cond, Operand::Val(offset)) if constant(cond) != Some(0) => Some((cond, ip as isize + offset)),
+            _ => None,
+        }
+    }
+
+    // Lifts `start..end`, where `loop_exit` is where a `break` would continue.
+    fn lift(&self, start: usize, end: usize, loop_exit: Option<usize>) -> Result<Vec<Stmt>, LiftError> {
+        let mut stmts = Vec::new();
+        let mut ip = start;
+        while ip < end {
+            if let Some(latch) = (ip..end).rev().find(|&j| self.jump(j).is_some_and(|(_, target)| target == ip as isize)) {
+                let body = self.lift(ip, latch, Some(latch + 1))?;
+                let cond = match self.jump(latch) {
+                    Some((Operand::Reg(reg), _)) => Cond::NonZero(Operand::Reg(*reg)),
+                    _ => Cond::Always,
+                };
+                let mut stmt = Stmt::new_loop(ip, body, cond);
+                if let Stmt::Loop { counter: Some(counter), .. } = &mut stmt {
+                    if let Some(Stmt::Op(Instruction::Set(reg, init))) = stmts.last() {
+                        if *reg == counter.var {
+                            counter.init = Some(init.clone());
+                            stmts.pop();
+                        }
+                    }
+                }
+                stmts.push(stmt);
+                ip = latch + 1;
+                continue;
+            }
+            match &self.instructions[ip] {
+                Instruction::Jnz(_, Operand::Reg(_)) => return Err(LiftError::RegisterJump(ip)),
+                Instruction::Jnz(cond, Operand::Val(offset)) => {
+                    let target = ip as isize + offset;
+                    let leaves = target < 0 || target as usize >= self.instructions.len();
+                    match constant(cond) {
+                        Some(0) => (),
+                        Some(_) if loop_exit.is_some_and(|exit| exit as isize == target) => stmts.push(Stmt::Break),
+                        Some(_) if leaves => stmts.push(Stmt::Halt),
+                        Some(_) => return Err(LiftError::UnstructuredJump(ip)),
+                        None if loop_exit.is_some_and(|exit| exit as isize == target) => stmts.push(Stmt::If { cond: Cond::NonZero(cond.clone()), then: vec![Stmt::Break] }),
+                        None if leaves => stmts.push(Stmt::If { cond: Cond::NonZero(cond.clone()), then: vec![Stmt::Halt] }),
+                        None if *offset <= 0 || target as usize > end => return Err(LiftError::UnstructuredJump(ip)),
+                        None => {
+                            let target = target as usize;
+                            // An unconditional jump at the end of the skipped block
+                            // that leads further down introduces an else block.
+                            let else_end = match self.jump(target - 1) {
+                                Some((Operand::Val(_), after)) if target - 1 > ip && after > target as isize && after as usize <= end && loop_exit.is_none_or(|exit| exit as isize != after) => Some(after as usize),
+                                _ => None,
+                            };
+                            match else_end {
+                                Some(after) => {
+                                    let then = self.lift(ip + 1, target - 1, loop_exit)?;
+                                    let otherwise = self.lift(target, after, loop_exit)?;
+                                    if then.is_empty() {
+                                        stmts.push(Stmt::If { cond: Cond::NonZero(cond.clone()), then: otherwise });
+                                    } else {
+                                        stmts.push(Stmt::IfElse { cond: Cond::Zero(cond.clone()), then, otherwise });
+                                    }
+                                    ip = after;
+                                },
+                                None => {
+                                    stmts.push(Stmt::If { cond: Cond::Zero(cond.clone()), then: self.lift(ip + 1, target, loop_exit)? });
+                                    ip = target;
+                                },
+                            }
+                            continue;
+                        },
+                    }
+                },
+                op => stmts.push(Stmt::Op(op.clone())),
+            }
+            ip += 1;
+        }
+        Ok(stmts)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompositeCount {
+    pub counter: RegIdx,
+    pub initial: RegVal,
+    pub var: RegIdx,
+    pub start: RegVal,
+    pub end: RegVal,
+    pub step: RegVal,
+}
+
+impl CompositeCount {
+    fn is_prime(n: RegVal) -> bool {
+        n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| n % d != 0)
+    }
+
+    /// The value the counter register holds once the program has finished.
+    pub fn result(&self) -> RegVal {
+        self.initial + (self.start..=self.end).step_by(self.step as usize).filter(|&n| !Self::is_prime(n)).count() as RegVal
+    }
+}
+
+impl Display for CompositeCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} counts the composite numbers among {} = {}, {}, ..., {} (step {})",
+               reg_name(self.counter), reg_name(self.var), self.start, self.start + self.step, self.end, self.step)
+    }
+}
+
+// Matches
+//   for b in ..=c step k {
+//       f = 1
+//       for d in 2..b {
+//           for e in 2..b {
+//               g = d; g *= e; g -= b
+//               if g == 0 { f = 0 }
+//           }
+//       }
+//       if f == 0 { h += 1 }
+//   }
+// for any choice of registers, and returns (loop start, h, b, c, k).
+fn match_composites(stmt: &Stmt) -> Option<(usize, RegIdx, RegIdx, Operand, RegVal)> {
+    let Stmt::Loop { start, counter: Some(outer), body, .. } = stmt else { return None };
+    if !outer.inclusive || outer.step <= 0 || outer.init.is_some() {
+        return None;
+    }
+    let b = outer.var;
+    let [Stmt::Op(Instruction::Set(flag, Operand::Val(1))), d_loop, Stmt::If { cond: Cond::Zero(Operand::Reg(flag1)), then }] = &body[..] else { return None };
+    let [Stmt::Op(Instruction::Sub(h, Operand::Val(-1)))] = then[..] else { return None };
+    let divisor_loop = |stmt: &Stmt| match stmt {
+        Stmt::Loop { counter: Some(Counter { var, init: Some(Operand::Val(2)), step: 1, bound: Operand::Reg(bound), inclusive: false }), body, .. } if *bound == b => Some((*var, body.clone())),
+        _ => None,
+    };
+    let (d, d_body) = divisor_loop(d_loop)?;
+    let [e_loop] = &d_body[..] else { return None };
+    let (e, e_body) = divisor_loop(e_loop)?;
+    let [Stmt::Op(Instruction::Set(g, Operand::Reg(x))), Stmt::Op(Instruction::Mul(g1, Operand::Reg(y))), Stmt::Op(Instruction::Sub(g2, Operand::Reg(b1))), Stmt::If { cond: Cond::Zero(Operand::Reg(g3)), then }] = &e_body[..] else { return None };
+    let [Stmt::Op(Instruction::Set(flag2, Operand::Val(0)))] = then[..] else { return None };
+    let regs_match = flag == flag1 && *flag == flag2 && g == g1 && g == g2 && g == g3 && *b1 == b
+        && ((*x == d && *y == e) || (*x == e && *y == d))
+        && ![b, d, e, *flag, *g].contains(&h);
+    regs_match.then(|| (*start, h, b, outer.bound.clone(), outer.step))
+}
+
+/// The structured form of a program, and what it computes if we can tell.
+pub struct Analysis {
+    code: Vec<Stmt>,
+    summary: Option<CompositeCount>,
+}
+
+impl Analysis {
+    /// Lifts the program and tries to summarise what it computes when run
+    /// with register `a` set to `a`.
+    pub(crate) fn new(instructions: &[Instruction], a: RegVal) -> Result<Self, LiftError> {
+        let code = Lifter { instructions }.lift(0, instructions.len(), None)?;
+        let summary = code.iter().find_map(match_composites).and_then(|(start, counter, var, bound, step)| {
+            // The constants are computed by the straight-line code before the
+            // loop, so just run that part.
+            let mut cpu = Cpu {
+                instructions: instructions.to_vec(),
+                ..Default::default()
+            };
+            cpu.registers[0] = a;
+            cpu.run_until(start, MAX_SETUP_STEPS)?;
+            let start = cpu.registers[var];
+            let end = match bound {
+                Operand::Reg(reg) => cpu.registers[reg],
+                Operand::Val(val) => val,
+            };
+            let initial = cpu.registers[counter];
+            (start <= end && (end - start) % step == 0).then_some(CompositeCount { counter, initial, var, start, end, step })
+        });
+        Ok(Self { code, summary })
+    }
+
+    pub fn summary(&self) -> Option<&CompositeCount> {
+        self.summary.as_ref()
+    }
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Stmt::fmt_block(&self.code, f, 0)?;
+        match &self.summary {
+            Some(summary) => write!(f, "// {summary}"),
+            None => write!(f, "// no known pattern recognised"),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::str::FromStr;
-// use std::thread;
-// use std::sync::mpsc::{channel, Receiver, Sender};
+
+mod decompile;
+pub use decompile::{Analysis, CompositeCount, LiftError};
 
 type RegIdx = usize;
 type RegVal = isize;
@@ -8,7 +9,7 @@ type RegVal = isize;
 #[derive(Debug)]
 struct ParseError;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Operand {
     Reg(RegIdx),
     Val(RegVal),
@@ -102,17 +103,17 @@ struct Cpu {
 }
 
 impl Cpu {
-    /// This only works for this particular AoC challenge input. The only allowed modifications are the numbers range set in instructions (0..=7)!
-    fn run_optimized(&mut self) {
-        while self.next_instr_ptr <= 8 {
-            let next_instruction = &self.instructions[self.next_instr_ptr];
+    // Runs until the instruction at `ip` is next. Returns `None` if the
+    // program halts before that, or takes more than `max_steps` steps.
+    fn run_until(&mut self, ip: usize, max_steps: usize) -> Option<()> {
+        for _ in 0..max_steps {
+            if self.next_instr_ptr == ip {
+                return Some(());
+            }
+            let next_instruction = self.instructions.get(self.next_instr_ptr)?;
             next_instruction.perform(&mut self.registers, &mut self.out, &mut self.next_instr_ptr);
         }
-        self.registers[7] = (self.registers[1]..=self.registers[2]).step_by(17).filter(|i| !Self::is_prime(*i)).count() as isize;
-    }
-
-    fn is_prime(n: isize) -> bool {
-        !(2..n).any(|a| n % a == 0)
+        (self.next_instr_ptr == ip).then_some(())
     }
 
     fn run(&mut self) -> RegVal {
@@ -155,19 +156,22 @@ impl Cpu {
     }
 }
 
-pub fn run(input: &str) -> (isize, isize) {
+/// Lifts the program into structured code and, if it has the usual shape,
+/// summarises what it computes when started with register `a` set to `a`.
+pub fn analyse(input: &str, a: RegVal) -> Result<Analysis, LiftError> {
+    let instructions: Vec<_> = input.lines().map(Instruction::from).collect();
+    Analysis::new(&instructions, a)
+}
+
+pub fn run(input: &str) -> Result<(isize, isize), LiftError> {
     let mut cpu = Cpu {
         instructions: input.lines().map(Instruction::from).collect(),
         ..Default::default()
     };
-    let mut cpu_1 = cpu.clone();
-    cpu_1.registers[0] = 1;
     cpu.run();
     let first = cpu.out;
-    // cpu_1.run();
-    cpu_1.run_optimized();
-    let second = cpu_1.registers[7];
-    (first, second)
+    let second = analyse(input, 1)?.summary().ok_or(LiftError::Unrecognized)?.result();
+    Ok((first, second))
 }
 
 #[cfg(test)]
@@ -182,7 +186,60 @@ mod tests {
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
-        assert_eq!(run(&challenge_input), (9409, 913));
+        assert_eq!(run(&challenge_input), Ok((9409, 913)));
+    }
+
+    #[test]
+    fn test_analyse() {
+        let challenge_input = read_file("tests/challenge_input");
+        let expected = "b = 99
+c = b
+if a != 0 {
+    b *= 100
+    b += 100000
+    c = b
+    c += 17000
+}
+for b in b..=c step 17 {
+    f = 1
+    for d in 2..b {
+        for e in 2..b {
+            g = d
+            g *= e
+            g -= b
+            if g == 0 {
+                f = 0
+            }
+        }
+    }
+    if f == 0 {
+        h += 1
+    }
+}
+// h counts the composite numbers among b = 109900, 109917, ..., 126900 (step 17)";
+        let analysis = analyse(&challenge_input, 1).unwrap();
+        assert_eq!(analysis.to_string(), expected);
+        assert_eq!(analysis.summary(), Some(&CompositeCount { counter: 7, initial: 0, var: 1, start: 109900, end: 126900, step: 17 }));
+        // In debug mode, only the number 99 is checked.
+        assert_eq!(analyse(&challenge_input, 0).unwrap().summary().map(|s| s.result()), Some(1));
+
+        // The same program with the registers renamed and different constants.
+        let renamed = challenge_input
+            .replace("set b 99", "set b 81")
+            .replace("-17000", "-1000")
+            .replace("-17", "-5")
+            .replace(" h ", " a ");
+        let summary = analyse(&renamed, 1).unwrap().summary().cloned().unwrap();
+        assert_eq!(summary, CompositeCount { counter: 0, initial: 1, var: 1, start: 108100, end: 109100, step: 5 });
+        assert_eq!(summary.result(), 1 + (108100..=109100).step_by(5).filter(|n| (2..*n).any(|d| n % d == 0)).count() as isize);
+
+        assert_eq!(analyse("set a 1\njnz a b", 0).err(), Some(LiftError::RegisterJump(1)));
+        assert_eq!(analyse("jnz 1 2\nset a 1\njnz a -1", 0).err(), Some(LiftError::UnstructuredJump(0)));
+        assert_eq!(run("set a 1\nmul a 2").err(), Some(LiftError::Unrecognized));
+
+        // The constants are never computed if the code before the loop doesn't finish.
+        let stuck = format!("set f 1\nsub f 0\njnz f -1\n{challenge_input}");
+        assert_eq!(analyse(&stuck, 1).unwrap().summary(), None);
     }
 }
 
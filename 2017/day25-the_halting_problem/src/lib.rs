@@ -1,112 +1,345 @@
-use std::collections::HashSet;
-
-struct State {
-    write_0: bool,
-    write_1: bool,
-    move_0: isize,
-    move_1: isize,
-    next_0: usize,
-    next_1: usize,
-}
-
-impl From<&str> for State {
-    fn from(value: &str) -> Self {
-        let lines: Vec<_> = value.lines().collect();
-        let write_0 = match lines[2].split_whitespace().nth(4) {
-            Some("1.") => true,
-            Some("0.") => false,
-            v => panic!("Illegal value to write on 0: {v:?}"),
-        };
-        let move_0 = match lines[3].split_whitespace().nth(6) {
-            Some("right.") => 1,
-            Some("left.") => -1,
-            v => panic!("Illegal direction to move on 0: {v:?}"),
-        };
-        let next_0 = match lines[4].split_whitespace().nth(4).unwrap().bytes().next() {
-            Some(v) if (b'A'..=b'Z').contains(&v) => (v - b'A') as usize,
-            e => panic!("Illegal state to continue on 0: {e:?}"),
-        };
-        let write_1 = match lines[6].split_whitespace().nth(4) {
-            Some("1.") => true,
-            Some("0.") => false,
-            v => panic!("Illegal value to write on 1: {v:?}"),
-        };
-        let move_1 = match lines[7].split_whitespace().nth(6) {
-            Some("right.") => 1,
-            Some("left.") => -1,
-            v => panic!("Illegal direction to write on 1: {v:?}"),
-        };
-        let next_1 = match lines[8].split_whitespace().nth(4).unwrap().bytes().next() {
-            Some(v) if (b'A'..=b'Z').contains(&v) => (v - b'A') as usize,
-            e => panic!("Illegal state to continue on 1: {e:?}"),
-        };
+use core::fmt::Display;
+use std::collections::HashMap;
+use std::num::ParseIntError;
 
+// How many cells a macro step covers by default, and for how long we follow
+// a machine inside a single block before assuming it never leaves.
+const DEFAULT_BLOCK_LEN: usize = 64;
+const MACRO_STEP_LIMIT: usize = 1 << 16;
+// Blocks seen on real tapes repeat a lot, but a machine that keeps writing new
+// patterns would otherwise grow the macro-step cache without bound, so it
+// starts over once it holds this many entries.
+const MAX_CACHE_ENTRIES: usize = 1 << 20;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError<'a> {
+    DuplicateRule(&'a str, &'a str),
+    DuplicateState(&'a str),
+    LineMalformed(&'a str),
+    MissingRule(&'a str, &'a str),
+    ParseIntError(ParseIntError),
+    TooManySymbols(usize),
+    UnexpectedEof,
+    UnknownState(&'a str),
+}
+
+impl From<ParseIntError> for ParseError<'_> {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl Display for ParseError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DuplicateRule(state, symbol) => write!(f, "State {state} defines more than one rule for value {symbol}"),
+            Self::DuplicateState(state) => write!(f, "State {state} is defined more than once"),
+            Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
+            Self::MissingRule(state, symbol) => write!(f, "State {state} doesn't define what to do on value {symbol}"),
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::TooManySymbols(n) => write!(f, "At most 256 different values are supported, but the blueprint uses {n}"),
+            Self::UnexpectedEof => write!(f, "Blueprint ended in the middle of a rule"),
+            Self::UnknownState(state) => write!(f, "Blueprint refers to state {state}, which isn't defined"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Rule {
+    write: u8,
+    right: bool,
+    next: usize,
+}
+
+fn field<'a>(line: Option<&'a str>, prefix: &str, suffix: char) -> Result<&'a str, ParseError<'a>> {
+    let line = line.ok_or(ParseError::UnexpectedEof)?;
+    line.strip_prefix(prefix)
+        .and_then(|rest| rest.strip_suffix(suffix))
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .ok_or(ParseError::LineMalformed(line))
+}
+
+pub struct Blueprint<'a> {
+    states: Vec<&'a str>,
+    // The blank symbol, which fills the initial tape, always comes first.
+    symbols: Vec<&'a str>,
+    rules: Vec<Vec<Rule>>,
+    start: usize,
+    steps: usize,
+}
+
+impl<'a> TryFrom<&'a str> for Blueprint<'a> {
+    type Error = ParseError<'a>;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        let mut lines = value.lines().map(str::trim).filter(|l| !l.is_empty()).peekable();
+        let start = field(lines.next(), "Begin in state ", '.')?;
+        let steps_line = lines.next();
+        let steps = field(steps_line, "Perform a diagnostic checksum after ", '.')?
+            .strip_suffix(" steps")
+            .ok_or(ParseError::LineMalformed(steps_line.unwrap_or_default()))?
+            .trim()
+            .parse::<usize>()?;
+
+        let mut states = Vec::new();
+        let mut raw_rules = Vec::new();
+        let mut symbols = Vec::new();
+        while lines.peek().is_some() {
+            let state = field(lines.next(), "In state ", ':')?;
+            if states.contains(&state) {
+                return Err(ParseError::DuplicateState(state));
+            }
+            states.push(state);
+            let mut state_rules = Vec::new();
+            while let Some(condition) = lines.next_if(|l| l.starts_with("If the current value is ")) {
+                let symbol = field(Some(condition), "If the current value is ", ':')?;
+                let write = field(lines.next(), "- Write the value ", '.')?;
+                let direction_line = lines.next();
+                let right = match field(direction_line, "- Move one slot to the ", '.')? {
+                    "right" => true,
+                    "left" => false,
+                    _ => return Err(ParseError::LineMalformed(direction_line.unwrap_or_default())),
+                };
+                let next = field(lines.next(), "- Continue with state ", '.')?;
+                if state_rules.iter().any(|&(s, _, _, _)| s == symbol) {
+                    return Err(ParseError::DuplicateRule(state, symbol));
+                }
+                for s in [symbol, write] {
+                    if !symbols.contains(&s) {
+                        symbols.push(s);
+                    }
+                }
+                state_rules.push((symbol, write, right, next));
+            }
+            raw_rules.push(state_rules);
+        }
+
+        if let Some(blank) = symbols.iter().position(|&s| s == "0") {
+            symbols[..=blank].rotate_right(1);
+        }
+        if symbols.len() > 256 {
+            return Err(ParseError::TooManySymbols(symbols.len()));
+        }
+        let state_idx = |name| states.iter().position(|&s| s == name).ok_or(ParseError::UnknownState(name));
+        let symbol_idx = |name| symbols.iter().position(|&s| s == name).unwrap() as u8;
+        let rules = states.iter().zip(raw_rules.iter()).map(|(&state, state_rules)| {
+            symbols.iter().map(|&symbol| {
+                let &(_, write, right, next) = state_rules
+                    .iter()
+                    .find(|&&(s, _, _, _)| s == symbol)
+                    .ok_or(ParseError::MissingRule(state, symbol))?;
+                Ok::<_, ParseError>(Rule {
+                    write: symbol_idx(write),
+                    right,
+                    next: state_idx(next)?,
+                })
+            }).collect::<Result<Vec<_>, _>>()
+        }).collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            start: state_idx(start)?,
+            states,
+            symbols,
+            rules,
+            steps,
+        })
+    }
+}
+
+impl Blueprint<'_> {
+    pub fn states(&self) -> &[&str] {
+        &self.states
+    }
+
+    pub fn symbols(&self) -> &[&str] {
+        &self.symbols
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Transition {
+    block: u64,
+    state: usize,
+    right: bool,
+    steps: usize,
+}
+
+/// A dense tape that grows in both directions. Cells are packed into `u64`
+/// blocks of `block_len` cells of `width` bits each, and the blank symbol is
+/// always 0, so fresh blocks are simply 0.
+struct Tape {
+    blocks: Vec<u64>,
+    block: usize,
+    pos: usize,
+    width: usize,
+    block_len: usize,
+}
+
+impl Tape {
+    fn new(symbol_count: usize, block_len: usize) -> Self {
+        let width = (usize::BITS - symbol_count.saturating_sub(1).leading_zeros()).max(1) as usize;
         Self {
-            write_0,
-            write_1,
-            move_0,
-            move_1,
-            next_0,
-            next_1,
+            blocks: vec![0],
+            block: 0,
+            pos: 0,
+            width,
+            block_len: block_len.clamp(1, 64 / width),
+        }
+    }
+
+    fn mask(&self) -> u64 {
+        (1 << self.width) - 1
+    }
+
+    fn read(&self, block: u64, pos: usize) -> u8 {
+        ((block >> (pos * self.width)) & self.mask()) as u8
+    }
+
+    fn write(&self, block: u64, pos: usize, symbol: u8) -> u64 {
+        let shift = pos * self.width;
+        block & !(self.mask() << shift) | (symbol as u64) << shift
+    }
+
+    fn move_block(&mut self, right: bool) {
+        if right {
+            self.block += 1;
+            if self.block == self.blocks.len() {
+                self.blocks.resize(2 * self.blocks.len(), 0);
+            }
+        } else if self.block == 0 {
+            let grow = self.blocks.len();
+            self.blocks.splice(0..0, std::iter::repeat_n(0, grow));
+            self.block = grow - 1;
+        } else {
+            self.block -= 1;
+        }
+    }
+
+    fn move_cursor(&mut self, right: bool) {
+        match (right, self.pos) {
+            (true, p) if p + 1 == self.block_len => {
+                self.move_block(true);
+                self.pos = 0;
+            },
+            (true, _) => self.pos += 1,
+            (false, 0) => {
+                self.move_block(false);
+                self.pos = self.block_len - 1;
+            },
+            (false, _) => self.pos -= 1,
         }
     }
+
+    fn count_non_blank(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|&block| (0..self.block_len).filter(|&pos| self.read(block, pos) != 0).count())
+            .sum()
+    }
 }
 
-#[derive(Default)]
-struct Cpu {
-    tape: HashSet<isize>,
-    cursor: isize,
-    state_machine: Vec<State>,
-    state_ptr: usize,
+pub struct Machine {
+    rules: Vec<Vec<Rule>>,
+    tape: Tape,
+    state: usize,
+    steps: usize,
+    cache: HashMap<(usize, u64, usize), Option<Transition>>,
+}
+
+impl From<&Blueprint<'_>> for Machine {
+    fn from(value: &Blueprint<'_>) -> Self {
+        Self::with_block_len(value, DEFAULT_BLOCK_LEN)
+    }
 }
 
-impl Cpu {
-    fn run_for(&mut self, steps: usize) {
-        for _ in 0..steps {
-            self.step();
+impl Machine {
+    /// Macro steps will cover `block_len` cells at a time (capped to what
+    /// fits into 64 bits for the blueprint's alphabet).
+    pub fn with_block_len(blueprint: &Blueprint<'_>, block_len: usize) -> Self {
+        Self {
+            rules: blueprint.rules.clone(),
+            tape: Tape::new(blueprint.symbols.len(), block_len),
+            state: blueprint.start,
+            steps: 0,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub fn step(&mut self) {
+        let block = self.tape.blocks[self.tape.block];
+        let rule = self.rules[self.state][self.tape.read(block, self.tape.pos) as usize];
+        self.tape.blocks[self.tape.block] = self.tape.write(block, self.tape.pos, rule.write);
+        self.tape.move_cursor(rule.right);
+        self.state = rule.next;
+        self.steps += 1;
+    }
+
+    // Follows the machine from `pos` in `block` until it leaves the block.
+    fn transition(&self, mut state: usize, mut block: u64, mut pos: usize) -> Option<Transition> {
+        for steps in 1..=MACRO_STEP_LIMIT {
+            let rule = self.rules[state][self.tape.read(block, pos) as usize];
+            block = self.tape.write(block, pos, rule.write);
+            state = rule.next;
+            match (rule.right, pos) {
+                (true, p) if p + 1 == self.tape.block_len => return Some(Transition { block, state, right: true, steps }),
+                (false, 0) => return Some(Transition { block, state, right: false, steps }),
+                (true, _) => pos += 1,
+                (false, _) => pos -= 1,
+            }
         }
-    } 
-
-    fn step(&mut self) {
-        let current_state = &self.state_machine[self.state_ptr];
-        match self.tape.contains(&self.cursor) {
-            true => {
-                    if !current_state.write_1 {
-                        self.tape.remove(&self.cursor);
+        None
+    }
+
+    /// Runs for exactly `steps` steps, skipping over whole blocks at once
+    /// wherever the remaining step budget allows.
+    pub fn run_for(&mut self, steps: usize) {
+        let target = self.steps + steps;
+        while self.steps < target {
+            let key = (self.state, self.tape.blocks[self.tape.block], self.tape.pos);
+            let transition = match self.cache.get(&key) {
+                Some(&t) => t,
+                None => {
+                    let t = self.transition(key.0, key.1, key.2);
+                    if self.cache.len() >= MAX_CACHE_ENTRIES {
+                        self.cache.clear();
                     }
-                    self.cursor += current_state.move_1;
-                    self.state_ptr = current_state.next_1;
+                    self.cache.insert(key, t);
+                    t
                 },
-            false => {
-                    if current_state.write_0 {
-                        self.tape.insert(self.cursor);
-                    }
-                    self.cursor += current_state.move_0;
-                    self.state_ptr = current_state.next_0;
+            };
+            match transition {
+                Some(t) if self.steps + t.steps <= target => {
+                    self.tape.blocks[self.tape.block] = t.block;
+                    self.tape.move_block(t.right);
+                    self.tape.pos = if t.right { 0 } else { self.tape.block_len - 1 };
+                    self.state = t.state;
+                    self.steps += t.steps;
                 },
+                _ => self.step(),
+            }
         }
     }
 
-    fn checksum(&self) -> usize {
-        self.tape.len()
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// The number of cells that don't hold the blank symbol.
+    pub fn checksum(&self) -> usize {
+        self.tape.count_non_blank()
     }
 }
 
-pub fn run(input: &str) -> usize {
-    let states: Vec<_> = input.split("\n\n").collect();
-    let initial = states[0].split_once('\n').unwrap();
-    let initial_state_str = initial.0.split_whitespace().nth(3).unwrap();
-    let initial_state = (initial_state_str.bytes().next().unwrap() - b'A') as usize;
-    let step_count = initial.1.split_whitespace().nth(5).unwrap().parse::<usize>().unwrap();
-    
-    let state_machine: Vec<_> = states[1..].iter().map(|s| State::from(*s)).collect();
-    let mut cpu = Cpu {
-        state_machine,
-        state_ptr: initial_state,
-        ..Default::default()
-    };
-    cpu.run_for(step_count);
-    cpu.checksum()
+pub fn run(input: &str) -> Result<usize, ParseError<'_>> {
+    let blueprint = Blueprint::try_from(input)?;
+    let mut machine = Machine::from(&blueprint);
+    machine.run_for(blueprint.steps());
+    Ok(machine.checksum())
 }
 
 #[cfg(test)]
@@ -121,12 +354,80 @@ mod tests {
     #[test]
     fn test_sample() {
         let sample_input = read_file("tests/sample_input");
-        assert_eq!(run(&sample_input), 3);
+        assert_eq!(run(&sample_input), Ok(3));
     }
 
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
-        assert_eq!(run(&challenge_input), 4769);
+        assert_eq!(run(&challenge_input), Ok(4769));
+    }
+
+    #[test]
+    fn test_macro_steps() {
+        let challenge_input = read_file("tests/challenge_input");
+        let blueprint = Blueprint::try_from(&challenge_input[..]).unwrap();
+        for block_len in [1, 3, 8, 16, 64] {
+            let mut single = Machine::with_block_len(&blueprint, block_len);
+            let mut chunked = Machine::with_block_len(&blueprint, block_len);
+            for steps in [1, 10, 1000, 54321] {
+                (0..steps).for_each(|_| single.step());
+                chunked.run_for(steps);
+                assert_eq!(single.steps(), chunked.steps());
+                assert_eq!(single.checksum(), chunked.checksum());
+            }
+        }
+    }
+
+    #[test]
+    fn test_symbols() {
+        let input = "Begin in state start.
+Perform a diagnostic checksum after 4 steps.
+
+In state start:
+  If the current value is x:
+    - Write the value 0.
+    - Move one slot to the left.
+    - Continue with state start.
+  If the current value is 0:
+    - Write the value y.
+    - Move one slot to the right.
+    - Continue with state other.
+  If the current value is y:
+    - Write the value x.
+    - Move one slot to the right.
+    - Continue with state start.
+
+In state other:
+  If the current value is 0:
+    - Write the value x.
+    - Move one slot to the left.
+    - Continue with state start.
+  If the current value is x:
+    - Write the value x.
+    - Move one slot to the left.
+    - Continue with state start.
+  If the current value is y:
+    - Write the value y.
+    - Move one slot to the left.
+    - Continue with state start.";
+        let blueprint = Blueprint::try_from(input).unwrap();
+        assert_eq!(blueprint.symbols(), ["0", "x", "y"]);
+        assert_eq!(blueprint.states(), ["start", "other"]);
+        // 00 -> y0 -> yx -> xx -> x0
+        assert_eq!(run(input), Ok(1));
+    }
+
+    #[test]
+    fn test_errors() {
+        let sample_input = read_file("tests/sample_input");
+        assert_eq!(run(&sample_input.replace("state B.", "state D.")).err(), Some(ParseError::UnknownState("D")));
+        assert_eq!(run(&sample_input.replace("one slot to the left", "one slot up")).err(), Some(ParseError::LineMalformed("- Move one slot up.")));
+        assert_eq!(run(&sample_input.replace("In state B", "In state A")).err(), Some(ParseError::DuplicateState("A")));
+        assert_eq!(run(&sample_input.replace("current value is 1", "current value is 0")).err(), Some(ParseError::DuplicateRule("A", "0")));
+        let truncated = sample_input.lines().take(sample_input.lines().count() - 1).collect::<Vec<_>>().join("\n");
+        assert_eq!(run(&truncated).err(), Some(ParseError::UnexpectedEof));
+        let missing = sample_input.replace("Write the value 0.", "Write the value 2.");
+        assert_eq!(run(&missing).err(), Some(ParseError::MissingRule("A", "2")));
     }
 }
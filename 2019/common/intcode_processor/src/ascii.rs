@@ -0,0 +1,323 @@
+//! A line-based front-end for intcode programs that talk to us, like the
+//! ASCII-capable droids, or the arcade cabinet and repair droid when fed
+//! numbers.
+//!
+//! A `Terminal` can be played live, driven by commands and macros, and keeps a
+//! `Transcript` of everything that was said. Transcripts can be written to a
+//! file and later replayed headlessly with `replay()` to check that the
+//! program still answers the same way.
+//!
+//! ## Transcript format
+//! Each line starts with a marker:
+//! - `> ` a line of input,
+//! - `| ` a line of output (including its newline),
+//! - `- ` output without a trailing newline,
+//! - `= ` a value that is not printable (like the final answer of most ASCII programs).
+
+use crate::intcode_processor::{Cpu, OutputState};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+type RegVal = isize;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionError {
+    Halted(usize),
+    InvalidNumber(String),
+    Malformed(String),
+    Mismatch { input: usize, expected: String, actual: String },
+}
+
+impl Display for SessionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Halted(idx) => write!(f, "Program halted before reading input #{idx}"),
+            Self::InvalidNumber(v) => write!(f, "Unable to parse input into numbers: {v}"),
+            Self::Malformed(v) => write!(f, "Transcript line is malformed: {v}"),
+            Self::Mismatch { input, expected, actual } => write!(f, "Output before input #{input} differs.\nExpected:\n{expected}\nActual:\n{actual}"),
+        }
+    }
+}
+
+/// How lines of input are fed to the program, and how its output is shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Input lines are sent character by character, followed by a newline.
+    /// Output below 128 is printed as text, anything else as a value.
+    Ascii,
+    /// Input lines hold comma or space separated numbers, and all output is
+    /// shown as values.
+    Numeric,
+}
+
+/// Why the terminal stopped reading output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    AwaitingInput,
+    Halted,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Input(String),
+    Output(String),
+    Value(RegVal),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub events: Vec<Event>,
+}
+
+impl Transcript {
+    fn push_output(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.events.last_mut() {
+            Some(Event::Output(previous)) => previous.push_str(text),
+            _ => self.events.push(Event::Output(text.to_string())),
+        }
+    }
+
+    /// The inputs, in order.
+    pub fn inputs(&self) -> impl Iterator<Item = &str> {
+        self.events.iter().filter_map(|e| match e {
+            Event::Input(line) => Some(line.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+}
+
+impl Display for Transcript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for event in &self.events {
+            match event {
+                Event::Input(line) => writeln!(f, "> {line}")?,
+                Event::Value(v) => writeln!(f, "= {v}")?,
+                Event::Output(text) => for line in text.split_inclusive('\n') {
+                    match line.strip_suffix('\n') {
+                        Some(line) => writeln!(f, "| {line}")?,
+                        None => writeln!(f, "- {line}")?,
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Transcript {
+    type Err = SessionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut transcript = Self::default();
+        for line in s.lines() {
+            // Allow for editors stripping trailing spaces from empty lines.
+            let (marker, content) = line.split_at_checked(1).ok_or(SessionError::Malformed(line.to_string()))?;
+            let content = content.strip_prefix(' ').unwrap_or(content);
+            match marker {
+                ">" => transcript.events.push(Event::Input(content.to_string())),
+                "|" => transcript.push_output(&format!("{content}\n")),
+                "-" => transcript.push_output(content),
+                "=" => transcript.events.push(Event::Value(content.parse().map_err(|_| SessionError::Malformed(line.to_string()))?)),
+                _ => return Err(SessionError::Malformed(line.to_string())),
+            }
+        }
+        Ok(transcript)
+    }
+}
+
+pub struct Terminal {
+    cpu: Cpu,
+    encoding: Encoding,
+    macros: HashMap<String, Vec<String>>,
+    transcript: Transcript,
+}
+
+impl Terminal {
+    pub fn new(cpu: Cpu, encoding: Encoding) -> Self {
+        Self {
+            cpu,
+            encoding,
+            macros: HashMap::new(),
+            transcript: Transcript::default(),
+        }
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// For patching the program before it runs, like inserting coins.
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn transcript(&self) -> &Transcript {
+        &self.transcript
+    }
+
+    /// Makes `name` stand for all of `commands` in `command()` and `interact()`.
+    pub fn define_macro(&mut self, name: &str, commands: &[&str]) {
+        self.macros.insert(name.to_string(), commands.iter().map(|c| c.to_string()).collect());
+    }
+
+    /// Runs the program until it wants input or halts. Returns the text it
+    /// printed and any other values it output by then.
+    pub fn read(&mut self) -> (String, Vec<RegVal>, Stop) {
+        let mut text = String::new();
+        let mut pending = String::new();
+        let mut values = Vec::new();
+        let stop = loop {
+            let (value, last) = match self.cpu.run() {
+                OutputState::Output(v) => (v, false),
+                OutputState::DiagnosticCode(v) => (v, true),
+                OutputState::Halt if self.cpu.awaits_input() => break Stop::AwaitingInput,
+                OutputState::Halt => break Stop::Halted,
+            };
+            match (self.encoding, u8::try_from(value)) {
+                (Encoding::Ascii, Ok(c)) if c < 128 => {
+                    text.push(c as char);
+                    pending.push(c as char);
+                },
+                _ => {
+                    self.transcript.push_output(&pending);
+                    pending.clear();
+                    self.transcript.events.push(Event::Value(value));
+                    values.push(value);
+                },
+            }
+            if last {
+                break Stop::Halted;
+            }
+        };
+        self.transcript.push_output(&pending);
+        (text, values, stop)
+    }
+
+    /// Sends one line of input, without expanding macros.
+    pub fn send(&mut self, line: &str) -> Result<(), SessionError> {
+        match self.encoding {
+            Encoding::Ascii => {
+                line.bytes().for_each(|b| self.cpu.set_input(b as RegVal));
+                self.cpu.set_input(b'\n' as RegVal);
+            },
+            Encoding::Numeric => {
+                let values = line
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|v| !v.is_empty())
+                    .map(|v| v.parse::<RegVal>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| SessionError::InvalidNumber(line.to_string()))?;
+                values.into_iter().for_each(|v| self.cpu.set_input(v));
+            },
+        }
+        self.transcript.events.push(Event::Input(line.to_string()));
+        Ok(())
+    }
+
+    /// Sends `line` (or every line of the macro it names) and reads the
+    /// answer to each of them. Stops early if the program halts.
+    pub fn command(&mut self, line: &str) -> Result<(String, Vec<RegVal>, Stop), SessionError> {
+        let lines = self.macros.get(line).cloned().unwrap_or_else(|| vec![line.to_string()]);
+        let mut text = String::new();
+        let mut values = Vec::new();
+        for line in lines {
+            self.send(&line)?;
+            let (t, v, stop) = self.read();
+            text.push_str(&t);
+            values.extend(v);
+            if stop == Stop::Halted {
+                return Ok((text, values, stop));
+            }
+        }
+        Ok((text, values, Stop::AwaitingInput))
+    }
+
+    /// Plays the program live. Besides commands for the program, `input` may
+    /// contain
+    /// - `!macro <name> <command>;<command>;...` to define a macro, and
+    /// - `!save <path>` to write the transcript so far to a file.
+    ///
+    /// Returns once the program halted or `input` ran dry.
+    pub fn interact(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<Stop> {
+        let (text, values, mut stop) = self.read();
+        write!(output, "{text}")?;
+        values.iter().try_for_each(|v| writeln!(output, "{v}"))?;
+        let mut lines = input.lines();
+        while stop == Stop::AwaitingInput {
+            output.flush()?;
+            let Some(line) = lines.next() else {
+                break;
+            };
+            let line = line?;
+            if let Some(definition) = line.strip_prefix("!macro ") {
+                let (name, commands) = definition.split_once(' ').unwrap_or((definition, ""));
+                let commands: Vec<_> = commands.split(';').map(str::trim).filter(|c| !c.is_empty()).collect();
+                self.define_macro(name, &commands);
+                continue;
+            }
+            if let Some(path) = line.strip_prefix("!save ") {
+                match self.transcript.save(path.trim()) {
+                    Ok(()) => writeln!(output, "Transcript saved to {}", path.trim())?,
+                    Err(e) => writeln!(output, "Unable to save transcript: {e}")?,
+                }
+                continue;
+            }
+            match self.command(&line) {
+                Ok((text, values, s)) => {
+                    write!(output, "{text}")?;
+                    values.iter().try_for_each(|v| writeln!(output, "{v}"))?;
+                    stop = s;
+                },
+                Err(e) => writeln!(output, "{e}")?,
+            }
+        }
+        Ok(stop)
+    }
+}
+
+/// Feeds the inputs of `transcript` to a fresh run of `cpu` and checks that it
+/// produces exactly the recorded output in between.
+pub fn replay(cpu: Cpu, encoding: Encoding, transcript: &Transcript) -> Result<Terminal, SessionError> {
+    let mut terminal = Terminal::new(cpu, encoding);
+    let mut expected = Transcript::default();
+    let mut input_idx = 0;
+    for event in &transcript.events {
+        match event {
+            Event::Input(line) => {
+                if terminal.read().2 == Stop::Halted {
+                    return Err(SessionError::Halted(input_idx));
+                }
+                compare(&terminal.transcript, &expected, input_idx)?;
+                terminal.send(line)?;
+                expected.events.push(event.clone());
+                input_idx += 1;
+            },
+            Event::Output(text) => expected.push_output(text),
+            Event::Value(_) => expected.events.push(event.clone()),
+        }
+    }
+    terminal.read();
+    compare(&terminal.transcript, &expected, input_idx)?;
+    Ok(terminal)
+}
+
+fn compare(actual: &Transcript, expected: &Transcript, input: usize) -> Result<(), SessionError> {
+    if actual == expected {
+        return Ok(());
+    }
+    // Only show what happened since the last input.
+    let since_input = |t: &Transcript| {
+        let start = t.events.iter().rposition(|e| matches!(e, Event::Input(_))).map_or(0, |idx| idx + 1);
+        Transcript { events: t.events[start..].to_vec() }.to_string()
+    };
+    Err(SessionError::Mismatch { input, expected: since_input(expected), actual: since_input(actual) })
+}
@@ -1,15 +1,68 @@
+pub mod ascii;
+
 pub mod intcode_processor {
-    use std::{collections::VecDeque, num::ParseIntError};
+    use std::{collections::{HashMap, VecDeque}, num::ParseIntError};
 
     type RegVal = isize;
 
+    // Addresses below this live in one contiguous block (along with their decoded instructions),
+    // anything above in sparsely allocated pages.
+    const DENSE_LIMIT: usize = 1 << 16;
+    const PAGE_SIZE: usize = 1 << 10;
+
+    // What executing one instruction amounts to: `None` to carry on, or the state `run()` stops in.
+    type Handler = fn(&mut Cpu, Decoded) -> Option<OutputState>;
+
+    /// An instruction word split into its opcode and parameter modes, along with the handler that
+    /// executes it. Every word in dense memory is kept decoded like this, so `run()` neither has to
+    /// take it apart again nor look up what to do every time it gets there, but just calls the
+    /// handler. Writes to memory decode the new word right away, so self-modifying programs still
+    /// see what they wrote.
+    #[derive(Clone, Copy)]
+    struct Decoded {
+        opcode: u8,
+        modes: [u8; 3],
+        handler: Handler,
+    }
+
+    impl Decoded {
+        fn new(word: RegVal) -> Self {
+            if !(0..100_000).contains(&word) {
+                // Not a valid instruction (but maybe valid data).
+                return Self { opcode: 0, modes: [0; 3], handler: Cpu::invalid };
+            }
+            let digit = |place: RegVal| ((word / place) % 10) as u8;
+            let opcode = (word % 100) as u8;
+            let handler: Handler = match opcode {
+                1 => Cpu::exec_add,
+                2 => Cpu::exec_mul,
+                3 => Cpu::exec_input,
+                4 => Cpu::exec_output,
+                5 => Cpu::exec_jnz,
+                6 => Cpu::exec_jiz,
+                7 => Cpu::exec_lt,
+                8 => Cpu::exec_eq,
+                9 => Cpu::exec_adj_rel_base,
+                99 => Cpu::halt,
+                _ => Cpu::invalid,
+            };
+            Self { opcode, modes: [digit(100), digit(1000), digit(10_000)], handler }
+        }
+    }
+
+    impl Default for Decoded {
+        fn default() -> Self {
+            Self::new(0)
+        }
+    }
+
     /// The return conditions of the Cpu:
     /// - Output(RegVal) if some return instruction ocurred mid-program (without a Halt immediately
-    /// succeeding it),
+    ///   succeeding it),
     /// - DiagnosticCode(RegVal) if a return instruction with a Halt immediately after it was
-    /// encountered, or
+    ///   encountered, or
     /// - Halt if a Halt instruction was triggered that did not immediately follow an output
-    /// instruction
+    ///   instruction
     #[derive(PartialEq, Eq, Debug)]
     pub enum OutputState { Output(RegVal), DiagnosticCode(RegVal), Halt }
 
@@ -27,6 +80,8 @@ pub mod intcode_processor {
     #[derive(Default, Clone)]
     pub struct Cpu {
         memory: Vec<RegVal>,
+        decoded: Vec<Decoded>,
+        pages: HashMap<usize, Box<[RegVal]>>,
         instr_ptr: usize,
         input: VecDeque<RegVal>,
         rel_base: RegVal,
@@ -48,21 +103,16 @@ pub mod intcode_processor {
         /// assert_eq!(cpu.get(4), 99);
         /// ````
         pub fn with_memory(memory: Vec<RegVal>) -> Self {
-            Self {
-                memory,
-                ..Default::default()
+            let mut cpu = Self::default();
+            for (address, value) in memory.into_iter().enumerate() {
+                cpu.set(address, value);
             }
+            cpu
         }
 
         pub fn try_with_memory_from_str(input: &str) -> Result<Self, ParseIntError> {
             let mem: Result<Vec<_>, _> = input.split(',').map(|s| s.parse::<RegVal>()).collect();
-            match mem {
-                Ok(memory) => Ok(Self {
-                                    memory,
-                                    ..Default::default()
-                                }),
-                Err(e) => Err(e),
-            }
+            mem.map(Self::with_memory)
         }
 
         /// Set the value in memory address `address` to `value`.
@@ -76,11 +126,17 @@ pub mod intcode_processor {
         /// assert_eq!(cpu.get(0), 2);
         /// ````
         pub fn set(&mut self, address: usize, value: RegVal) {
-            let len = self.memory.len();
-            if address >= len {
-                self.memory.append(&mut vec![0; address+1-len]);
+            if address >= DENSE_LIMIT {
+                let page = self.pages.entry(address / PAGE_SIZE).or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice());
+                page[address % PAGE_SIZE] = value;
+                return;
+            }
+            if address >= self.memory.len() {
+                self.memory.resize(address + 1, 0);
+                self.decoded.resize(address + 1, Decoded::default());
             }
             self.memory[address] = value;
+            self.decoded[address] = Decoded::new(value);
         }
 
         /// Sets the input to `input`. This will be read by certain commands, like opcode 3
@@ -110,6 +166,24 @@ pub mod intcode_processor {
             self.input.len()
         }
 
+        /// Returns true if the program is stuck at an input instruction because the input is empty.
+        /// This tells apart a `Halt` returned by `run()` for lack of input from an actual Halt.
+        ///
+        /// ## Example
+        /// ````
+        /// use intcode_processor::intcode_processor::{Cpu, OutputState};
+        ///
+        /// let mut cpu = Cpu::with_memory(vec![3, 5, 4, 5, 99, 0]);
+        /// assert_eq!(cpu.run(), OutputState::Halt);
+        /// assert!(cpu.awaits_input());
+        /// cpu.set_input(42);
+        /// assert_eq!(cpu.run(), OutputState::DiagnosticCode(42));
+        /// assert!(!cpu.awaits_input());
+        /// ````
+        pub fn awaits_input(&self) -> bool {
+            self.input.is_empty() && self.get(self.instr_ptr) % 100 == 3
+        }
+
         /// Get the value from memory address `address`.
         ///
         /// ## Example
@@ -122,6 +196,8 @@ pub mod intcode_processor {
         pub fn get(&self, address: usize) -> RegVal {
             if address < self.memory.len() {
                 self.memory[address]
+            } else if address >= DENSE_LIMIT {
+                self.pages.get(&(address / PAGE_SIZE)).map_or(0, |page| page[address % PAGE_SIZE])
             } else {
                 0
             }
@@ -173,10 +249,8 @@ pub mod intcode_processor {
             self.instr_ptr += 4;
         }
 
-        /// Store the first element of `input` at address `dest`.
-        ///
-        /// ## Panics
-        /// Panics if dest points outside the memory or input is empty.
+        /// Store the first element of `input` at address `dest`. If `input` is empty, the instruction
+        /// pointer stays where it is, so the instruction is executed again on the next `run()`.
         ///
         /// ## Example
         /// ````
@@ -189,10 +263,11 @@ pub mod intcode_processor {
         /// assert_eq!(cpu.get(4), 42);
         /// ````
         fn set_to_input(&mut self/*, dest: usize*/) -> Option<isize> {
-            // let input = self.input.pop_front().expect("Input is empty");
-            // self.set(dest, input);
-            self.instr_ptr += 2;
-            self.input.pop_front()
+            let input = self.input.pop_front();
+            if input.is_some() {
+                self.instr_ptr += 2;
+            }
+            input
         }
 
         /// Return DiagnosticCode(val) if the next instruction is Halt (opcode 99) and Output(val) otherwise.
@@ -357,6 +432,16 @@ pub mod intcode_processor {
             (self.rel_base + self.get(self.instr_ptr + offset)) as usize 
         } 
 
+        // The address parameter `offset` (1-based) of the current instruction refers to.
+        fn param(&self, decoded: &Decoded, offset: usize) -> usize {
+            match decoded.modes[offset - 1] {
+                0 => self.pos(offset),
+                1 => self.imm(offset),
+                2 => self.rel(offset),
+                e => panic!("Unexpected mode: {e}"),
+            }
+        }
+
         /// Run the program from current memory, starting at `instr_ptr` and running until opcode
         /// 99 (Halt) is encountered.
         ///
@@ -380,36 +465,121 @@ pub mod intcode_processor {
         /// ````
         pub fn run(&mut self) -> OutputState {
             loop {
-                let instruction = self.memory[self.instr_ptr];
-                // eprintln!("{}: {}", self.instr_ptr, instruction);
-                let params: Vec<usize> = (1..=3).map(|i| match (instruction/(10_isize.pow(i+1)))%10 {
-                    0 => self.pos(i as usize),
-                    1 => self.imm(i as usize),
-                    2 => self.rel(i as usize),
-                    e => panic!("Unexpected mode: {e}"),
-                }).collect();
-
-                match instruction % 100 {
-                    1 => self.add(params[0], params[1], params[2]),
-                    2 => self.mul(params[0], params[1], params[2]),
+                let decoded = match self.decoded.get(self.instr_ptr) {
+                    Some(decoded) => *decoded,
+                    None => Decoded::new(self.get(self.instr_ptr)),
+                };
+                if let Some(state) = (decoded.handler)(self, decoded) {
+                    return state;
+                }
+            }
+        }
+
+        /// Does the same as `run()`, but the plain way: every instruction is taken apart again
+        /// whenever the program gets to it, and dispatched by its opcode. This is kept around to
+        /// check the two against each other, and as a baseline for benchmarks.
+        ///
+        /// ## Example
+        /// ````
+        /// use intcode_processor::intcode_processor::{Cpu, OutputState};
+        ///
+        /// let mut cpu = Cpu::with_memory(vec![1, 5, 6, 0, 99, 23, 42]);
+        /// assert_eq!(cpu.clone().run_decoding(), cpu.run());
+        /// assert_eq!(cpu.get(0), 65);
+        /// ````
+        pub fn run_decoding(&mut self) -> OutputState {
+            loop {
+                let ip = self.instr_ptr;
+                let decoded = Decoded::new(self.get(ip));
+                let p = |offset| self.param(&decoded, offset);
+
+                match decoded.opcode {
+                    1 => { let (a, b, c) = (p(1), p(2), p(3)); self.add(a, b, c) },
+                    2 => { let (a, b, c) = (p(1), p(2), p(3)); self.mul(a, b, c) },
                     3 => { 
+                        let dest = p(1);
                         if let Some(input) = self.set_to_input() {
-                            self.set(params[0], input);
+                            self.set(dest, input);
                         } else {
                             return OutputState::Halt;
                         }
                     },
-                    4 => return self.ret(params[0]),
-                    5 => self.jnz(params[0], params[1]),
-                    6 => self.jiz(params[0], params[1]),
-                    7 => self.lt(params[0], params[1], params[2]),
-                    8 => self.eq(params[0], params[1], params[2]),
-                    9 => self.adj_rel_base(params[0]),
+                    4 => { let a = p(1); return self.ret(a) },
+                    5 => { let (a, b) = (p(1), p(2)); self.jnz(a, b) },
+                    6 => { let (a, b) = (p(1), p(2)); self.jiz(a, b) },
+                    7 => { let (a, b, c) = (p(1), p(2), p(3)); self.lt(a, b, c) },
+                    8 => { let (a, b, c) = (p(1), p(2), p(3)); self.eq(a, b, c) },
+                    9 => { let a = p(1); self.adj_rel_base(a) },
                     99 => return OutputState::Halt,
-                    _ => panic!("Unexpected instruction: {}", instruction),
+                    _ => panic!("Unexpected instruction: {}", self.get(ip)),
                 }
             }
         }
+
+        // The handlers `Decoded` resolves opcodes to, one per instruction.
+        fn exec_add(&mut self, d: Decoded) -> Option<OutputState> {
+            let (a, b, c) = (self.param(&d, 1), self.param(&d, 2), self.param(&d, 3));
+            self.add(a, b, c);
+            None
+        }
+
+        fn exec_mul(&mut self, d: Decoded) -> Option<OutputState> {
+            let (a, b, c) = (self.param(&d, 1), self.param(&d, 2), self.param(&d, 3));
+            self.mul(a, b, c);
+            None
+        }
+
+        fn exec_input(&mut self, d: Decoded) -> Option<OutputState> {
+            let dest = self.param(&d, 1);
+            let Some(input) = self.set_to_input() else {
+                return Some(OutputState::Halt);
+            };
+            self.set(dest, input);
+            None
+        }
+
+        fn exec_output(&mut self, d: Decoded) -> Option<OutputState> {
+            let a = self.param(&d, 1);
+            Some(self.ret(a))
+        }
+
+        fn exec_jnz(&mut self, d: Decoded) -> Option<OutputState> {
+            let (a, b) = (self.param(&d, 1), self.param(&d, 2));
+            self.jnz(a, b);
+            None
+        }
+
+        fn exec_jiz(&mut self, d: Decoded) -> Option<OutputState> {
+            let (a, b) = (self.param(&d, 1), self.param(&d, 2));
+            self.jiz(a, b);
+            None
+        }
+
+        fn exec_lt(&mut self, d: Decoded) -> Option<OutputState> {
+            let (a, b, c) = (self.param(&d, 1), self.param(&d, 2), self.param(&d, 3));
+            self.lt(a, b, c);
+            None
+        }
+
+        fn exec_eq(&mut self, d: Decoded) -> Option<OutputState> {
+            let (a, b, c) = (self.param(&d, 1), self.param(&d, 2), self.param(&d, 3));
+            self.eq(a, b, c);
+            None
+        }
+
+        fn exec_adj_rel_base(&mut self, d: Decoded) -> Option<OutputState> {
+            let a = self.param(&d, 1);
+            self.adj_rel_base(a);
+            None
+        }
+
+        fn halt(&mut self, _d: Decoded) -> Option<OutputState> {
+            Some(OutputState::Halt)
+        }
+
+        fn invalid(&mut self, _d: Decoded) -> Option<OutputState> {
+            panic!("Unexpected instruction: {}", self.get(self.instr_ptr))
+        }
         
     }
 }
@@ -500,4 +670,67 @@ mod tests {
 
         assert_eq!(cpu.run(), OutputState::DiagnosticCode(2316632620));
     }
+
+    #[test]
+    fn engines() {
+        // Both engines run into the same outputs, and leave the same memory behind.
+        let mem = vec![3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99];
+        for input in 0..=10 {
+            let mut threaded = Cpu::with_memory(mem.to_vec());
+            threaded.set_input(input);
+            let mut decoding = threaded.clone();
+            assert_eq!(threaded.run(), decoding.run_decoding());
+            assert_eq!(threaded.run(), decoding.run_decoding());
+            assert!((0..mem.len()).all(|addr| threaded.get(addr) == decoding.get(addr)));
+        }
+        let mut cpu = Cpu::with_memory(vec![1101, 104, 0, 4, 1101, 0, 0, 0, 99]);
+        assert_eq!(cpu.run_decoding(), OutputState::Output(0));
+    }
+
+    #[test]
+    fn memory() {
+        // Write far beyond the program and read it back via relative mode.
+        let mut cpu = Cpu::with_memory(vec![109, 1_000_000_000, 21101, 23, 19, 7, 204, 7, 99]);
+        assert_eq!(cpu.run(), OutputState::DiagnosticCode(42));
+        assert_eq!(cpu.get(1_000_000_007), 42);
+        assert_eq!(cpu.get(1_000_000_008), 0);
+
+        // Overwrite the upcoming instruction: 1101,0,0,0 (which would store 0 to 0)
+        // becomes 104,0 (output 0).
+        let mut cpu = Cpu::with_memory(vec![1101, 104, 0, 4, 1101, 0, 0, 0, 99]);
+        assert_eq!(cpu.run(), OutputState::Output(0));
+    }
+
+    #[test]
+    fn ascii_terminal() {
+        use super::ascii::*;
+
+        // Echo whatever comes in, forever.
+        let echo = Cpu::with_memory(vec![3, 100, 4, 100, 1105, 1, 0]);
+        let mut terminal = Terminal::new(echo.clone(), Encoding::Ascii);
+        assert_eq!(terminal.read(), (String::new(), vec![], Stop::AwaitingInput));
+        terminal.define_macro("twice", &["hello", "world"]);
+        assert_eq!(terminal.command("twice"), Ok(("hello\nworld\n".to_string(), vec![], Stop::AwaitingInput)));
+        let mut output = Vec::new();
+        assert_eq!(terminal.interact("!macro hi hey;ho\nhi\n".as_bytes(), &mut output).unwrap(), Stop::AwaitingInput);
+        assert_eq!(String::from_utf8(output).unwrap(), "hey\nho\n");
+
+        let transcript = terminal.transcript().to_string();
+        assert_eq!(transcript, "> hello\n| hello\n> world\n| world\n> hey\n| hey\n> ho\n| ho\n");
+        let transcript: Transcript = transcript.parse().unwrap();
+        assert!(replay(echo.clone(), Encoding::Ascii, &transcript).is_ok());
+        let tampered: Transcript = "> hello\n| hello\n> world\n| word\n".parse().unwrap();
+        assert_eq!(replay(echo.clone(), Encoding::Ascii, &tampered).err(), Some(SessionError::Mismatch {
+            input: 2,
+            expected: "| word\n".to_string(),
+            actual: "| world\n".to_string(),
+        }));
+
+        // Add two numbers and halt.
+        let add = Cpu::with_memory(vec![3, 12, 3, 13, 1, 12, 13, 14, 4, 14, 99, 0, 0, 0, 0]);
+        let mut terminal = Terminal::new(add, Encoding::Numeric);
+        terminal.read();
+        assert_eq!(terminal.command("1000, 337"), Ok((String::new(), vec![1337], Stop::Halted)));
+        assert_eq!(terminal.transcript().to_string(), "> 1000, 337\n= 1337\n");
+    }
 }
@@ -1,106 +1,169 @@
-use std::{sync::{mpsc, Arc}, thread, collections::VecDeque, num::ParseIntError};
+use std::{collections::VecDeque, fmt::Display, num::ParseIntError};
 
 use intcode_processor::intcode_processor::{Cpu, OutputState};
 
-pub fn run(input: &str) -> Result<(isize, isize), ParseIntError> {
-    let template = Cpu::try_with_memory_from_str(input)?;
-    let mut first = 0;
-    for perm in get_permutations(&(0..5).collect()) {
-        let mut output = 0;
-        for input in perm.iter() {
-            let mut amp = template.clone();
-            amp.set_input(*input);
-            amp.set_input(output);
-            loop {
-                match amp.run() {
-                    OutputState::DiagnosticCode(out) => {
-                            output = out;
-                            break;
-                        },
-                    OutputState::Output(e) => amp.set_input(e),
-                    OutputState::Halt => break,
-                }
-            }
+#[derive(Debug, PartialEq, Eq)]
+pub enum CircuitError {
+    ParseIntError(ParseIntError),
+    NoOutput,
+    UnknownAmplifier(usize),
+}
+
+impl From<ParseIntError> for CircuitError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl Display for CircuitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::NoOutput => write!(f, "The output amplifier never sent anything"),
+            Self::UnknownAmplifier(idx) => write!(f, "There is no amplifier {idx}"),
         }
-        first = first.max(output);
     }
-    let mut second = 0;
-    for perm in get_permutations(&(5..=9).collect()) {
-        let output = Arc::new(std::sync::Mutex::new(0));
-        let mut receivers = VecDeque::new();
-        let mut transmitters = VecDeque::new();
-        let last = perm.len()-1;
-        for _ in 0..=last {
-            let (t, r) = mpsc::channel::<isize>();
-            receivers.push_back(r);
-            transmitters.push_back(t);
+}
+
+/// Yields every ordering of some items, in lexicographic order of their
+/// original positions.
+pub struct Permutations<T> {
+    items: Vec<T>,
+    indices: Option<Vec<usize>>,
+}
+
+impl<T> Permutations<T> {
+    pub fn new(items: Vec<T>) -> Self {
+        let indices = Some((0..items.len()).collect());
+        Self { items, indices }
+    }
+}
+
+impl<T: Clone> Iterator for Permutations<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let indices = self.indices.as_mut()?;
+        let current = indices.iter().map(|&idx| self.items[idx].clone()).collect();
+        // Advance to the next permutation: find the last ascent, swap it with
+        // the smallest larger element after it and reverse the tail.
+        match (1..indices.len()).rev().find(|&i| indices[i-1] < indices[i]) {
+            Some(i) => {
+                let j = (i..indices.len()).rev().find(|&j| indices[j] > indices[i-1]).unwrap_or(i);
+                indices.swap(i-1, j);
+                indices[i..].reverse();
+            },
+            None => self.indices = None,
         }
-        transmitters[0].send(0).unwrap();
-        transmitters.rotate_left(1);
-
-        let mut handles = Vec::new();
-        for (amp_idx, phase) in perm.into_iter().enumerate() {
-            let tx = transmitters.pop_front().unwrap();
-            let rx = receivers.pop_front().unwrap();
-            let output = Arc::clone(&output);
-            let mut local_out = 0;
-            let mut amp = template.clone();
-            let handle = thread::spawn(move || {
-                amp.set_input(phase);
+        Some(current)
+    }
+}
+
+/// A number of amplifiers running the same program, each with its own phase
+/// setting, and connections between them. Every output of an amplifier is
+/// sent to all amplifiers it is connected to.
+pub struct Circuit {
+    amplifiers: Vec<Cpu>,
+    connections: Vec<(usize, usize)>,
+}
+
+impl Circuit {
+    pub fn new(program: &Cpu, phases: &[isize]) -> Self {
+        let amplifiers = phases.iter().map(|&phase| {
+            let mut amp = program.clone();
+            amp.set_input(phase);
+            amp
+        }).collect();
+        Self { amplifiers, connections: Vec::new() }
+    }
+
+    /// Amplifiers connected one after another, in the order of `phases`.
+    pub fn chain(program: &Cpu, phases: &[isize]) -> Self {
+        let mut circuit = Self::new(program, phases);
+        for idx in 1..phases.len() {
+            circuit.connect(idx - 1, idx);
+        }
+        circuit
+    }
+
+    /// A chain whose last amplifier feeds back into the first one.
+    pub fn feedback_loop(program: &Cpu, phases: &[isize]) -> Self {
+        let mut circuit = Self::chain(program, phases);
+        if !phases.is_empty() {
+            circuit.connect(phases.len() - 1, 0);
+        }
+        circuit
+    }
+
+    pub fn connect(&mut self, from: usize, to: usize) -> &mut Self {
+        self.connections.push((from, to));
+        self
+    }
+
+    /// Sends `signal` to amplifier `entry` and runs all amplifiers until none
+    /// of them can make progress anymore. Returns the last signal `exit` sent.
+    pub fn run(&mut self, entry: usize, signal: isize, exit: usize) -> Result<isize, CircuitError> {
+        for &idx in [entry, exit].iter().chain(self.connections.iter().flat_map(|(from, to)| [from, to])) {
+            if idx >= self.amplifiers.len() {
+                return Err(CircuitError::UnknownAmplifier(idx));
+            }
+        }
+        let mut inboxes = vec![VecDeque::new(); self.amplifiers.len()];
+        inboxes[entry].push_back(signal);
+        let mut halted = vec![false; self.amplifiers.len()];
+        let mut result = None;
+        loop {
+            let mut progress = false;
+            for idx in 0..self.amplifiers.len() {
+                if halted[idx] {
+                    // Nobody is listening anymore.
+                    inboxes[idx].clear();
+                    continue;
+                }
+                let amp = &mut self.amplifiers[idx];
+                inboxes[idx].drain(..).for_each(|v| amp.set_input(v));
                 loop {
-                    amp.set_input(rx.recv().unwrap());
-                    match amp.run() {
-                        OutputState::Output(out) => {
-                            local_out = out;
-                            tx.send(out).unwrap(); 
-                        },
+                    let (value, stopped) = match amp.run() {
+                        OutputState::Output(v) => (v, false),
+                        OutputState::DiagnosticCode(v) => (v, true),
                         OutputState::Halt => {
-                            // Swallow errors, because at this point we are halting anyway, so we
-                            // don't care about whether or not the next amp has already
-                            // deconstructed anymore.
-                            let _ = tx.send(0);
-                            break;
-                        },
-                        OutputState::DiagnosticCode(d) => { 
-                            local_out = d;
-                            // Swallow errors, because at this point we are halting anyway, so we
-                            // don't care about whether or not the next amp has already
-                            // deconstructed anymore.
-                            let _ = tx.send(d);
+                            halted[idx] = !amp.awaits_input();
                             break;
                         },
+                    };
+                    progress = true;
+                    if idx == exit {
+                        result = Some(value);
+                    }
+                    for &(_, to) in self.connections.iter().filter(|(from, _)| *from == idx) {
+                        inboxes[to].push_back(value);
+                    }
+                    if stopped {
+                        halted[idx] = true;
+                        break;
                     }
                 }
-                if amp_idx == last {
-                    *output.lock().unwrap() = local_out;
-                }
-            });
-            handles.push(handle);
-        }
-        for handle in handles {
-            handle.join().unwrap()
+            }
+            if !progress && inboxes.iter().all(VecDeque::is_empty) {
+                return result.ok_or(CircuitError::NoOutput);
+            }
         }
-        second = second.max(*output.lock().unwrap());
     }
-    Ok((first, second))
 }
 
-fn get_permutations(numbers: &Vec<isize>) -> Vec<Vec<isize>> {
-    if numbers.len() == 1 {
-        vec![numbers.to_vec()]
-    } else {
-        let mut res = Vec::new();
-        for (idx, n) in numbers.iter().enumerate() {
-            let mut rest = numbers.to_vec();
-            rest.remove(idx);
-            let rest_perms = get_permutations(&rest);
-            for mut p in rest_perms {
-                p.push(*n);
-                res.push(p);
-            }
-        }
-        res
-    }
+fn max_signal(program: &Cpu, phases: Vec<isize>, build: fn(&Cpu, &[isize]) -> Circuit) -> Result<isize, CircuitError> {
+    // Without amplifiers, there's nothing to send a signal to the thrusters.
+    let last = phases.len().checked_sub(1).ok_or(CircuitError::NoOutput)?;
+    Permutations::new(phases)
+        .map(|perm| build(program, &perm).run(0, 0, last))
+        .try_fold(isize::MIN, |max, signal| signal.map(|s| max.max(s)))
+}
+
+pub fn run(input: &str) -> Result<(isize, isize), CircuitError> {
+    let template = Cpu::try_with_memory_from_str(input)?;
+    let first = max_signal(&template, (0..5).collect(), Circuit::chain)?;
+    let second = max_signal(&template, (5..10).collect(), Circuit::feedback_loop)?;
+    Ok((first, second))
 }
 
 #[cfg(test)]
@@ -123,7 +186,36 @@ mod tests {
     fn test_sample_2() {
         let sample_input = read_file("tests/sample_input_2");
         // The first part of the output is not verified, but that's what my solution produces.
-        assert_eq!(run(&sample_input), Ok((0, 18216)));
+        assert_eq!(run(&sample_input), Ok((20, 18216)));
+    }
+
+    #[test]
+    fn test_permutations() {
+        let perms: Vec<_> = Permutations::new(vec!['a', 'b', 'c']).collect();
+        assert_eq!(perms, [['a', 'b', 'c'], ['a', 'c', 'b'], ['b', 'a', 'c'], ['b', 'c', 'a'], ['c', 'a', 'b'], ['c', 'b', 'a']]);
+        assert_eq!(Permutations::new((0..6).collect::<Vec<_>>()).count(), 720);
+        assert_eq!(Permutations::<u8>::new(vec![]).collect::<Vec<_>>(), [Vec::<u8>::new()]);
+    }
+
+    #[test]
+    fn test_circuit() {
+        let sample_input = read_file("tests/sample_input_2");
+        let program = Cpu::try_with_memory_from_str(&sample_input).unwrap();
+        let phases = [9, 7, 8, 5, 6];
+        assert_eq!(Circuit::feedback_loop(&program, &phases).run(0, 0, 4), Ok(18216));
+        // The same loop, wired up by hand.
+        let mut circuit = Circuit::new(&program, &phases);
+        circuit.connect(0, 1).connect(1, 2).connect(2, 3).connect(3, 4).connect(4, 0);
+        assert_eq!(circuit.run(0, 0, 4), Ok(18216));
+        assert_eq!(Circuit::chain(&program, &phases).run(0, 0, 7), Err(CircuitError::UnknownAmplifier(7)));
+
+        // Adds its phase to every input. Two branches meet again, and the last signal is the
+        // one that passed amplifier 2.
+        let adder = Cpu::try_with_memory_from_str("3,20,3,21,1,20,21,21,4,21,1105,1,2").unwrap();
+        let mut circuit = Circuit::new(&adder, &[1, 10, 100, 1000]);
+        circuit.connect(0, 1).connect(0, 2).connect(2, 3).connect(1, 3);
+        assert_eq!(circuit.run(0, 0, 3), Ok(1101));
+        assert_eq!(max_signal(&adder, vec![], Circuit::chain), Err(CircuitError::NoOutput));
     }
 
     #[test]
@@ -1,62 +1,385 @@
-use std::collections::HashMap;
-
-use intcode_processor::intcode_processor::{Cpu, OutputState};
-
-pub fn run(input: &str) -> (usize, isize) {
-    let mut cpu = Cpu::try_with_memory_from_str(input).unwrap();
-    let mut cpu_2 = cpu.clone();
-    let mut screen = HashMap::new();
-    let mut screen_2 = HashMap::new();
-    while let OutputState::Output(x) = cpu.run() {
-        if let OutputState::Output(y) = cpu.run() {
-            if let OutputState::Output(tile) = cpu.run() {
-                screen.insert((x, y), tile);
-            }
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+use std::num::ParseIntError;
+
+use intcode_processor::ascii::{Encoding, SessionError, Stop, Terminal, Transcript};
+use intcode_processor::intcode_processor::Cpu;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArcadeError {
+    ParseIntError(ParseIntError),
+    IncompleteOutput,
+    Io(String),
+    Session(SessionError),
+    UnknownTile(isize),
+}
+
+impl From<ParseIntError> for ArcadeError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl From<SessionError> for ArcadeError {
+    fn from(value: SessionError) -> Self {
+        Self::Session(value)
+    }
+}
+
+impl From<std::io::Error> for ArcadeError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value.to_string())
+    }
+}
+
+impl Display for ArcadeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::IncompleteOutput => write!(f, "Game stopped in the middle of drawing a tile"),
+            Self::Io(e) => write!(f, "Unable to talk to the player: {e}"),
+            Self::Session(e) => write!(f, "{e}"),
+            Self::UnknownTile(t) => write!(f, "Unknown tile id: {t}"),
         }
     }
-    let first = screen.iter().filter(|(_coords, tile)| **tile == 2).count();
-
-    cpu_2.set(0, 2);
-    let second = play(&mut cpu_2, &mut screen_2);
-    (first, second)
-}
-
-fn play(cpu: &mut Cpu, screen: &mut HashMap<(isize, isize), isize>) -> isize {
-    let mut res = 0;
-    let mut ball_pos = 0;
-    let mut pad_pos = 0;
-    while let OutputState::Output(x) = cpu.run() {
-        if let OutputState::Output(y) = cpu.run() {
-            if let OutputState::Output(tile) = cpu.run() {
-                if x == -1 && y == 0 {
-                    res = tile;
-                } else {
-                    if tile == 4 {
-                        ball_pos = x;
-                    } else if tile == 3 {
-                        pad_pos = x;
-                    }
-                    screen.insert((x, y), tile);
-                    cpu.reset_input((ball_pos - pad_pos).signum());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tile { Empty, Wall, Block, Paddle, Ball }
+
+impl TryFrom<isize> for Tile {
+    type Error = ArcadeError;
+
+    fn try_from(value: isize) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Empty),
+            1 => Ok(Self::Wall),
+            2 => Ok(Self::Block),
+            3 => Ok(Self::Paddle),
+            4 => Ok(Self::Ball),
+            _ => Err(ArcadeError::UnknownTile(value)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Joystick { Left, Neutral, Right }
+
+// Keys the arcade understands besides plain joystick values.
+const JOYSTICK_MACROS: [(&str, &str); 3] = [("a", "-1"), ("d", "1"), ("", "0")];
+
+impl Joystick {
+    fn value(self) -> isize {
+        match self {
+            Self::Left => -1,
+            Self::Neutral => 0,
+            Self::Right => 1,
+        }
+    }
+}
+
+/// Decides where to tilt the joystick whenever the game asks for it.
+pub trait Controller {
+    fn joystick(&mut self, arcade: &Arcade) -> Joystick;
+
+    /// The line to send to the game for the next move. It may name one of the
+    /// arcade's joystick macros. `None` stops playing.
+    fn command(&mut self, arcade: &Arcade) -> Result<Option<String>, ArcadeError> {
+        Ok(Some(self.joystick(arcade).value().to_string()))
+    }
+}
+
+/// Keeps the paddle right below the ball.
+pub struct Tracker;
+
+impl Controller for Tracker {
+    fn joystick(&mut self, arcade: &Arcade) -> Joystick {
+        match (arcade.ball(), arcade.paddle()) {
+            (Some((ball, _)), Some((paddle, _))) if ball < paddle => Joystick::Left,
+            (Some((ball, _)), Some((paddle, _))) if ball > paddle => Joystick::Right,
+            _ => Joystick::Neutral,
+        }
+    }
+}
+
+/// Plays back a fixed sequence of moves and stays put once they run out.
+pub struct Scripted {
+    moves: VecDeque<Joystick>,
+}
+
+impl Scripted {
+    pub fn new(moves: &[Joystick]) -> Self {
+        Self { moves: moves.iter().copied().collect() }
+    }
+}
+
+impl Controller for Scripted {
+    fn joystick(&mut self, _arcade: &Arcade) -> Joystick {
+        self.moves.pop_front().unwrap_or(Joystick::Neutral)
+    }
+}
+
+/// Lets a human play on the console: `a` tilts the joystick left, `d` right,
+/// and an empty line keeps it neutral. Numbers are passed on to the game as
+/// they are, and `!save <path>` writes the transcript so far to a file. Play
+/// stops once `input` runs dry.
+pub struct Keyboard<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Keyboard<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+}
+
+impl<R: BufRead, W: Write> Controller for Keyboard<R, W> {
+    fn joystick(&mut self, arcade: &Arcade) -> Joystick {
+        let line = self.command(arcade).ok().flatten().unwrap_or_default();
+        let value = JOYSTICK_MACROS.iter().find(|&&(key, _)| key == line).map_or(&line[..], |&(_, value)| value);
+        match value.parse::<isize>().map(isize::signum) {
+            Ok(-1) => Joystick::Left,
+            Ok(1) => Joystick::Right,
+            _ => Joystick::Neutral,
+        }
+    }
+
+    fn command(&mut self, arcade: &Arcade) -> Result<Option<String>, ArcadeError> {
+        loop {
+            write!(self.output, "{arcade}[a] left, [d] right, [enter] stay: ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim();
+            if let Some(path) = line.strip_prefix("!save ") {
+                match arcade.transcript().save(path.trim()) {
+                    Ok(()) => writeln!(self.output, "Transcript saved to {}", path.trim())?,
+                    Err(e) => writeln!(self.output, "Unable to save transcript: {e}")?,
                 }
+            } else if JOYSTICK_MACROS.iter().any(|&(key, _)| key == line) || line.parse::<isize>().is_ok() {
+                return Ok(Some(line.to_string()));
+            } else {
+                writeln!(self.output, "Unknown move: {line}")?;
             }
         }
     }
-    res
+}
+
+pub struct Arcade {
+    terminal: Terminal,
+    tiles: HashMap<(isize, isize), Tile>,
+    ball: Option<(isize, isize)>,
+    paddle: Option<(isize, isize)>,
+    score: isize,
+    frames: usize,
+    over: bool,
+}
+
+impl Arcade {
+    pub fn new(cpu: Cpu) -> Self {
+        let mut terminal = Terminal::new(cpu, Encoding::Numeric);
+        for (key, value) in JOYSTICK_MACROS {
+            terminal.define_macro(key, &[value]);
+        }
+        Self {
+            terminal,
+            tiles: HashMap::new(),
+            ball: None,
+            paddle: None,
+            score: 0,
+            frames: 0,
+            over: false,
+        }
+    }
+
+    /// Sets the cabinet to free play, so it waits for joystick input instead
+    /// of just drawing the screen once.
+    pub fn insert_coins(&mut self) {
+        self.terminal.cpu_mut().set(0, 2);
+    }
+
+    pub fn tile(&self, pos: (isize, isize)) -> Tile {
+        self.tiles.get(&pos).copied().unwrap_or(Tile::Empty)
+    }
+
+    pub fn tiles(&self) -> &HashMap<(isize, isize), Tile> {
+        &self.tiles
+    }
+
+    pub fn blocks(&self) -> usize {
+        self.tiles.values().filter(|&&t| t == Tile::Block).count()
+    }
+
+    pub fn ball(&self) -> Option<(isize, isize)> {
+        self.ball
+    }
+
+    pub fn paddle(&self) -> Option<(isize, isize)> {
+        self.paddle
+    }
+
+    pub fn score(&self) -> isize {
+        self.score
+    }
+
+    /// How many frames were drawn so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.over
+    }
+
+    /// Everything the game drew and every move it was sent so far.
+    pub fn transcript(&self) -> &Transcript {
+        self.terminal.transcript()
+    }
+
+    // Updates the screen and score from the game's output.
+    fn draw(&mut self, values: &[isize]) -> Result<(), ArcadeError> {
+        let tiles = values.chunks_exact(3);
+        if !tiles.remainder().is_empty() {
+            return Err(ArcadeError::IncompleteOutput);
+        }
+        for tile in tiles {
+            let (x, y, id) = (tile[0], tile[1], tile[2]);
+            if (x, y) == (-1, 0) {
+                self.score = id;
+                continue;
+            }
+            let tile = Tile::try_from(id)?;
+            match tile {
+                Tile::Ball => self.ball = Some((x, y)),
+                Tile::Paddle => self.paddle = Some((x, y)),
+                _ => (),
+            }
+            self.tiles.insert((x, y), tile);
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Runs the game until it either asks for the joystick or ends, updating
+    /// the screen and score on the way.
+    pub fn frame(&mut self) -> Result<(), ArcadeError> {
+        let (_, values, stop) = self.terminal.read();
+        self.over |= stop == Stop::Halted;
+        self.draw(&values)
+    }
+
+    /// Plays until the game is over or `controller` gives up, asking it for
+    /// every move. Returns the final score.
+    pub fn play(&mut self, controller: &mut dyn Controller) -> Result<isize, ArcadeError> {
+        self.frame()?;
+        while !self.over {
+            let Some(command) = controller.command(self)? else {
+                break;
+            };
+            let (_, values, stop) = self.terminal.command(&command)?;
+            self.over |= stop == Stop::Halted;
+            self.draw(&values)?;
+        }
+        Ok(self.score)
+    }
+
+    /// Lets a human play on the console, see `Keyboard`.
+    pub fn interact(&mut self, input: impl BufRead, output: impl Write) -> Result<isize, ArcadeError> {
+        self.play(&mut Keyboard::new(input, output))
+    }
+}
+
+impl Display for Arcade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let max_x = self.tiles.keys().map(|&(x, _)| x).max().unwrap_or(0);
+        let max_y = self.tiles.keys().map(|&(_, y)| y).max().unwrap_or(0);
+        writeln!(f, "Score: {}", self.score)?;
+        for y in 0..=max_y {
+            let line: String = (0..=max_x).map(|x| match self.tile((x, y)) {
+                Tile::Empty => ' ',
+                Tile::Wall => '#',
+                Tile::Block => '=',
+                Tile::Paddle => '-',
+                Tile::Ball => 'o',
+            }).collect();
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(input: &str) -> Result<(usize, isize), ArcadeError> {
+    let cpu = Cpu::try_with_memory_from_str(input)?;
+    let mut arcade = Arcade::new(cpu.clone());
+    arcade.frame()?;
+    let first = arcade.blocks();
+
+    let mut arcade = Arcade::new(cpu);
+    arcade.insert_coins();
+    let second = arcade.play(&mut Tracker)?;
+    Ok((first, second))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use intcode_processor::ascii::replay;
     use std::fs::read_to_string;
 
     fn read_file(name: &str) -> String {
         read_to_string(name).expect(&format!("Unable to read file: {name}")[..]).trim().to_string()
     }
 
+    #[test]
+    fn test_arcade() {
+        let challenge_input = read_file("tests/challenge_input");
+        let cpu = Cpu::try_with_memory_from_str(&challenge_input).unwrap();
+
+        let mut arcade = Arcade::new(cpu.clone());
+        arcade.insert_coins();
+        arcade.frame().unwrap();
+        assert!(!arcade.is_over());
+        assert_eq!(arcade.frames(), 1);
+        let screen = arcade.to_string();
+        let (score, board) = screen.split_once('\n').unwrap();
+        assert_eq!(score, "Score: 0");
+        assert!(board.starts_with("####"));
+        assert_eq!(board.matches('=').count(), 333);
+        assert_eq!(board.matches('o').count(), 1);
+        assert_eq!(board.matches('-').count(), 1);
+
+        // Not moving at all loses the ball quickly.
+        let score = arcade.play(&mut Scripted::new(&[])).unwrap();
+        assert!(arcade.is_over());
+        assert!(arcade.blocks() > 0);
+        assert_eq!(score, arcade.score());
+
+        // The same game, played back from its transcript.
+        let mut coins = cpu.clone();
+        coins.set(0, 2);
+        let transcript: Transcript = arcade.transcript().to_string().parse().unwrap();
+        assert!(replay(coins, Encoding::Numeric, &transcript).is_ok());
+
+        // Neither does a lazy player, who moves right once and then only
+        // types enter.
+        let mut output = Vec::new();
+        let mut arcade = Arcade::new(cpu);
+        arcade.insert_coins();
+        let input = "x\nd\n".to_string() + &"\n".repeat(10_000);
+        assert_eq!(arcade.play(&mut Keyboard::new(input.as_bytes(), &mut output)).unwrap(), score);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("[a] left"));
+        assert!(output.contains("Unknown move: x"));
+        assert_eq!(arcade.transcript().inputs().take(3).collect::<Vec<_>>(), ["1", "0", "0"]);
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
-        assert_eq!(run(&challenge_input), (333, 16539));
+        assert_eq!(run(&challenge_input), Ok((333, 16539)));
     }
 }
@@ -1,85 +1,116 @@
-use std::{num::ParseIntError, collections::{HashMap, HashSet}};
-use intcode_processor::intcode_processor::{Cpu, OutputState};
+use std::num::ParseIntError;
+use intcode_processor::ascii::{Encoding, SessionError, Terminal, Transcript};
+use intcode_processor::intcode_processor::Cpu;
 
-pub fn run(input: &str) -> Result<(usize, usize), ParseIntError> {
-    let cpu = Cpu::try_with_memory_from_str(input)?;
-    let (first, cpu_2) = find_oxygen(&cpu);
-    let second = fill_map(&cpu_2);
-    Ok((first, second))
+mod maze;
+pub use maze::{Direction, Maze, ParseError, Position, Tile};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExploreError {
+    ParseIntError(ParseIntError),
+    Halted,
+    NoOxygen,
+    Session(SessionError),
+    UnexpectedStatus(isize),
 }
 
-fn fill_map(cpu: &Cpu) -> usize {
-    let current = (0, 0);
-    let mut area = HashSet::from([current]);
-    let mut reached_last_step = Vec::from([(current, cpu.clone())]);
-    for steps in 0.. {
-        let mut reached_this_step = Vec::new();
-        for (pos, this_cpu) in &reached_last_step {
-            for direction in 1..=4 {
-                let new_pos = get_coords(*pos, direction);
-                if !area.contains(&new_pos) {
-                    let mut new_cpu = this_cpu.clone();
-                    new_cpu.set_input(direction);
-                    if let OutputState::Output(state) = new_cpu.run() {
-                        area.insert(new_pos);
-                        match state {
-                            0 => (),
-                            1 | 2=> reached_this_step.push((new_pos, new_cpu)),
-                            _ => panic!("Unexpected state returned: {state}"),
-                        }
-                    } else {
-                        panic!("Cpu did not return an Output");
-                    }
-                }
-            }
-        }
-        if reached_this_step.is_empty() {
-            return steps;
+impl From<ParseIntError> for ExploreError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl From<SessionError> for ExploreError {
+    fn from(value: SessionError) -> Self {
+        Self::Session(value)
+    }
+}
+
+impl std::fmt::Display for ExploreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::Halted => write!(f, "Repair droid stopped responding"),
+            Self::NoOxygen => write!(f, "The oxygen system is nowhere to be found"),
+            Self::Session(e) => write!(f, "{e}"),
+            Self::UnexpectedStatus(s) => write!(f, "Unexpected status code: {s}"),
         }
-        std::mem::swap(&mut reached_this_step, &mut reached_last_step);
     }
-    unreachable!("The loop always runs and can never exit except by returning");
 }
 
-fn find_oxygen(cpu: &Cpu) -> (usize, Cpu) {
-    let current = (0, 0);
-    let mut area = HashMap::from([(current, 1)]);
-    let mut reached_last_step = Vec::from([(current, cpu.clone())]);
-    for steps in 1.. {
-        let mut reached_this_step = Vec::new();
-        for (pos, this_cpu) in &reached_last_step {
-            for direction in 1..=4 {
-                let new_pos = get_coords(*pos, direction);
-                if let std::collections::hash_map::Entry::Vacant(e) = area.entry(new_pos) {
-                    let mut new_cpu = this_cpu.clone();
-                    new_cpu.set_input(direction);
-                    if let OutputState::Output(state) = new_cpu.run() {
-                        e.insert(state);
-                        match state {
-                            0 => (),
-                            1 => reached_this_step.push((new_pos, new_cpu)),
-                            2 => return (steps, new_cpu),
-                            _ => panic!("Unexpected state returned: {state}"),
-                        }
-                    } else {
-                        panic!("Cpu did not return an Output");
-                    }
+/// Drives the repair droid around via its intcode program.
+pub struct Droid {
+    terminal: Terminal,
+}
+
+impl Droid {
+    pub fn new(cpu: Cpu) -> Self {
+        Self { terminal: Terminal::new(cpu, Encoding::Numeric) }
+    }
+
+    /// Every movement command and status code so far.
+    pub fn transcript(&self) -> &Transcript {
+        self.terminal.transcript()
+    }
+
+    /// Tries to move one step and returns what the droid found there. It only
+    /// actually moves if that isn't a wall.
+    pub fn try_move(&mut self, direction: Direction) -> Result<Tile, ExploreError> {
+        let command = match direction {
+            Direction::North => 1,
+            Direction::South => 2,
+            Direction::West => 3,
+            Direction::East => 4,
+        };
+        self.terminal.send(&command.to_string())?;
+        match self.terminal.read().1[..] {
+            [0] => Ok(Tile::Wall),
+            [1] => Ok(Tile::Open),
+            [2] => Ok(Tile::Oxygen),
+            [] => Err(ExploreError::Halted),
+            [s, ..] => Err(ExploreError::UnexpectedStatus(s)),
+        }
+    }
+
+    /// Maps the entire area by walking the droid through every corridor (and
+    /// back again), starting from wherever it is now.
+    pub fn explore(&mut self) -> Result<Maze, ExploreError> {
+        let mut maze = Maze::default();
+        maze.insert((0, 0), Tile::Open);
+        // The way back to the start, so we can retrace our steps.
+        let mut path: Vec<(Direction, usize)> = vec![];
+        let mut pos = (0, 0);
+        let mut next_direction = 0;
+        loop {
+            if let Some(tried) = (next_direction..4).find(|&idx| maze.get(Direction::ALL[idx].step(pos)).is_none()) {
+                let direction = Direction::ALL[tried];
+                let tile = self.try_move(direction)?;
+                maze.insert(direction.step(pos), tile);
+                if tile == Tile::Wall {
+                    next_direction = tried + 1;
+                } else {
+                    path.push((direction, tried + 1));
+                    pos = direction.step(pos);
+                    next_direction = 0;
                 }
+            } else if let Some((direction, resume)) = path.pop() {
+                self.try_move(direction.opposite())?;
+                pos = direction.opposite().step(pos);
+                next_direction = resume;
+            } else {
+                return Ok(maze);
             }
         }
-        std::mem::swap(&mut reached_this_step, &mut reached_last_step);
     }
-    unreachable!("The loop always runs and can never exit except by returning");
 }
 
-fn get_coords(old: (isize, isize), direction: isize) -> (isize, isize) {
-    match direction {
-        1 => (old.0, old.1 - 1), // N
-        2 => (old.0, old.1 + 1), // S
-        3 => (old.0 - 1, old.1), // W
-        4 => (old.0 + 1, old.1), // E
-        _ => panic!("Unexpected direction: {direction}"),
-    }
+pub fn run(input: &str) -> Result<(usize, usize), ExploreError> {
+    let cpu = Cpu::try_with_memory_from_str(input)?;
+    let maze = Droid::new(cpu).explore()?;
+    let oxygen = maze.oxygen().ok_or(ExploreError::NoOxygen)?;
+    let first = maze.distances((0, 0)).get(&oxygen).copied().ok_or(ExploreError::NoOxygen)?;
+    let second = maze.distances(oxygen).into_values().max().unwrap_or_default();
+    Ok((first, second))
 }
 
 #[cfg(test)]
@@ -91,6 +122,31 @@ mod tests {
         read_to_string(name).expect(&format!("Unable to read file: {name}")[..]).trim().to_string()
     }
 
+    #[test]
+    fn test_maze() {
+        let sample = " ##\n#..##\n#D#..#\n#.O.#\n ###\n";
+        let maze: Maze = sample.parse().unwrap();
+        assert_eq!(maze.to_string(), sample);
+        assert_eq!(maze.oxygen(), Some((1, 1)));
+        assert_eq!(maze.distances((0, 0)).get(&(1, 1)), Some(&2));
+        assert_eq!(maze.distances((1, 1)).into_values().max(), Some(4));
+        assert_eq!("#x".parse::<Maze>(), Err(ParseError('x')));
+    }
+
+    #[test]
+    fn test_transcript() {
+        use intcode_processor::ascii::replay;
+
+        let challenge_input = read_file("tests/challenge_input");
+        let cpu = Cpu::try_with_memory_from_str(&challenge_input).unwrap();
+        let mut droid = Droid::new(cpu.clone());
+        let maze = droid.explore().unwrap();
+        let transcript = droid.transcript();
+        // Every step into the unknown, and back out of every dead end.
+        assert_eq!(transcript.inputs().count(), maze.to_string().matches(['.', 'D', 'O']).count() * 2 - 2 + maze.to_string().matches('#').count());
+        assert!(replay(cpu, Encoding::Numeric, &transcript.to_string().parse().unwrap()).is_ok());
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
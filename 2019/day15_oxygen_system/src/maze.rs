@@ -0,0 +1,126 @@
+// The map the repair droid discovered, independent of how it got there.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub type Position = (isize, isize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tile { Wall, Open, Oxygen }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction { North, South, West, East }
+
+impl Direction {
+    pub const ALL: [Self; 4] = [Self::North, Self::South, Self::West, Self::East];
+
+    pub fn step(self, (x, y): Position) -> Position {
+        match self {
+            Self::North => (x, y - 1),
+            Self::South => (x, y + 1),
+            Self::West => (x - 1, y),
+            Self::East => (x + 1, y),
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::North => Self::South,
+            Self::South => Self::North,
+            Self::West => Self::East,
+            Self::East => Self::West,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError(pub char);
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unexpected character in map: {}", self.0)
+    }
+}
+
+/// The discovered area, with the droid's starting point at (0, 0).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Maze {
+    tiles: HashMap<Position, Tile>,
+}
+
+impl Maze {
+    pub fn get(&self, pos: Position) -> Option<Tile> {
+        self.tiles.get(&pos).copied()
+    }
+
+    pub fn insert(&mut self, pos: Position, tile: Tile) {
+        self.tiles.insert(pos, tile);
+    }
+
+    pub fn oxygen(&self) -> Option<Position> {
+        self.tiles.iter().find(|(_, &tile)| tile == Tile::Oxygen).map(|(&pos, _)| pos)
+    }
+
+    /// The length of the shortest path from `from` to every reachable cell.
+    pub fn distances(&self, from: Position) -> HashMap<Position, usize> {
+        let mut distances = HashMap::from([(from, 0)]);
+        let mut open = VecDeque::from([from]);
+        while let Some(pos) = open.pop_front() {
+            let dist = distances[&pos];
+            for next in Direction::ALL.map(|d| d.step(pos)) {
+                if matches!(self.get(next), Some(Tile::Open | Tile::Oxygen)) && !distances.contains_key(&next) {
+                    distances.insert(next, dist + 1);
+                    open.push_back(next);
+                }
+            }
+        }
+        distances
+    }
+}
+
+impl Display for Maze {
+    /// Draws walls as `#`, open cells as `.`, the oxygen system as `O` and the
+    /// starting point as `D`. Undiscovered cells are left blank.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (min_x, max_x) = self.tiles.keys().fold((0, 0), |(lo, hi), &(x, _)| (lo.min(x), hi.max(x)));
+        let (min_y, max_y) = self.tiles.keys().fold((0, 0), |(lo, hi), &(_, y)| (lo.min(y), hi.max(y)));
+        for y in min_y..=max_y {
+            let line: String = (min_x..=max_x).map(|x| match self.get((x, y)) {
+                Some(Tile::Wall) => '#',
+                Some(Tile::Oxygen) => 'O',
+                Some(Tile::Open) if (x, y) == (0, 0) => 'D',
+                Some(Tile::Open) => '.',
+                None => ' ',
+            }).collect();
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Maze {
+    type Err = ParseError;
+
+    /// Reads the format `Display` writes. Without a `D`, the top left corner is
+    /// taken as (0, 0).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let origin = s.lines().enumerate()
+            .find_map(|(y, line)| line.find('D').map(|x| (x as isize, y as isize)))
+            .unwrap_or((0, 0));
+        let mut tiles = HashMap::new();
+        for (y, line) in s.lines().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                let tile = match c {
+                    '#' => Tile::Wall,
+                    '.' | 'D' => Tile::Open,
+                    'O' => Tile::Oxygen,
+                    ' ' => continue,
+                    _ => return Err(ParseError(c)),
+                };
+                tiles.insert((x as isize - origin.0, y as isize - origin.1), tile);
+            }
+        }
+        Ok(Self { tiles })
+    }
+}
@@ -1,37 +1,222 @@
-use intcode_processor::intcode_processor::{Cpu, OutputState};
+use intcode_processor::ascii::{Encoding, SessionError, Stop, Terminal, Transcript};
+use intcode_processor::intcode_processor::Cpu;
 use std::collections::HashSet;
+use std::fmt::Display;
 use std::num::ParseIntError;
 
+// The robot's memory limit for the main routine and each function.
+const MAX_ROUTINE_LEN: usize = 20;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScaffoldError {
+    ParseIntError(ParseIntError),
+    NoRobot,
+    EndlessLoop,
+    NoCompression,
+    NoDust,
+    Session(SessionError),
+}
+
+impl From<ParseIntError> for ScaffoldError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl From<SessionError> for ScaffoldError {
+    fn from(value: SessionError) -> Self {
+        Self::Session(value)
+    }
+}
+
+impl Display for ScaffoldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::NoRobot => write!(f, "Camera image doesn't show the vacuum robot"),
+            Self::EndlessLoop => write!(f, "Scaffold closes into a loop, so the robot never reaches its end"),
+            Self::NoCompression => write!(f, "Scaffold path can't be split into a main routine and 3 functions of at most {MAX_ROUTINE_LEN} characters each"),
+            Self::NoDust => write!(f, "Robot halted without reporting the collected dust"),
+            Self::Session(e) => write!(f, "{e}"),
+        }
+    }
+}
+
 type Coordinates = (usize, usize);
 
-pub fn run(input: &str) -> Result<(usize, isize), ParseIntError> {
-    let mut cpu = Cpu::try_with_memory_from_str(input)?;
-    let mut cpu_2 = cpu.clone();
-    let mut image = String::new();
-    while let OutputState::Output(pixel) = cpu.run() {
-        image.push((pixel as u8) as char);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Turn {
+    Left,
+    Right,
+    /// Only at the start, if the robot already faces along the scaffold.
+    Straight,
+    /// Only at the start, if the robot faces away from the scaffold.
+    Around,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub turn: Turn,
+    pub distance: usize,
+}
+
+impl Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.turn {
+            Turn::Left => write!(f, "L,{}", self.distance),
+            Turn::Right => write!(f, "R,{}", self.distance),
+            Turn::Straight => write!(f, "{}", self.distance),
+            Turn::Around => write!(f, "L,L,{}", self.distance),
+        }
     }
-    // println!("{image}");
-    let map: HashSet<_> = image.lines().enumerate().flat_map(|(y, line)| line.chars().enumerate().filter(|(_x, c)| *c == '#').map(|(x, _c)| (x, y)).collect::<HashSet<_>>()).collect();
-    let first = map.iter().filter(|s| neighbours(**s, &map) > 2).map(|(x, y)| x*y).sum();
+}
+
+/// A main routine (indices into `functions`) and the functions it calls.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Routines<T> {
+    pub main: Vec<usize>,
+    pub functions: Vec<Vec<T>>,
+}
+
+impl<T: Display> Display for Routines<T> {
+    /// The routines as the robot expects them: The main routine, then one line
+    /// per function.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let main: Vec<_> = self.main.iter().map(|&idx| ((b'A' + idx as u8) as char).to_string()).collect();
+        writeln!(f, "{}", main.join(","))?;
+        for function in &self.functions {
+            writeln!(f, "{}", join(function))?;
+        }
+        Ok(())
+    }
+}
+
+fn join<T: Display>(tokens: &[T]) -> String {
+    tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(",")
+}
+
+/// Splits `tokens` into at most `function_count` functions and a main routine
+/// calling them, such that neither the main routine nor any function is
+/// longer than `max_len` characters when written comma-separated.
+pub fn compress<T: Display + PartialEq + Clone>(tokens: &[T], function_count: usize, max_len: usize) -> Option<Routines<T>> {
+    let mut routines = Routines { main: Vec::new(), functions: Vec::new() };
+    compress_from(tokens, function_count, max_len, &mut routines).then_some(routines)
+}
 
-    // Sorry, Part 2 only works for this specific input
-    cpu_2.set(0, 2);
-    let commands = "A,B,A,C,A,B,C,C,A,B\nR,8,L,10,R,8\nR,12,R,8,L,8,L,12\nL,12,L,10,L,8\nn\n";
-    for byte in commands.bytes() {
-        cpu_2.set_input(byte as isize);
+fn compress_from<T: Display + PartialEq + Clone>(tokens: &[T], function_count: usize, max_len: usize, routines: &mut Routines<T>) -> bool {
+    if tokens.is_empty() {
+        return true;
+    }
+    // Every call takes one character, plus a comma to separate it from the last one.
+    if 2 * routines.main.len() + 1 > max_len {
+        return false;
     }
-    let mut second = 0;
+    for idx in 0..routines.functions.len() {
+        if tokens.starts_with(&routines.functions[idx]) {
+            let len = routines.functions[idx].len();
+            routines.main.push(idx);
+            if compress_from(&tokens[len..], function_count, max_len, routines) {
+                return true;
+            }
+            routines.main.pop();
+        }
+    }
+    if routines.functions.len() < function_count {
+        let longest = (1..=tokens.len()).take_while(|&len| join(&tokens[..len]).len() <= max_len).last().unwrap_or(0);
+        for len in (1..=longest).rev() {
+            routines.main.push(routines.functions.len());
+            routines.functions.push(tokens[..len].to_vec());
+            if compress_from(&tokens[len..], function_count, max_len, routines) {
+                return true;
+            }
+            routines.functions.pop();
+            routines.main.pop();
+        }
+    }
+    false
+}
+
+/// Follows the scaffold from the robot's position, turning only where it has
+/// to and going straight across every intersection, until it hits a dead end.
+/// Fails if there is no robot, or if the scaffold closes into a loop the robot
+/// would go around forever.
+pub fn trace_path(image: &str) -> Result<Vec<Move>, ScaffoldError> {
+    let mut scaffold = HashSet::new();
+    let mut robot = None;
+    for (y, line) in image.lines().enumerate() {
+        for (x, c) in line.chars().enumerate() {
+            let pos = (x as isize, y as isize);
+            match c {
+                '#' => (),
+                '^' => robot = Some((pos, (0, -1))),
+                'v' => robot = Some((pos, (0, 1))),
+                '<' => robot = Some((pos, (-1, 0))),
+                '>' => robot = Some((pos, (1, 0))),
+                _ => continue,
+            }
+            scaffold.insert(pos);
+        }
+    }
+    let (mut pos, mut dir) = robot.ok_or(ScaffoldError::NoRobot)?;
+    let mut path = Vec::new();
+    let mut seen = HashSet::new();
     loop {
-        match cpu_2.run() {
-            OutputState::Output(_) => (),
-            OutputState::DiagnosticCode(dust) => {
-                second = dust;
-                break;
-            },
-            OutputState::Halt => panic!("Halted without returning dust"),
+        if !seen.insert((pos, dir)) {
+            return Err(ScaffoldError::EndlessLoop);
+        }
+        let left = (dir.1, -dir.0);
+        let right = (-dir.1, dir.0);
+        let back = (-dir.0, -dir.1);
+        let open = |(dx, dy): (isize, isize)| scaffold.contains(&(pos.0 + dx, pos.1 + dy));
+        let (turn, new_dir) = if path.is_empty() && open(dir) {
+            (Turn::Straight, dir)
+        } else if open(left) {
+            (Turn::Left, left)
+        } else if open(right) {
+            (Turn::Right, right)
+        } else if path.is_empty() && open(back) {
+            (Turn::Around, back)
+        } else {
+            return Ok(path);
+        };
+        dir = new_dir;
+        let mut distance = 0;
+        while scaffold.contains(&(pos.0 + dir.0, pos.1 + dir.1)) {
+            pos = (pos.0 + dir.0, pos.1 + dir.1);
+            distance += 1;
+        }
+        path.push(Move { turn, distance });
+    }
+}
+
+/// Wakes the robot up and has it follow `routines`. Returns the dust it
+/// collected, and the conversation with it.
+pub fn collect_dust(cpu: Cpu, routines: &Routines<Move>) -> Result<(isize, Transcript), ScaffoldError> {
+    let mut terminal = Terminal::new(cpu, Encoding::Ascii);
+    terminal.cpu_mut().set(0, 2);
+    terminal.read();
+    let mut values = Vec::new();
+    // Decline the continuous video feed.
+    for line in routines.to_string().lines().chain(["n"]) {
+        let stop;
+        (_, values, stop) = terminal.command(line)?;
+        if stop == Stop::Halted {
+            break;
         }
     }
+    let dust = values.last().copied().ok_or(ScaffoldError::NoDust)?;
+    Ok((dust, terminal.transcript().clone()))
+}
+
+pub fn run(input: &str) -> Result<(usize, isize), ScaffoldError> {
+    let cpu = Cpu::try_with_memory_from_str(input)?;
+    let (image, _, _) = Terminal::new(cpu.clone(), Encoding::Ascii).read();
+    let map: HashSet<_> = image.lines().enumerate().flat_map(|(y, line)| line.chars().enumerate().filter(|(_x, c)| *c == '#').map(|(x, _c)| (x, y)).collect::<HashSet<_>>()).collect();
+    let first = map.iter().filter(|s| neighbours(**s, &map) > 2).map(|(x, y)| x*y).sum();
+
+    let path = trace_path(&image)?;
+    let routines = compress(&path, 3, MAX_ROUTINE_LEN).ok_or(ScaffoldError::NoCompression)?;
+    let (second, _) = collect_dust(cpu, &routines)?;
     Ok((first, second))
 }
 
@@ -66,6 +251,54 @@ mod tests {
         read_to_string(name).expect(&format!("Unable to read file: {name}")[..]).trim().to_string()
     }
 
+    #[test]
+    fn test_sample() {
+        let sample_input = read_file("tests/sample_input");
+        let path = trace_path(&sample_input).unwrap();
+        assert_eq!(join(&path), "R,8,R,8,R,4,R,4,R,8,L,6,L,2,R,4,R,4,R,8,R,8,R,8,L,6,L,2");
+
+        // The robot may also start out facing along the scaffold, or away from it.
+        let straight = trace_path(&sample_input.replace('^', ">")).unwrap();
+        assert_eq!(join(&straight), "8,R,8,R,4,R,4,R,8,L,6,L,2,R,4,R,4,R,8,R,8,R,8,L,6,L,2");
+        let around = trace_path(&sample_input.replace('^', "<")).unwrap();
+        assert_eq!(join(&around[..2]), "L,L,8,R,8");
+        assert_eq!(trace_path("^##\n#.#\n###"), Err(ScaffoldError::EndlessLoop));
+        assert_eq!(trace_path("###"), Err(ScaffoldError::NoRobot));
+        let routines = compress(&path, 3, MAX_ROUTINE_LEN).unwrap();
+        assert_eq!(routines.to_string(), "A,B,C\nR,8,R,8,R,4,R,4,R,8\nL,6,L,2,R,4,R,4,R,8\nR,8,R,8,L,6,L,2\n");
+        let routines = compress(&path, 3, 12).unwrap();
+        assert_eq!(routines.to_string(), "A,B,C,B,A,C\nR,8,R,8\nR,4,R,4,R,8\nL,6,L,2\n");
+        assert_eq!(compress(&path, 3, 10), None);
+    }
+
+    #[test]
+    fn test_compress() {
+        let tokens: Vec<_> = "abcabcxyabcxy".chars().collect();
+        let routines = compress(&tokens, 2, 9).unwrap();
+        assert_eq!(routines.main, vec![0, 0, 1, 0, 1]);
+        assert_eq!(routines.functions, vec![vec!['a', 'b', 'c'], vec!['x', 'y']]);
+        assert_eq!(compress(&tokens, 1, 9), None);
+        assert_eq!(compress(&tokens, 2, 8), None);
+    }
+
+    #[test]
+    fn test_transcript() {
+        use intcode_processor::ascii::replay;
+
+        let challenge_input = read_file("tests/challenge_input");
+        let cpu = Cpu::try_with_memory_from_str(&challenge_input).unwrap();
+        let (image, _, _) = Terminal::new(cpu.clone(), Encoding::Ascii).read();
+        let routines = compress(&trace_path(&image).unwrap(), 3, MAX_ROUTINE_LEN).unwrap();
+        let (dust, transcript) = collect_dust(cpu.clone(), &routines).unwrap();
+        assert_eq!(dust, 833429);
+        assert_eq!(transcript.inputs().last(), Some("n"));
+        assert!(transcript.to_string().contains("| Main:\n> "));
+
+        let mut robot = cpu;
+        robot.set(0, 2);
+        assert!(replay(robot, Encoding::Ascii, &transcript.to_string().parse().unwrap()).is_ok());
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use intcode_processor::intcode_processor::{Cpu, OutputState};
+use std::fs::read_to_string;
+
+// Scans the same 50x50 grid as part 1, once per engine.
+fn scan(cpu: &Cpu, run: fn(&mut Cpu) -> OutputState) -> usize {
+    let mut pulled = 0;
+    for y in 0..50 {
+        for x in 0..50 {
+            let mut drone = cpu.clone();
+            drone.set_input(x);
+            drone.set_input(y);
+            if run(&mut drone) == OutputState::DiagnosticCode(1) {
+                pulled += 1;
+            }
+        }
+    }
+    pulled
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let input = read_to_string("tests/challenge_input").expect("Unable to read challenge input");
+    let cpu = Cpu::try_with_memory_from_str(input.trim()).expect("Unable to parse challenge input");
+    let mut group = c.benchmark_group("day19_is_pulled_grid");
+    group.bench_function("decoding", |b| b.iter(|| scan(&cpu, Cpu::run_decoding)));
+    group.bench_function("threaded", |b| b.iter(|| scan(&cpu, Cpu::run)));
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -1,35 +1,180 @@
 use intcode_processor::intcode_processor::{Cpu, OutputState};
-use std::{num::ParseIntError, collections::{HashMap, HashSet}};
+use std::num::ParseIntError;
 
-pub fn run(input: &str) -> Result<(usize, isize), ParseIntError> {
-    let cpu = Cpu::try_with_memory_from_str(input)?;
-    let first = (0..50).map(|x| (0..50).filter(|y| is_pulled(&cpu, x, *y)).count()).sum();
-    let mut x = 0;
-    let mut y = 0;
-    while !is_pulled(&cpu, x, y+99) {
-        while !is_pulled(&cpu, x, y+99) {
-            x += 1;
-        }
-        while !is_pulled(&cpu, x+99, y) {
-            y += 1;
+// Rows with no pulled cell in them (which happens close to the emitter) are
+// only searched this many times their row number to the right.
+const EMPTY_ROW_FACTOR: isize = 10;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BeamError {
+    ParseIntError(ParseIntError),
+    NoBeam,
+    UnexpectedOutput(String),
+}
+
+impl From<ParseIntError> for BeamError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl std::fmt::Display for BeamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::NoBeam => write!(f, "Unable to find the beam"),
+            Self::UnexpectedOutput(o) => write!(f, "Unexpected output of the drone system: {o}"),
         }
     }
-    let second = 10_000*x + y;
+}
 
-    Ok((first, second))
+/// The tractor beam as seen through the drone system. Since the beam is a
+/// cone, every row holds one contiguous run of pulled cells, and both of its
+/// edges never move to the left from one row to the next.
+pub struct Beam {
+    cpu: Cpu,
+    evaluations: usize,
+    // The last row we found pulled cells in, and their first and last column.
+    reference: (isize, isize, isize),
 }
 
-fn is_pulled(cpu: &Cpu, x: isize, y: isize) -> bool {
-    let mut scan = cpu.clone();
-    scan.set_input(x);
-    scan.set_input(y);
-    match scan.run() {
-        OutputState::DiagnosticCode(1) => true,
-        OutputState::DiagnosticCode(0) => false,
-        other => panic!("Unexpected Output State: {other:?}"),
+impl Beam {
+    pub fn new(cpu: Cpu) -> Self {
+        Self { cpu, evaluations: 0, reference: (0, 0, 0) }
+    }
+
+    /// How often the drone system was run so far.
+    pub fn evaluations(&self) -> usize {
+        self.evaluations
+    }
+
+    pub fn is_pulled(&mut self, x: isize, y: isize) -> Result<bool, BeamError> {
+        if x < 0 || y < 0 {
+            return Ok(false);
+        }
+        self.evaluations += 1;
+        let mut scan = self.cpu.clone();
+        scan.set_input(x);
+        scan.set_input(y);
+        match scan.run() {
+            OutputState::DiagnosticCode(1) => Ok(true),
+            OutputState::DiagnosticCode(0) => Ok(false),
+            other => Err(BeamError::UnexpectedOutput(format!("{other:?}"))),
+        }
+    }
+
+    // The first and last pulled column in row `y`, searching from the edges
+    // of some row above.
+    fn trace_row(&mut self, y: isize, (start, end): (isize, isize)) -> Result<Option<(isize, isize)>, BeamError> {
+        let mut start = start;
+        while !self.is_pulled(start, y)? {
+            start += 1;
+            if start > EMPTY_ROW_FACTOR * (y + 1) {
+                return Ok(None);
+            }
+        }
+        let mut end = if end > start && self.is_pulled(end, y)? { end } else { start };
+        while self.is_pulled(end + 1, y)? {
+            end += 1;
+        }
+        self.reference = (y, start, end);
+        Ok(Some((start, end)))
+    }
+
+    /// Follows both edges of the beam through the first `rows` rows. Returns
+    /// the edges of every row that has any pulled cells.
+    pub fn trace(&mut self, rows: isize) -> Result<Vec<(isize, isize, isize)>, BeamError> {
+        let mut edges = Vec::new();
+        let mut last = (0, 0);
+        for y in 0..rows {
+            if let Some(row) = self.trace_row(y, last)? {
+                edges.push((y, row.0, row.1));
+                last = row;
+            }
+        }
+        Ok(edges)
+    }
+
+    // Finds the smallest `x` in `lo..=hi` for which `pulled` holds, assuming
+    // it holds for `hi` and is monotonous in between.
+    fn bisect(&mut self, mut lo: isize, mut hi: isize, pulled: impl Fn(&mut Self, isize) -> Result<bool, BeamError>) -> Result<isize, BeamError> {
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if pulled(self, mid)? {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        Ok(hi)
+    }
+
+    /// The first and last pulled column in any row below the traced ones,
+    /// found by bisection from where the traced beam says its middle is.
+    fn edges(&mut self, y: isize) -> Result<(isize, isize), BeamError> {
+        let (ref_y, ref_start, ref_end) = self.reference;
+        if y <= ref_y || ref_y == 0 {
+            return self.trace_row(y, (0, 0))?.ok_or(BeamError::NoBeam);
+        }
+        let middle = (ref_start + ref_end) * y / (2 * ref_y);
+        if !self.is_pulled(middle, y)? {
+            // Very narrow beam; follow it row by row instead.
+            let mut last = (ref_start, ref_end);
+            for row in ref_y + 1..=y {
+                last = self.trace_row(row, last)?.ok_or(BeamError::NoBeam)?;
+            }
+            return Ok(last);
+        }
+        let start = self.bisect(0, middle, |beam, x| beam.is_pulled(x, y))?;
+        let mut step = 1;
+        while self.is_pulled(middle + step, y)? {
+            step *= 2;
+        }
+        let end = self.bisect(middle + step / 2, middle + step, |beam, x| beam.is_pulled(x, y).map(|p| !p))? - 1;
+        Ok((start, end))
+    }
+
+    fn fits(&mut self, y: isize, size: isize) -> Result<Option<isize>, BeamError> {
+        let (start, end) = self.edges(y)?;
+        let x = end - size + 1;
+        Ok((x >= start && self.is_pulled(x, y + size - 1)?).then_some(x))
+    }
+
+    /// The top left corner of the `size` x `size` square closest to the
+    /// emitter that fits into the beam entirely.
+    pub fn closest_square(&mut self, size: isize) -> Result<(isize, isize), BeamError> {
+        // Close to the emitter, rows may be empty, so check those one by one.
+        for (y, start, end) in self.trace(size.max(10))? {
+            let x = end - size + 1;
+            if x >= start && self.is_pulled(x, y + size - 1)? {
+                return Ok((x, y));
+            }
+        }
+        // Further down, the beam only gets wider.
+        let mut lo = self.reference.0 + 1;
+        let mut hi = lo;
+        while self.fits(hi, size)?.is_none() {
+            lo = hi + 1;
+            hi *= 2;
+        }
+        let y = self.bisect(lo, hi, |beam, y| beam.fits(y, size).map(|x| x.is_some()))?;
+        let x = self.fits(y, size)?.ok_or(BeamError::NoBeam)?;
+        Ok((x, y))
     }
 }
 
+pub fn run(input: &str) -> Result<(usize, isize), BeamError> {
+    let cpu = Cpu::try_with_memory_from_str(input)?;
+    let mut beam = Beam::new(cpu);
+    let first = beam.trace(50)?
+        .iter()
+        .filter(|(_, start, _)| *start < 50)
+        .map(|(_, start, end)| (end.min(&49) - start + 1) as usize)
+        .sum();
+    let (x, y) = beam.closest_square(100)?;
+    Ok((first, 10_000*x + y))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -44,4 +189,35 @@ mod tests {
         let challenge_input = read_file("tests/challenge_input");
         assert_eq!(run(&challenge_input), Ok((226, 7900946)));
     }
+
+    #[test]
+    fn test_beam() {
+        let challenge_input = read_file("tests/challenge_input");
+        let cpu = Cpu::try_with_memory_from_str(&challenge_input).unwrap();
+
+        // Compare the traced edges to probing every single cell.
+        let mut beam = Beam::new(cpu.clone());
+        let edges = beam.trace(30).unwrap();
+        assert!(beam.evaluations() < 30 * 10);
+        let mut probe = Beam::new(cpu.clone());
+        for y in 0..30 {
+            let pulled: Vec<_> = (0..60).filter(|&x| probe.is_pulled(x, y).unwrap()).collect();
+            let traced = edges.iter().find(|(row, _, _)| *row == y).map(|&(_, start, end)| (start..=end).collect::<Vec<_>>());
+            assert_eq!(traced.unwrap_or_default(), pulled);
+        }
+
+        for size in [1, 2, 10, 100] {
+            let mut beam = Beam::new(cpu.clone());
+            let (x, y) = beam.closest_square(size).unwrap();
+            assert!(beam.evaluations() < 1000);
+            let mut probe = Beam::new(cpu.clone());
+            for (dx, dy) in [(0, 0), (size - 1, 0), (0, size - 1), (size - 1, size - 1)] {
+                assert!(probe.is_pulled(x + dx, y + dy).unwrap());
+            }
+            // No square fits a row further up.
+            if let Some(&(row, _, end)) = probe.trace(y).unwrap().last().filter(|(row, _, _)| *row == y - 1) {
+                assert!(!probe.is_pulled(end - size + 1, row + size - 1).unwrap());
+            }
+        }
+    }
 }
@@ -1,54 +1,439 @@
-use intcode_processor::intcode_processor::{Cpu, OutputState};
+use intcode_processor::ascii::{Encoding, Terminal};
+use intcode_processor::intcode_processor::Cpu;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::num::ParseIntError;
+use std::str::FromStr;
 
-pub fn run(input: &str) -> Result<(isize, isize), ParseIntError> {
-    let mut cpu = Cpu::try_with_memory_from_str(input)?;
-    let mut cpu_2 = cpu.clone();
-    let instructions = "OR A T
-AND B T
-AND C T
-NOT T J
-AND D J
-WALK
-"; // Jump if A or B or C are false AND D is true (i. e. there is a whole in front of us and we can reach the other end)
-    for c in instructions.bytes() {
-        cpu.set_input(c as isize);
-    }
-
-    let mut first = 0;
-    loop {
-        match cpu.run() {
-            OutputState::Output(i) => print!("{}", (i as u8) as char),
-            OutputState::DiagnosticCode(i) => first = i,
-            OutputState::Halt => break,
-        }
-    }
-
-    let instructions = "OR A T
-AND B T
-AND C T
-NOT T J
-AND J T
-AND D J
-OR H T
-OR E T
-AND T J
-RUN
-"; // Jump if A or B or C are false AND D is true AND at least one of E and H are true 
-//    (i. e. there is a whole in front, we can reach the other end and then step or jump)
-    for c in instructions.bytes() {
-        cpu_2.set_input(c as isize);
-    }
-
-    let mut second = 1;
-    loop {
-        match cpu_2.run() {
-            OutputState::Output(i) => print!("{}", (i as u8) as char),
-            OutputState::DiagnosticCode(i) => second = i,
-            OutputState::Halt => break,
+const MAX_INSTRUCTIONS: usize = 15;
+// How many times we let the droid fall before giving up, how many different
+// decision tables we try to express per round, and the largest terms we try.
+const MAX_SURVEYS: usize = 100;
+const MAX_DECISION_TABLES: usize = 1000;
+const MAX_LITERALS: u32 = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpringError {
+    ParseIntError(ParseIntError),
+    InvalidRegister(String),
+    LineMalformed(String),
+    MissingMode,
+    NoReport(String),
+    NoScript(usize),
+    ReadOnlyRegister(String),
+    TooLong(usize),
+}
+
+impl From<ParseIntError> for SpringError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl Display for SpringError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::InvalidRegister(r) => write!(f, "Unknown register (or one out of sensor range): {r}"),
+            Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
+            Self::MissingMode => write!(f, "Springscript must end with WALK or RUN"),
+            Self::NoReport(v) => write!(f, "Springdroid neither fell nor reported any damage:\n{v}"),
+            Self::NoScript(n) => write!(f, "Unable to find a springscript surviving all {n} known hull patterns"),
+            Self::ReadOnlyRegister(r) => write!(f, "Register {r} can't be written to"),
+            Self::TooLong(n) => write!(f, "Springscript may have at most {MAX_INSTRUCTIONS} instructions, but has {n}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode { Walk, Run }
+
+impl Mode {
+    /// How many tiles ahead the sensors reach.
+    pub fn range(self) -> usize {
+        match self {
+            Self::Walk => 4,
+            Self::Run => 9,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    // Ground at distance n+1
+    Sensor(u8),
+    T,
+    J,
+}
+
+impl Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sensor(n) => write!(f, "{}", (b'A' + n) as char),
+            Self::T => write!(f, "T"),
+            Self::J => write!(f, "J"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op { And, Or, Not }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub op: Op,
+    pub x: Register,
+    pub y: Register,
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self.op {
+            Op::And => "AND",
+            Op::Or => "OR",
+            Op::Not => "NOT",
+        };
+        write!(f, "{op} {} {}", self.x, self.y)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Script {
+    pub instructions: Vec<Instruction>,
+    pub mode: Mode,
+}
+
+impl FromStr for Script {
+    type Err = SpringError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines: Vec<_> = s.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+        let mode = match lines.last() {
+            Some(&"WALK") => Mode::Walk,
+            Some(&"RUN") => Mode::Run,
+            _ => return Err(SpringError::MissingMode),
+        };
+        let register = |name: &str| match name.as_bytes() {
+            b"T" => Ok(Register::T),
+            b"J" => Ok(Register::J),
+            [c @ b'A'..=b'I'] if ((c - b'A') as usize) < mode.range() => Ok(Register::Sensor(c - b'A')),
+            _ => Err(SpringError::InvalidRegister(name.to_string())),
+        };
+        let instructions = lines[..lines.len()-1].iter().map(|line| {
+            let components: Vec<_> = line.split_whitespace().collect();
+            let [op, x, y] = components[..] else {
+                return Err(SpringError::LineMalformed(line.to_string()));
+            };
+            let op = match op {
+                "AND" => Op::And,
+                "OR" => Op::Or,
+                "NOT" => Op::Not,
+                _ => return Err(SpringError::LineMalformed(line.to_string())),
+            };
+            let y_reg = register(y)?;
+            if matches!(y_reg, Register::Sensor(_)) {
+                return Err(SpringError::ReadOnlyRegister(y.to_string()));
+            }
+            Ok(Instruction { op, x: register(x)?, y: y_reg })
+        }).collect::<Result<Vec<_>, _>>()?;
+        if instructions.len() > MAX_INSTRUCTIONS {
+            return Err(SpringError::TooLong(instructions.len()));
+        }
+        Ok(Self { instructions, mode })
+    }
+}
+
+impl Display for Script {
+    /// The script in the form the springdroid expects as input.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for instruction in &self.instructions {
+            writeln!(f, "{instruction}")?;
+        }
+        match self.mode {
+            Mode::Walk => writeln!(f, "WALK"),
+            Mode::Run => writeln!(f, "RUN"),
+        }
+    }
+}
+
+// A sensor reading has bit n set if there is ground at distance n+1.
+fn reading(hull: &[bool], pos: usize, range: usize) -> u16 {
+    (0..range).filter(|d| hull.get(pos + 1 + d).copied().unwrap_or(true)).fold(0, |acc, d| acc | 1 << d)
+}
+
+fn lands_on(hull: &[bool], pos: usize, jump: bool) -> Option<usize> {
+    let next = pos + if jump { 4 } else { 1 };
+    hull.get(next).copied().unwrap_or(true).then_some(next)
+}
+
+impl Script {
+    /// Evaluates the script for one sensor reading (bit n set means there is
+    /// ground at distance n+1). Both writable registers start out false.
+    pub fn jumps(&self, reading: u16) -> bool {
+        let (mut t, mut j) = (false, false);
+        for instruction in &self.instructions {
+            let x = match instruction.x {
+                Register::Sensor(n) => reading & 1 << n != 0,
+                Register::T => t,
+                Register::J => j,
+            };
+            let y = if instruction.y == Register::T { &mut t } else { &mut j };
+            *y = match instruction.op {
+                Op::And => x && *y,
+                Op::Or => x || *y,
+                Op::Not => !x,
+            };
+        }
+        j
+    }
+
+    /// Walks the droid across `hull` (`true` is ground), starting on the
+    /// first tile. Returns where it falls, if it does.
+    pub fn fall_position(&self, hull: &[bool]) -> Option<usize> {
+        let mut pos = 0;
+        while pos < hull.len() {
+            let jump = self.jumps(reading(hull, pos, self.mode.range()));
+            match lands_on(hull, pos, jump) {
+                Some(next) => pos = next,
+                None => return Some(pos + if jump { 4 } else { 1 }),
+            }
+        }
+        None
+    }
+}
+
+// Extracts the hull the droid fell on from its last moments.
+fn parse_hull(output: &str) -> Option<Vec<bool>> {
+    let (_, frames) = output.split_once("Didn't make it across:")?;
+    let first_frame = frames.trim().split("\n\n").next()?;
+    let hull = first_frame.lines().last()?;
+    Some(hull.chars().map(|c| c != '.').collect())
+}
+
+// Feeds `script` to the droid. Returns the terminal it was typed on, what the
+// droid printed, and the damage it reported if it made it across.
+fn submit(cpu: &Cpu, script: &Script) -> (Terminal, String, Option<isize>) {
+    let mut terminal = Terminal::new(cpu.clone(), Encoding::Ascii);
+    terminal.read();
+    let (mut output, mut damage) = (String::new(), None);
+    for line in script.to_string().lines() {
+        // Only numeric input can be rejected.
+        if let Ok((text, values, _)) = terminal.command(line) {
+            output.push_str(&text);
+            damage = damage.or(values.first().copied());
+        }
+    }
+    (terminal, output, damage)
+}
+
+fn survey(cpu: &Cpu, script: &Script) -> Result<isize, Vec<bool>> {
+    let (_, output, damage) = submit(cpu, script);
+    damage.ok_or_else(|| parse_hull(&output).unwrap_or_default())
+}
+
+// Enumerates decision tables (sensor reading -> jump) under which the droid
+// survives all `patterns`, and calls `visit` on them until it returns true.
+fn decisions(patterns: &[Vec<bool>], range: usize, idx: usize, pos: usize, table: &mut HashMap<u16, bool>, visit: &mut dyn FnMut(&HashMap<u16, bool>) -> bool) -> bool {
+    let Some(hull) = patterns.get(idx) else {
+        return visit(table);
+    };
+    if pos >= hull.len() {
+        return decisions(patterns, range, idx + 1, 0, table, visit);
+    }
+    let reading = reading(hull, pos, range);
+    let fresh = !table.contains_key(&reading);
+    let options = match table.get(&reading) {
+        Some(&jump) => vec![jump],
+        None => vec![false, true],
+    };
+    for jump in options {
+        if let Some(next) = lands_on(hull, pos, jump) {
+            table.insert(reading, jump);
+            if decisions(patterns, range, idx, next, table, visit) {
+                return true;
+            }
         }
     }
+    if fresh {
+        table.remove(&reading);
+    }
+    false
+}
+
+// A conjunction of literals: sensor n is required to read `value` bit n for
+// every bit n in `care`.
+#[derive(Clone, Copy)]
+struct Cube {
+    care: u16,
+    value: u16,
+}
 
+impl Cube {
+    fn contains(&self, reading: u16) -> bool {
+        reading & self.care == self.value
+    }
+
+    fn literals(&self, range: usize) -> (Vec<Register>, Vec<Register>) {
+        let sensors = (0..range as u8).filter(|n| self.care & 1 << n != 0);
+        sensors.map(Register::Sensor).partition(|r| matches!(r, Register::Sensor(n) if self.value & 1 << n != 0))
+    }
+}
+
+fn cubes(range: usize) -> Vec<Cube> {
+    (0_u16..1 << range)
+        .filter(|care| care.count_ones() <= MAX_LITERALS)
+        .flat_map(|care| {
+            // All subsets of `care` as values.
+            let mut values = vec![0];
+            let mut value = 0_u16;
+            while value != care {
+                value = value.wrapping_sub(care) & care;
+                values.push(value);
+            }
+            values.into_iter().map(move |value| Cube { care, value })
+        })
+        .collect()
+}
+
+// Emits `dest = (l_1 inner l_2 inner ...)`, where the literals are `positive`
+// sensors and negations of `negative` sensors, and `inner` is AND for a term
+// or OR for a clause. The code never depends on the previous value of `dest`.
+fn emit(dest: Register, positive: &[Register], negative: &[Register], inner: Op) -> Vec<Instruction> {
+    let outer = if inner == Op::And { Op::Or } else { Op::And };
+    let ins = |op, x| Instruction { op, x, y: dest };
+    let mut code = Vec::new();
+    match (positive, negative) {
+        ([], []) => {
+            // Constant: A & !A or A | !A
+            code.push(ins(Op::Not, Register::Sensor(0)));
+            code.push(ins(outer, Register::Sensor(0)));
+        },
+        (_, [n]) => code.push(ins(Op::Not, *n)),
+        ([first, ..], []) => {
+            code.push(ins(Op::Not, *first));
+            code.push(ins(Op::Not, dest));
+        },
+        (_, [first, rest @ ..]) => {
+            // !a & !b & ... == !(a | b | ...) and vice versa
+            code.push(ins(Op::Not, *first));
+            code.push(ins(Op::Not, dest));
+            code.extend(rest.iter().map(|&r| ins(outer, r)));
+            code.push(ins(Op::Not, dest));
+        },
+    }
+    let skip = usize::from(negative.is_empty());
+    code.extend(positive.iter().skip(skip).map(|&r| ins(inner, r)));
+    code
+}
+
+// Covers all of `points` with cubes that avoid all of `avoid`, and compiles
+// the result into a sum of products (inner AND, outer OR) or a product of
+// sums (where each cube describes the negation of a clause).
+fn compile(points: &[u16], avoid: &[u16], range: usize, inner: Op) -> Option<Vec<Instruction>> {
+    let outer = if inner == Op::And { Op::Or } else { Op::And };
+    let literals = |cube: &Cube| {
+        let (positive, negative) = cube.literals(range);
+        if inner == Op::And { (positive, negative) } else { (negative, positive) }
+    };
+    // Cost of adding a cube that is computed in T and then merged into J.
+    let cost = |cube: &Cube| match literals(cube) {
+        (p, n) if p.len() == 1 && n.is_empty() => 1,
+        (p, n) => emit(Register::T, &p, &n, inner).len() + 1,
+    };
+    let candidates: Vec<_> = cubes(range)
+        .into_iter()
+        .filter(|cube| !avoid.iter().any(|&r| cube.contains(r)) && points.iter().any(|&r| cube.contains(r)))
+        .map(|cube| (cost(&cube), cube))
+        .collect();
+
+    fn cover(points: &[u16], candidates: &[(usize, Cube)], chosen: &mut Vec<Cube>, cost: usize, best: &mut Option<(usize, Vec<Cube>)>) {
+        if best.as_ref().is_some_and(|(b, _)| cost >= *b) {
+            return;
+        }
+        let Some(&point) = points.iter().find(|&&p| !chosen.iter().any(|c| c.contains(p))) else {
+            *best = Some((cost, chosen.clone()));
+            return;
+        };
+        for &(c, cube) in candidates.iter().filter(|(_, cube)| cube.contains(point)) {
+            chosen.push(cube);
+            cover(points, candidates, chosen, cost + c, best);
+            chosen.pop();
+        }
+    }
+    let mut best = None;
+    cover(points, &candidates, &mut Vec::new(), 0, &mut best);
+    let (_, mut chosen) = best?;
+
+    // The first cube goes straight into J, so pick the one that saves most.
+    let direct = |cube: &Cube| {
+        let (p, n) = literals(cube);
+        emit(Register::J, &p, &n, inner).len()
+    };
+    if let Some(idx) = (0..chosen.len()).max_by_key(|&idx| cost(&chosen[idx]) as isize - direct(&chosen[idx]) as isize) {
+        chosen.swap(0, idx);
+    }
+    let mut code = Vec::new();
+    for (idx, cube) in chosen.iter().enumerate() {
+        let (p, n) = literals(cube);
+        if idx == 0 {
+            code.extend(emit(Register::J, &p, &n, inner));
+        } else if p.len() == 1 && n.is_empty() {
+            code.push(Instruction { op: outer, x: p[0], y: Register::J });
+        } else {
+            code.extend(emit(Register::T, &p, &n, inner));
+            code.push(Instruction { op: outer, x: Register::T, y: Register::J });
+        }
+    }
+    Some(code)
+}
+
+/// Finds a springscript of at most 15 instructions under which the droid
+/// survives every one of `patterns` (each a hull starting at the droid's
+/// position, with `true` for ground).
+pub fn synthesize(patterns: &[Vec<bool>], mode: Mode) -> Option<Script> {
+    let range = mode.range();
+    let mut attempts = 0;
+    let mut found = None;
+    decisions(patterns, range, 0, 0, &mut HashMap::new(), &mut |table| {
+        attempts += 1;
+        let (jump, stay): (Vec<_>, Vec<_>) = table.iter().partition(|(_, &jump)| jump);
+        let jump: Vec<_> = jump.into_iter().map(|(&r, _)| r).collect();
+        let stay: Vec<_> = stay.into_iter().map(|(&r, _)| r).collect();
+        let instructions = if jump.is_empty() {
+            Some(Vec::new())
+        } else {
+            [compile(&jump, &stay, range, Op::And), compile(&stay, &jump, range, Op::Or)]
+                .into_iter()
+                .flatten()
+                .min_by_key(|code| code.len())
+        };
+        if let Some(instructions) = instructions.filter(|code| code.len() <= MAX_INSTRUCTIONS) {
+            found = Some(Script { instructions, mode });
+        }
+        found.is_some() || attempts >= MAX_DECISION_TABLES
+    });
+    found
+}
+
+/// Lets the droid fall into as many holes as it takes to come up with a
+/// script that gets it across, and returns the reported damage and the script.
+pub fn explore(cpu: &Cpu, mode: Mode) -> Result<(isize, Script), SpringError> {
+    let mut patterns = Vec::new();
+    for _ in 0..MAX_SURVEYS {
+        let script = synthesize(&patterns, mode).ok_or(SpringError::NoScript(patterns.len()))?;
+        match survey(cpu, &script) {
+            Ok(damage) => return Ok((damage, script)),
+            Err(hull) if hull.is_empty() || patterns.contains(&hull) => return Err(SpringError::NoReport(script.to_string())),
+            Err(hull) => patterns.push(hull),
+        }
+    }
+    Err(SpringError::NoScript(patterns.len()))
+}
+
+pub fn run(input: &str) -> Result<(isize, isize), SpringError> {
+    let cpu = Cpu::try_with_memory_from_str(input)?;
+    let (first, _) = explore(&cpu, Mode::Walk)?;
+    let (second, _) = explore(&cpu, Mode::Run)?;
     Ok((first, second))
 }
 
@@ -61,6 +446,63 @@ mod tests {
         read_to_string(name).expect(&format!("Unable to read file: {name}")[..]).trim().to_string()
     }
 
+    fn hull(s: &str) -> Vec<bool> {
+        s.chars().map(|c| c == '#').collect()
+    }
+
+    #[test]
+    fn test_script() {
+        let script: Script = "NOT D J\nWALK\n".parse().unwrap();
+        assert_eq!(script.to_string(), "NOT D J\nWALK\n");
+        assert_eq!(script.fall_position(&hull("#####.###########")), Some(5));
+
+        let script: Script = "OR A T\nAND B T\nAND C T\nNOT T J\nAND D J\nWALK".parse().unwrap();
+        assert_eq!(script.fall_position(&hull("#####.###########")), None);
+        assert_eq!(script.fall_position(&hull("#####.#..########")), None);
+        assert_eq!(script.fall_position(&hull("#####.#.##.#.####")), Some(7));
+
+        assert_eq!("NOT E J\nWALK".parse::<Script>(), Err(SpringError::InvalidRegister("E".to_string())));
+        assert_eq!("NOT A B\nRUN".parse::<Script>(), Err(SpringError::ReadOnlyRegister("B".to_string())));
+        assert_eq!("NOT A J".parse::<Script>(), Err(SpringError::MissingMode));
+        assert_eq!("XOR A J\nRUN".parse::<Script>(), Err(SpringError::LineMalformed("XOR A J".to_string())));
+        assert_eq!("NOT A J\n".repeat(16).parse::<Script>().err(), Some(SpringError::MissingMode));
+        assert_eq!(format!("{}RUN", "NOT A J\n".repeat(16)).parse::<Script>(), Err(SpringError::TooLong(16)));
+    }
+
+    #[test]
+    fn test_synthesize() {
+        let patterns = [
+            hull("#####.###########"),
+            hull("#####..#.########"),
+            hull("#####...#########"),
+        ];
+        for mode in [Mode::Walk, Mode::Run] {
+            let script = synthesize(&patterns, mode).unwrap();
+            assert!(script.instructions.len() <= MAX_INSTRUCTIONS);
+            assert!(patterns.iter().all(|p| script.fall_position(p).is_none()));
+        }
+        // Both hulls show the droid the same four tiles at some point, but
+        // only one of them wants it to jump there.
+        let patterns = [hull("######.#.#...####"), hull("#####.#...#..####")];
+        assert_eq!(synthesize(&patterns, Mode::Walk), None);
+        let script = synthesize(&patterns, Mode::Run).unwrap();
+        assert!(patterns.iter().all(|p| script.fall_position(p).is_none()));
+    }
+
+    #[test]
+    fn test_transcript() {
+        use intcode_processor::ascii::replay;
+
+        let challenge_input = read_file("tests/challenge_input");
+        let cpu = Cpu::try_with_memory_from_str(&challenge_input).unwrap();
+        let (damage, script) = explore(&cpu, Mode::Walk).unwrap();
+        let (terminal, _, _) = submit(&cpu, &script);
+        let transcript = terminal.transcript();
+        assert_eq!(transcript.inputs().collect::<Vec<_>>(), script.to_string().lines().collect::<Vec<_>>());
+        assert!(transcript.to_string().ends_with(&format!("= {damage}\n")));
+        assert!(replay(cpu, Encoding::Ascii, &transcript.to_string().parse().unwrap()).is_ok());
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
@@ -0,0 +1,11 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::fs::read_to_string;
+use day23_category_six::run;
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let input = read_to_string("tests/challenge_input").expect("Unable to read challenge input");
+    c.bench_function("day23_category_six", |b| b.iter(|| run(input.trim())));
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
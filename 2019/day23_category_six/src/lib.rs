@@ -1,60 +1,69 @@
-use intcode_processor::intcode_processor::{Cpu, OutputState};
-use std::{num::ParseIntError, collections::VecDeque};
-
-pub fn run(input: &str) -> Result<(isize, isize), ParseIntError> {
-    let program = Cpu::try_with_memory_from_str(input)?;
-    let mut cpus = Vec::new();
-    for addr in 0..50 {
-        let mut cpu = program.clone();
-        cpu.set_input(addr);
-        cpu.set_input(-1);
-        cpus.push(cpu);
+use intcode_processor::intcode_processor::Cpu;
+
+mod network;
+pub use network::{Nat, Network, NetworkError, Packet};
+
+const NICS: usize = 50;
+const NAT_ADDRESS: usize = 255;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    ParseIntError(std::num::ParseIntError),
+}
+
+/// Anything that can go wrong from reading the program to running the network.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Parse(ParseError),
+    Network(NetworkError),
+}
+
+impl From<std::num::ParseIntError> for ParseError {
+    fn from(value: std::num::ParseIntError) -> Self {
+        Self::ParseIntError(value)
     }
-    let mut first = 0;
-    let mut nat = (0, 0);
-    let mut last = 0;
-    let mut messages = vec![VecDeque::new(); 50];
-    loop {
-        let mut halted = 0;
-        for (id, cpu) in cpus.iter_mut().enumerate() {
-            while let Some((x, y)) = messages[id].pop_front() {
-                cpu.set_input(x);
-                cpu.set_input(y);
-            }
-            cpu.set_input(-1);
-            match cpu.run() {
-                OutputState::Halt => halted += 1,
-                OutputState::DiagnosticCode(i) => eprintln!("CPU {id} stopped with DiagnosticCode {i}"),
-                OutputState::Output(i) => {
-                    let x = match cpu.run() {
-                        OutputState::Halt => panic!("Received Halt instead of X"),
-                        OutputState::DiagnosticCode(i) => panic!("CPU {id} stopped with DiagnosticCode {i}"),
-                        OutputState::Output(i) => i,
-                    };
-                    let y = match cpu.run() {
-                        OutputState::Halt => panic!("Received Halt instead of Y"),
-                        OutputState::DiagnosticCode(i) => panic!("CPU {id} stopped with DiagnosticCode {i}"),
-                        OutputState::Output(i) => i,
-                    };
-                    if i == 255 {
-                        if first == 0 {
-                            first = y;
-                        }
-                        nat = (x, y);
-                    } else {
-                        messages[i as usize].push_back((x, y));
-                    }
-                }
-            }
+}
+
+impl From<ParseError> for Error {
+    fn from(value: ParseError) -> Self {
+        Self::Parse(value)
+    }
+}
+
+impl From<NetworkError> for Error {
+    fn from(value: NetworkError) -> Self {
+        Self::Network(value)
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
         }
-        if halted == 50 {
-            if last == nat.1 {
-                return Ok((first, last));
-            } else {
-                last = nat.1;
-                messages[0].push_back(nat);
-            }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "{e}"),
+            Self::Network(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+pub fn run(input: &str) -> Result<(isize, isize), Error> {
+    let program = Cpu::try_with_memory_from_str(input).map_err(ParseError::from)?;
+    let mut network = Network::new(&program, NICS, Some(Nat::new(NAT_ADDRESS, 0)));
+    let mut last = network.wake()?;
+    let first = network.log().iter().find(|p| p.to == NAT_ADDRESS).map(|p| p.y).ok_or(NetworkError::NothingForNat)?;
+    loop {
+        let packet = network.wake()?;
+        if packet.y == last.y {
+            return Ok((first, packet.y));
         }
+        last = packet;
     }
 }
 
@@ -72,4 +81,32 @@ mod tests {
         let challenge_input = read_file("tests/challenge_input");
         assert_eq!(run(&challenge_input), Ok((17949, 12326)));
     }
+
+    #[test]
+    fn test_network() {
+        let challenge_input = read_file("tests/challenge_input");
+        let program = Cpu::try_with_memory_from_str(&challenge_input).unwrap();
+
+        // Without a NAT, the first packet for 255 has nowhere to go.
+        let mut network = Network::new(&program, NICS, None);
+        let err = loop {
+            if let Err(e) = network.step() {
+                break e;
+            }
+        };
+        assert!(matches!(err, NetworkError::UnknownAddress(Packet { to: 255, y: 17949, .. })));
+
+        let mut network = Network::new(&program, NICS, Some(Nat::new(NAT_ADDRESS, 0)));
+        let packet = network.wake().unwrap();
+        assert_eq!((packet.from, packet.to), (NAT_ADDRESS, 0));
+        assert_eq!(network.log().last(), Some(&packet));
+        assert!(!network.is_idle());
+        assert_eq!(network.nat().unwrap().sent(), [packet]);
+
+        // NICs that only ever listen leave the NAT with nothing to send.
+        let listener = Cpu::try_with_memory_from_str("3,5,1105,1,0,0").unwrap();
+        let mut network = Network::new(&listener, 2, Some(Nat::new(NAT_ADDRESS, 0)));
+        assert_eq!(network.wake(), Err(NetworkError::NothingForNat));
+        assert_eq!(Network::new(&listener, 2, None).wake(), Err(NetworkError::Stalled));
+    }
 }
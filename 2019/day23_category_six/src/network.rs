@@ -0,0 +1,186 @@
+// A network of NICs running copies of the same intcode program, each of them
+// with an input queue of packets and a router between them. Addresses beyond
+// the NICs may be taken by a NAT, which stores the last packet sent to it and
+// wakes up the network with it once it goes idle.
+
+use intcode_processor::intcode_processor::{Cpu, OutputState};
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+type RegVal = isize;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NetworkError {
+    Halted(usize),
+    IncompletePacket(usize),
+    NothingForNat,
+    Stalled,
+    UnknownAddress(Packet),
+}
+
+impl Display for NetworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Halted(addr) => write!(f, "NIC {addr} halted"),
+            Self::IncompletePacket(addr) => write!(f, "NIC {addr} asked for input in the middle of sending a packet"),
+            Self::NothingForNat => write!(f, "NAT woke the network up without having received anything"),
+            Self::Stalled => write!(f, "Network is idle, but there is no NAT to wake it up"),
+            Self::UnknownAddress(p) => write!(f, "Packet sent to unknown address: {p}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub round: usize,
+    pub from: usize,
+    pub to: usize,
+    pub x: RegVal,
+    pub y: RegVal,
+}
+
+impl Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {} -> {}: ({}, {})", self.round, self.from, self.to, self.x, self.y)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nat {
+    pub address: usize,
+    /// Where the stored packet is sent once the network is idle.
+    pub wake: usize,
+    last: Option<Packet>,
+    sent: Vec<Packet>,
+}
+
+impl Nat {
+    pub fn new(address: usize, wake: usize) -> Self {
+        Self { address, wake, last: None, sent: Vec::new() }
+    }
+
+    /// The packets the NAT sent to wake up the network, in order.
+    pub fn sent(&self) -> &[Packet] {
+        &self.sent
+    }
+}
+
+struct Nic {
+    cpu: Cpu,
+    outgoing: Vec<RegVal>,
+    // Whether the NIC has only been polling an empty queue since the last
+    // time it sent or received anything.
+    starved: bool,
+}
+
+pub struct Network {
+    nics: Vec<Nic>,
+    queues: Vec<VecDeque<(RegVal, RegVal)>>,
+    nat: Option<Nat>,
+    log: Vec<Packet>,
+    round: usize,
+}
+
+impl Network {
+    /// Boots `size` NICs, handing each its address as the first input.
+    pub fn new(program: &Cpu, size: usize, nat: Option<Nat>) -> Self {
+        let nics = (0..size).map(|addr| {
+            let mut cpu = program.clone();
+            cpu.set_input(addr as RegVal);
+            Nic { cpu, outgoing: Vec::new(), starved: false }
+        }).collect();
+        Self {
+            nics,
+            queues: vec![VecDeque::new(); size],
+            nat,
+            log: Vec::new(),
+            round: 0,
+        }
+    }
+
+    pub fn nat(&self) -> Option<&Nat> {
+        self.nat.as_ref()
+    }
+
+    /// Every packet that was sent so far, including those of the NAT.
+    pub fn log(&self) -> &[Packet] {
+        &self.log
+    }
+
+    pub fn round(&self) -> usize {
+        self.round
+    }
+
+    /// True if no packets are waiting anywhere, and every NIC is blocked on
+    /// input after being told that its queue is empty.
+    pub fn is_idle(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty) && self.nics.iter().all(|nic| nic.starved)
+    }
+
+    fn route(&mut self, packet: Packet) -> Result<(), NetworkError> {
+        self.log.push(packet);
+        match &mut self.nat {
+            Some(nat) if nat.address == packet.to => nat.last = Some(packet),
+            _ => self.queues.get_mut(packet.to).ok_or(NetworkError::UnknownAddress(packet))?.push_back((packet.x, packet.y)),
+        }
+        Ok(())
+    }
+
+    // Runs NIC `addr` until it waits for input again, and routes whatever it sent.
+    fn run_nic(&mut self, addr: usize) -> Result<bool, NetworkError> {
+        let mut sent = false;
+        loop {
+            let nic = &mut self.nics[addr];
+            match nic.cpu.run() {
+                OutputState::Output(v) => nic.outgoing.push(v),
+                OutputState::DiagnosticCode(_) => return Err(NetworkError::Halted(addr)),
+                OutputState::Halt if !nic.cpu.awaits_input() => return Err(NetworkError::Halted(addr)),
+                OutputState::Halt if !nic.outgoing.is_empty() => return Err(NetworkError::IncompletePacket(addr)),
+                OutputState::Halt => return Ok(sent),
+            }
+            if let [to, x, y] = nic.outgoing[..] {
+                nic.outgoing.clear();
+                sent = true;
+                self.route(Packet { round: self.round, from: addr, to: to as usize, x, y })?;
+            }
+        }
+    }
+
+    /// Lets every NIC process (at most) one packet from its queue, or poll
+    /// its empty queue once.
+    pub fn step(&mut self) -> Result<(), NetworkError> {
+        self.round += 1;
+        for addr in 0..self.nics.len() {
+            let received = match self.queues[addr].pop_front() {
+                Some((x, y)) => {
+                    self.nics[addr].cpu.set_input(x);
+                    self.nics[addr].cpu.set_input(y);
+                    true
+                },
+                None => {
+                    self.nics[addr].cpu.set_input(-1);
+                    false
+                },
+            };
+            let sent = self.run_nic(addr)?;
+            self.nics[addr].starved = !received && !sent;
+        }
+        Ok(())
+    }
+
+    /// Runs until the network is idle, and lets the NAT wake it up with the
+    /// packet stored last. Returns that packet.
+    pub fn wake(&mut self) -> Result<Packet, NetworkError> {
+        loop {
+            self.step()?;
+            if self.is_idle() {
+                let nat = self.nat.as_mut().ok_or(NetworkError::Stalled)?;
+                let last = nat.last.ok_or(NetworkError::NothingForNat)?;
+                let packet = Packet { round: self.round, from: nat.address, to: nat.wake, x: last.x, y: last.y };
+                nat.sent.push(packet);
+                self.route(packet)?;
+                return Ok(packet);
+            }
+        }
+    }
+}
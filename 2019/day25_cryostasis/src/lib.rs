@@ -1,20 +1,358 @@
+use intcode_processor::ascii::{Encoding, Terminal};
 use intcode_processor::intcode_processor::{Cpu, OutputState};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Display;
 use std::num::ParseIntError;
 
-pub fn run(input: &str) -> Result<(), ParseIntError> {
-    let mut cpu = Cpu::try_with_memory_from_str(input)?;
-    loop {
-        match cpu.run() {
-            OutputState::Output(i) => { print!("{}", (i as u8) as char); continue; },
-            OutputState::DiagnosticCode(i) => println!("{}", (i as u8) as char),
-            OutputState::Halt => (),
+const PROMPT: &str = "Command?\n";
+// Taking the infinite loop makes the droid print forever, so anything longer
+// than this without a prompt counts as a runaway.
+const MAX_OUTPUT: usize = 10_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AdventureError {
+    ParseIntError(ParseIntError),
+    GameOver(String),
+    Io(String),
+    NoCheckpoint,
+    NoCombination,
+    NoPassword(String),
+    NoRoom(String),
+    Runaway,
+}
+
+impl From<ParseIntError> for AdventureError {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl Display for AdventureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::GameOver(v) => write!(f, "Game ended unexpectedly:\n{v}"),
+            Self::Io(e) => write!(f, "Unable to talk to the player: {e}"),
+            Self::NoCheckpoint => write!(f, "Unable to find the pressure-sensitive floor"),
+            Self::NoCombination => write!(f, "No combination of items gets us past the pressure-sensitive floor"),
+            Self::NoPassword(v) => write!(f, "Unable to find the password in:\n{v}"),
+            Self::NoRoom(v) => write!(f, "Unable to find a room description in:\n{v}"),
+            Self::Runaway => write!(f, "Droid kept on talking without ever asking for a command"),
         }
-        let mut read = String::new();
-        std::io::stdin().read_line(&mut read).expect("Unable to read input");
-        read.chars().for_each(|c| cpu.set_input(c as isize));
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction { North, East, South, West }
+
+impl Direction {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "north" => Some(Self::North),
+            "east" => Some(Self::East),
+            "south" => Some(Self::South),
+            "west" => Some(Self::West),
+            _ => None,
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::North => Self::South,
+            Self::East => Self::West,
+            Self::South => Self::North,
+            Self::West => Self::East,
+        }
+    }
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::North => write!(f, "north"),
+            Self::East => write!(f, "east"),
+            Self::South => write!(f, "south"),
+            Self::West => write!(f, "west"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Room {
+    pub name: String,
+    pub description: String,
+    pub doors: Vec<Direction>,
+    pub items: Vec<String>,
+}
+
+impl Room {
+    /// Parses the last room description in some output of the droid (moving
+    /// onto the pressure-sensitive floor shows two rooms in one go).
+    pub fn parse(output: &str) -> Option<Self> {
+        let lines: Vec<_> = output.lines().collect();
+        let header = lines.iter().rposition(|l| l.starts_with("== ") && l.ends_with(" =="))?;
+        let name = lines[header].trim_matches(|c| c == '=' || c == ' ').to_string();
+        let description = lines.get(header + 1).unwrap_or(&"").to_string();
+        let list = |title: &str| -> Vec<&str> {
+            lines[header..]
+                .iter()
+                .skip_while(|l| **l != title)
+                .skip(1)
+                .map_while(|l| l.strip_prefix("- "))
+                .collect()
+        };
+        let doors = list("Doors here lead:").into_iter().filter_map(Direction::parse).collect();
+        let items = list("Items here:").into_iter().map(String::from).collect();
+        Some(Self { name, description, doors, items })
+    }
+}
+
+enum Reply {
+    Prompt(String),
+    Ended(String),
+}
+
+// The droid, fed one command at a time. We only ever give it input once it
+// asked for a command, so it never reads from an empty queue.
+#[derive(Clone)]
+struct Droid {
+    cpu: Cpu,
+}
+
+impl Droid {
+    fn read(&mut self) -> Result<Reply, AdventureError> {
+        let mut output = String::new();
+        loop {
+            match self.cpu.run() {
+                OutputState::Output(i) => {
+                    output.push((i as u8) as char);
+                    if output.ends_with(PROMPT) {
+                        return Ok(Reply::Prompt(output));
+                    }
+                    if output.len() > MAX_OUTPUT {
+                        return Err(AdventureError::Runaway);
+                    }
+                },
+                OutputState::DiagnosticCode(i) => {
+                    output.push((i as u8) as char);
+                    return Ok(Reply::Ended(output));
+                },
+                OutputState::Halt => return Ok(Reply::Ended(output)),
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Result<Reply, AdventureError> {
+        command.bytes().for_each(|b| self.cpu.set_input(b as isize));
+        self.cpu.set_input(b'\n' as isize);
+        self.read()
+    }
+
+    fn prompt(&mut self, command: &str) -> Result<String, AdventureError> {
+        match self.command(command)? {
+            Reply::Prompt(output) => Ok(output),
+            Reply::Ended(output) => Err(AdventureError::GameOver(output)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Weight { TooLight, TooHeavy }
+
+/// Maps the ship, collects every item that doesn't kill or trap the droid and
+/// finds out which of them get it past the pressure-sensitive floor.
+pub struct Explorer {
+    droid: Droid,
+    location: String,
+    rooms: HashMap<String, Room>,
+    // Where each door leads to, once we went through it.
+    map: HashMap<(String, Direction), String>,
+    // The room and door leading onto the pressure-sensitive floor.
+    checkpoint: Option<(String, Direction)>,
+    inventory: Vec<String>,
+    deadly: Vec<String>,
+}
+
+impl Explorer {
+    pub fn try_new(input: &str) -> Result<Self, AdventureError> {
+        let mut droid = Droid { cpu: Cpu::try_with_memory_from_str(input)? };
+        let output = match droid.read()? {
+            Reply::Prompt(output) => output,
+            Reply::Ended(output) => return Err(AdventureError::GameOver(output)),
+        };
+        let room = Room::parse(&output).ok_or(AdventureError::NoRoom(output))?;
+        Ok(Self {
+            droid,
+            location: room.name.clone(),
+            rooms: HashMap::from([(room.name.clone(), room)]),
+            map: HashMap::new(),
+            checkpoint: None,
+            inventory: Vec::new(),
+            deadly: Vec::new(),
+        })
+    }
+
+    pub fn rooms(&self) -> impl Iterator<Item = &Room> {
+        self.rooms.values()
+    }
+
+    pub fn inventory(&self) -> &[String] {
+        &self.inventory
+    }
+
+    /// The items we left behind because they end the game or trap the droid.
+    pub fn deadly(&self) -> &[String] {
+        &self.deadly
+    }
+
+    fn go(&mut self, direction: Direction) -> Result<Room, AdventureError> {
+        let output = self.droid.prompt(&direction.to_string())?;
+        let room = Room::parse(&output).ok_or(AdventureError::NoRoom(output))?;
+        self.location = room.name.clone();
+        Ok(room)
+    }
+
+    // Tries taking the item on a copy of the droid first, and only keeps it if
+    // the game goes on and the droid can still move afterwards.
+    fn take(&mut self, item: &str, doors: &[Direction]) -> Result<bool, AdventureError> {
+        let mut trial = self.droid.clone();
+        match trial.command(&format!("take {item}")) {
+            Ok(Reply::Prompt(output)) if output.contains(&format!("You take the {item}.")) => (),
+            Ok(_) | Err(AdventureError::Runaway) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        if let Some(door) = doors.first() {
+            match trial.clone().command(&door.to_string()) {
+                Ok(Reply::Prompt(output)) if Room::parse(&output).is_some() => (),
+                _ => return Ok(false),
+            }
+        }
+        self.droid = trial;
+        Ok(true)
+    }
+
+    fn visit(&mut self, room: Room) -> Result<(), AdventureError> {
+        let name = room.name.clone();
+        for item in &room.items {
+            if self.take(item, &room.doors)? {
+                self.inventory.push(item.clone());
+            } else {
+                self.deadly.push(item.clone());
+            }
+        }
+        self.rooms.insert(name.clone(), room.clone());
+        for &direction in &room.doors {
+            if self.map.contains_key(&(name.clone(), direction)) {
+                continue;
+            }
+            let next = self.go(direction)?;
+            if next.name == name {
+                // Bounced back off the pressure-sensitive floor.
+                self.checkpoint = Some((name.clone(), direction));
+                continue;
+            }
+            self.map.insert((name.clone(), direction), next.name.clone());
+            self.map.insert((next.name.clone(), direction.opposite()), name.clone());
+            if !self.rooms.contains_key(&next.name) {
+                self.visit(next)?;
+            }
+            self.go(direction.opposite())?;
+        }
+        Ok(())
+    }
+
+    /// Walks through every door of the ship and returns to the start.
+    pub fn explore(&mut self) -> Result<(), AdventureError> {
+        let start = self.rooms[&self.location].clone();
+        self.visit(start)
+    }
+
+    fn path(&self, to: &str) -> Option<Vec<Direction>> {
+        let mut previous = HashMap::new();
+        let mut seen = HashSet::from([self.location.as_str()]);
+        let mut open = VecDeque::from([self.location.as_str()]);
+        while let Some(room) = open.pop_front() {
+            if room == to {
+                let mut path = Vec::new();
+                let mut current = room;
+                while let Some(&(from, direction)) = previous.get(current) {
+                    path.push(direction);
+                    current = from;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for ((from, direction), next) in &self.map {
+                if from == room && seen.insert(next.as_str()) {
+                    previous.insert(next.as_str(), (room, *direction));
+                    open.push_back(next.as_str());
+                }
+            }
+        }
+        None
+    }
+
+    /// Goes to the security checkpoint and tries combinations of carried items
+    /// on the floor until it lets the droid through. Returns the password.
+    pub fn pass_checkpoint(&mut self) -> Result<usize, AdventureError> {
+        let (checkpoint, floor) = self.checkpoint.clone().ok_or(AdventureError::NoCheckpoint)?;
+        for direction in self.path(&checkpoint).ok_or(AdventureError::NoCheckpoint)? {
+            self.go(direction)?;
+        }
+        let items = self.inventory.clone();
+        let mut held = (1_usize << items.len()) - 1;
+        // Every superset of a combination that was too heavy is too heavy as
+        // well, and vice versa.
+        let mut known: Vec<(usize, Weight)> = Vec::new();
+        for idx in 0..1_usize << items.len() {
+            let wanted = idx ^ (idx >> 1);
+            if known.iter().any(|&(mask, weight)| match weight {
+                Weight::TooHeavy => wanted & mask == mask,
+                Weight::TooLight => wanted & mask == wanted,
+            }) {
+                continue;
+            }
+            for (bit, item) in items.iter().enumerate() {
+                match (held & 1 << bit != 0, wanted & 1 << bit != 0) {
+                    (true, false) => { self.droid.prompt(&format!("drop {item}"))?; },
+                    (false, true) => { self.droid.prompt(&format!("take {item}"))?; },
+                    _ => (),
+                }
+            }
+            held = wanted;
+            match self.droid.command(&floor.to_string())? {
+                Reply::Ended(output) => return password(&output),
+                Reply::Prompt(output) if output.contains("heavier than the detected") => known.push((wanted, Weight::TooLight)),
+                Reply::Prompt(output) if output.contains("lighter than the detected") => known.push((wanted, Weight::TooHeavy)),
+                Reply::Prompt(output) => return Err(AdventureError::NoPassword(output)),
+            }
+        }
+        Err(AdventureError::NoCombination)
+    }
+}
+
+fn password(output: &str) -> Result<usize, AdventureError> {
+    output
+        .split_once("typing ")
+        .and_then(|(_, rest)| rest.split_whitespace().next())
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| AdventureError::NoPassword(output.to_string()))
+}
+
+/// Lets a human play the game on the console. Besides the game's commands,
+/// this supports the terminal's `!macro` and `!save` commands.
+pub fn play(input: &str) -> Result<(), AdventureError> {
+    let mut terminal = Terminal::new(Cpu::try_with_memory_from_str(input)?, Encoding::Ascii);
+    terminal.interact(std::io::stdin().lock(), std::io::stdout()).map_err(|e| AdventureError::Io(e.to_string()))?;
+    Ok(())
+}
+
+pub fn run(input: &str) -> Result<usize, AdventureError> {
+    let mut explorer = Explorer::try_new(input)?;
+    explorer.explore()?;
+    explorer.pass_checkpoint()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -25,11 +363,38 @@ mod tests {
     }
 
     #[test]
-    #[ignore = "Requires manual inputs."]
+    fn test_room() {
+        let output = "\n\n\n== Science Lab ==\nYou see evidence here of prototype polymer design work.\n\nDoors here lead:\n- east\n- west\n\nItems here:\n- cake\n\nCommand?\n";
+        assert_eq!(Room::parse(output), Some(Room {
+            name: "Science Lab".to_string(),
+            description: "You see evidence here of prototype polymer design work.".to_string(),
+            doors: vec![Direction::East, Direction::West],
+            items: vec!["cake".to_string()],
+        }));
+        assert_eq!(Room::parse("You take the cake.\n\nCommand?\n"), None);
+    }
+
+    #[test]
+    fn test_transcript() {
+        use intcode_processor::ascii::{replay, Transcript};
+
+        let challenge_input = read_file("tests/challenge_input");
+        let cpu = Cpu::try_with_memory_from_str(&challenge_input).unwrap();
+        let transcript: Transcript = read_file("tests/solution_transcript").parse().unwrap();
+        let terminal = replay(cpu, Encoding::Ascii, &transcript).unwrap();
+        assert!(terminal.transcript().to_string().contains("by typing 19013632 on the keypad"));
+    }
+
+    #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
-        // Required items are Cake + Coin + Monolith + Mug (shortest route: W, take cake, W, S, take monolith, N,
-        // W, S, E, E, E, take mug, W, W, W, N, E, E, E, S, take coin, S, W, N, N, N). Should output 19013632 in the end.
-        assert_eq!(run(&challenge_input), Ok(()));
+        // Required items are Cake + Coin + Monolith + Mug (see tests/solution_transcript for the shortest route).
+        let mut explorer = Explorer::try_new(&challenge_input).unwrap();
+        explorer.explore().unwrap();
+        let mut deadly = explorer.deadly().to_vec();
+        deadly.sort();
+        assert_eq!(deadly, ["escape pod", "giant electromagnet", "infinite loop", "molten lava", "photons"]);
+        assert_eq!(explorer.pass_checkpoint(), Ok(19013632));
+        assert_eq!(run(&challenge_input), Ok(19013632));
     }
 }
@@ -0,0 +1,220 @@
+// Arithmetic with whatever operator precedence the locals happen to use. The
+// rules are a table of precedences and associativities, and a Pratt parser
+// builds the syntax tree according to them.
+
+use core::fmt::Display;
+
+use crate::ParseError;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EvalError {
+    DivisionByZero,
+    Overflow,
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::Overflow => write!(f, "Result is out of range"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator { Add, Sub, Mul, Div }
+
+impl Operator {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '+' => Some(Self::Add),
+            '-' => Some(Self::Sub),
+            '*' => Some(Self::Mul),
+            '/' => Some(Self::Div),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Add => write!(f, "+"),
+            Self::Sub => write!(f, "-"),
+            Self::Mul => write!(f, "*"),
+            Self::Div => write!(f, "/"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Associativity { Left, Right }
+
+/// Which operators exist, and how tightly they bind. Higher precedences bind
+/// tighter. Operators without an entry are rejected by the parser.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rules {
+    table: [Option<(u8, Associativity)>; 4],
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, operator: Operator, precedence: u8, associativity: Associativity) -> Self {
+        self.table[operator.index()] = Some((precedence, associativity));
+        self
+    }
+
+    /// Addition and multiplication, evaluated strictly from left to right.
+    pub fn left_to_right() -> Self {
+        Self::new()
+            .with(Operator::Add, 1, Associativity::Left)
+            .with(Operator::Mul, 1, Associativity::Left)
+    }
+
+    /// Addition and multiplication, with addition binding tighter.
+    pub fn addition_first() -> Self {
+        Self::new()
+            .with(Operator::Add, 2, Associativity::Left)
+            .with(Operator::Mul, 1, Associativity::Left)
+    }
+
+    /// The rules taught in schools back home.
+    pub fn conventional() -> Self {
+        Self::new()
+            .with(Operator::Add, 1, Associativity::Left)
+            .with(Operator::Sub, 1, Associativity::Left)
+            .with(Operator::Mul, 2, Associativity::Left)
+            .with(Operator::Div, 2, Associativity::Left)
+    }
+
+    fn get(&self, operator: Operator) -> Option<(u8, Associativity)> {
+        self.table[operator.index()]
+    }
+
+    pub fn parse(&self, input: &str) -> Result<Expr, ParseError> {
+        let mut parser = Parser { input, tokens: tokenize(input)?.into_iter().peekable(), rules: self };
+        let expr = parser.expression(0)?;
+        match parser.tokens.next() {
+            None => Ok(expr),
+            Some(_) => Err(ParseError::LineMalformed(input.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Num(i64),
+    Op(Box<Expr>, Operator, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the expression. Divisions round towards zero.
+    pub fn eval(&self) -> Result<i64, EvalError> {
+        match self {
+            Self::Num(n) => Ok(*n),
+            Self::Op(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.eval()?, rhs.eval()?);
+                match op {
+                    Operator::Add => lhs.checked_add(rhs),
+                    Operator::Sub => lhs.checked_sub(rhs),
+                    Operator::Mul => lhs.checked_mul(rhs),
+                    Operator::Div if rhs == 0 => return Err(EvalError::DivisionByZero),
+                    Operator::Div => lhs.checked_div(rhs),
+                }.ok_or(EvalError::Overflow)
+            },
+        }
+    }
+}
+
+impl Display for Expr {
+    /// Writes the expression with every operation in parentheses.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Num(n) => write!(f, "{n}"),
+            Self::Op(lhs, op, rhs) => write!(f, "({lhs} {op} {rhs})"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Op(Operator),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            c if c.is_whitespace() => (),
+            c if c.is_ascii_digit() => {
+                let mut end = start + 1;
+                while let Some((idx, _)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    end = idx + 1;
+                }
+                tokens.push(Token::Num(input[start..end].parse()?));
+            },
+            c => tokens.push(Token::Op(Operator::from_char(c).ok_or(ParseError::InvalidToken(c))?)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    rules: &'a Rules,
+}
+
+impl Parser<'_> {
+    fn malformed(&self) -> ParseError {
+        ParseError::LineMalformed(self.input.to_string())
+    }
+
+    // A number or a parenthesised expression.
+    fn operand(&mut self) -> Result<Expr, ParseError> {
+        match self.tokens.next() {
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Open) => {
+                let expr = self.expression(0)?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(self.malformed()),
+                }
+            },
+            _ => Err(self.malformed()),
+        }
+    }
+
+    // Reads operands joined by operators that bind at least as tightly as
+    // `min_precedence`.
+    fn expression(&mut self, min_precedence: u16) -> Result<Expr, ParseError> {
+        let mut lhs = self.operand()?;
+        while let Some(&Token::Op(op)) = self.tokens.peek() {
+            let (precedence, associativity) = self.rules.get(op).ok_or(ParseError::UnsupportedOperator(op))?;
+            let precedence = precedence as u16;
+            if precedence < min_precedence {
+                break;
+            }
+            self.tokens.next();
+            let rhs = match associativity {
+                Associativity::Left => self.expression(precedence + 1)?,
+                Associativity::Right => self.expression(precedence)?,
+            };
+            lhs = Expr::Op(Box::new(lhs), op, Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+}
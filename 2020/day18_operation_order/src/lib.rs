@@ -1,10 +1,15 @@
 use core::fmt::Display;
 use std::num::ParseIntError;
 
+mod expression;
+pub use expression::{Associativity, EvalError, Expr, Operator, Rules};
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError {
+    InvalidToken(char),
     ParseIntError(std::num::ParseIntError),
     LineMalformed(String),
+    UnsupportedOperator(Operator),
 }
 
 impl From<ParseIntError> for ParseError {
@@ -13,177 +18,52 @@ impl From<ParseIntError> for ParseError {
     }
 }
 
-impl Display for ParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
-            Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
-        }
-    }
-}
-
-enum Operator {
-    Add,
-    Mul,
-}
-
-enum SubExpression {
-    Expr(Box<Expression>),
-    Num(usize),
+/// Anything that can go wrong from reading the homework to adding it up.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Parse(ParseError),
+    Eval(EvalError),
 }
 
-impl SubExpression {
-    fn eval(&self) -> usize {
-        match self {
-            Self::Expr(e) => e.eval(),
-            Self::Num(n) => *n,
-        }
+impl From<ParseError> for Error {
+    fn from(value: ParseError) -> Self {
+        Self::Parse(value)
     }
 }
 
-struct Expression {
-    op: Operator,
-    lhs: SubExpression,
-    rhs: SubExpression,
-}
-
-impl TryFrom<&str> for Expression {
-    type Error = ParseError;
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let components: Vec<_> = value.split_whitespace().collect();
-        let last = components.len()-1;
-        if last < 2 {
-            Err(Self::Error::LineMalformed(value.to_string()))
-        } else {
-            if components[last].ends_with(')') {
-                let mut recursion_level = components[last].matches(')').count();
-                let mut rhs_components = 0;
-                while recursion_level > 0 {
-                    rhs_components += 1;
-                    recursion_level += components[last-rhs_components].matches(')').count();
-                    recursion_level -= components[last-rhs_components].matches('(').count();
-                }
-                if rhs_components == last {
-                    Expression::try_from(&value[1..value.len()-1])
-                } else {
-                    let rhs_str = &components[last-rhs_components..].join(" ")[..];
-                    let rhs = SubExpression::Expr(Box::new(Expression::try_from(&rhs_str[1..rhs_str.len()-1])?));
-                let op = match components[last-rhs_components-1] {
-                    "+" => Operator::Add,
-                    "*" => Operator::Mul,
-                    _ => return Err(Self::Error::LineMalformed(value.to_string())),
-                };
-                let lhs = match last-rhs_components {
-                    2 => SubExpression::Num(components[0].parse()?),
-                    _ => SubExpression::Expr(Box::new(Expression::try_from(&components[..last-rhs_components-1].join(" ")[..])?)),
-                };
-                Ok(Self { op, lhs, rhs })
-                }
-            } else {
-                let rhs = SubExpression::Num(components[last].parse()?);
-                let op = match components[last-1] {
-                    "+" => Operator::Add,
-                    "*" => Operator::Mul,
-                    _ => return Err(Self::Error::LineMalformed(value.to_string())),
-                };
-                let lhs = match last {
-                    2 => SubExpression::Num(components[0].parse()?),
-                    _ => SubExpression::Expr(Box::new(Expression::try_from(&components[..last-1].join(" ")[..])?)),
-                };
-                Ok(Self { op, lhs, rhs })
-            }
-        }
+impl From<EvalError> for Error {
+    fn from(value: EvalError) -> Self {
+        Self::Eval(value)
     }
 }
 
-impl Expression {
-    fn eval(&self) -> usize {
-        match self.op {
-            Operator::Mul => self.lhs.eval() * self.rhs.eval(),
-            Operator::Add => self.lhs.eval() + self.rhs.eval(),
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidToken(c) => write!(f, "Unexpected character: {c}"),
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
+            Self::UnsupportedOperator(op) => write!(f, "Operator {op} isn't known here"),
         }
     }
+}
 
-    fn try_from_advanced(value: &str) -> Result<Self, ParseError> {
-        let components: Vec<_> = value.split_whitespace().collect();
-        let last = components.len()-1;
-        if last < 2 {
-            Err(ParseError::LineMalformed(value.to_string()))
-        } else {
-            if components[last].ends_with(')') {
-                let mut recursion_level = components[last].matches(')').count();
-                let mut rhs_components = 0;
-                while recursion_level > 0 {
-                    rhs_components += 1;
-                    recursion_level += components[last-rhs_components].matches(')').count();
-                    recursion_level -= components[last-rhs_components].matches('(').count();
-                }
-                if rhs_components == last {
-                    Expression::try_from_advanced(&value[1..value.len()-1])
-                } else {
-                    let mut recursion_level = 0;
-                    for idx in (0..last-rhs_components).rev() {
-                        recursion_level += components[idx].matches(')').count();
-                        recursion_level -= components[idx].matches('(').count();
-                        if recursion_level == 0 && components[idx] == "*" {
-                            let op = Operator::Mul;
-                            let lhs = if idx == 1 {
-                                SubExpression::Num(components[0].parse()?)
-                            } else {
-                                SubExpression::Expr(Box::new(Expression::try_from_advanced(&components[..idx].join(" ")[..])?))
-                            };
-                            let rhs = SubExpression::Expr(Box::new(Expression::try_from_advanced(&components[idx+1..].join(" ")[..])?));
-                            return Ok(Self { op, lhs, rhs });
-                        }
-                    }
-                    let op = Operator::Add;
-                    let rhs = SubExpression::Expr(Box::new(Expression::try_from_advanced(&components[last-rhs_components..].join(" ")[..])?));
-                    let lhs = match last-rhs_components {
-                        2 => SubExpression::Num(components[0].parse()?),
-                        _ => SubExpression::Expr(Box::new(Expression::try_from_advanced(&components[..last-rhs_components-1].join(" ")[..])?)),
-                    };
-
-                    Ok(Self { op, lhs, rhs })
-                }
-            } else {
-                let mut recursion_level = 0;
-                for idx in (0..=last).rev() {
-                    recursion_level += components[idx].matches(')').count();
-                    recursion_level -= components[idx].matches('(').count();
-                    if recursion_level == 0 && components[idx] == "*" {
-                        let op = Operator::Mul;
-                        let lhs = if idx == 1 {
-                            SubExpression::Num(components[0].parse()?)
-                        } else {
-                            SubExpression::Expr(Box::new(Expression::try_from_advanced(&components[..idx].join(" ")[..])?))
-                        };
-                        let rhs = if idx == last-1 {
-                            SubExpression::Num(components[components.len()-1].parse()?)
-                        } else {
-                            SubExpression::Expr(Box::new(Expression::try_from_advanced(&components[idx+1..].join(" ")[..])?))
-                        };
-                        return Ok(Self { op, lhs, rhs });
-                    }
-                }
-                let op = Operator::Add;
-                let rhs = SubExpression::Num(components[last].parse()?);
-                let lhs = match last {
-                    2 => SubExpression::Num(components[0].parse()?),
-                    _ => SubExpression::Expr(Box::new(Expression::try_from_advanced(&components[..last-1].join(" ")[..])?)),
-                };
-
-                Ok(Self { op, lhs, rhs })
-            }
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "{e}"),
+            Self::Eval(e) => write!(f, "Unable to evaluate: {e}"),
         }
     }
 }
 
-pub fn run(input: &str) -> Result<(usize, usize), ParseError> {
-    let formulas_1: Vec<_> = input.lines().map(Expression::try_from).collect::<Result<Vec<_>, _>>()?;
-    let formulas_2: Vec<_> = input.lines().map(Expression::try_from_advanced).collect::<Result<Vec<_>, _>>()?;
-    let first = formulas_1.iter().map(|f| f.eval()).sum();
-    let second = formulas_2.iter().map(|f| f.eval()).sum();
+pub fn run(input: &str) -> Result<(i64, i64), Error> {
+    let total = |rules: Rules| input.lines().try_fold(0_i64, |sum, line| {
+        let value = rules.parse(line)?.eval()?;
+        sum.checked_add(value).ok_or(Error::Eval(EvalError::Overflow))
+    });
+    let first = total(Rules::left_to_right())?;
+    let second = total(Rules::addition_first())?;
     Ok((first, second))
 }
 
@@ -202,6 +82,37 @@ mod tests {
         assert_eq!(run(&sample_input), Ok((26386, 693942)));
     }
 
+    #[test]
+    fn test_rules() {
+        let line = "2 * 3 + (4 * 5)";
+        assert_eq!(Rules::left_to_right().parse(line).unwrap().to_string(), "((2 * 3) + (4 * 5))");
+        assert_eq!(Rules::addition_first().parse(line).unwrap().to_string(), "(2 * (3 + (4 * 5)))");
+        assert_eq!(Rules::addition_first().parse(line).unwrap().eval(), Ok(46));
+
+        let conventional = Rules::conventional();
+        assert_eq!(conventional.parse("10 - 4 - 3").unwrap().eval(), Ok(3));
+        assert_eq!(conventional.parse("2 + 12 / 4 * 3").unwrap().to_string(), "(2 + ((12 / 4) * 3))");
+        assert_eq!(conventional.parse("1 - 7 / 2").unwrap().eval(), Ok(-2));
+        let right = Rules::new().with(Operator::Sub, 1, Associativity::Right);
+        assert_eq!(right.parse("10 - 4 - 3").unwrap().eval(), Ok(9));
+        // Subtraction first, then addition, then multiplication.
+        let alien = Rules::new()
+            .with(Operator::Sub, 3, Associativity::Left)
+            .with(Operator::Add, 2, Associativity::Left)
+            .with(Operator::Mul, 1, Associativity::Right);
+        assert_eq!(alien.parse("2 * 3 + 5 - 1 * 2").unwrap().to_string(), "(2 * ((3 + (5 - 1)) * 2))");
+
+        assert_eq!(conventional.parse("1 / (2 - 2)").unwrap().eval(), Err(EvalError::DivisionByZero));
+        assert_eq!(conventional.parse("9223372036854775807 + 1").unwrap().eval(), Err(EvalError::Overflow));
+        assert_eq!(Rules::left_to_right().parse("1 - 2"), Err(ParseError::UnsupportedOperator(Operator::Sub)));
+        assert_eq!(conventional.parse("1 % 2"), Err(ParseError::InvalidToken('%')));
+        assert_eq!(conventional.parse("(1 + 2"), Err(ParseError::LineMalformed("(1 + 2".to_string())));
+        assert_eq!(conventional.parse("1 + 2)"), Err(ParseError::LineMalformed("1 + 2)".to_string())));
+        assert_eq!(conventional.parse("1 +"), Err(ParseError::LineMalformed("1 +".to_string())));
+        assert_eq!(run("1 % 2"), Err(Error::Parse(ParseError::InvalidToken('%'))));
+        assert_eq!(run("9223372036854775807\n1"), Err(Error::Eval(EvalError::Overflow)));
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
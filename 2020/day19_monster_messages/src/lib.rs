@@ -6,6 +6,7 @@ pub enum ParseError {
     InputMalformed(String),
     ParseIntError(std::num::ParseIntError),
     LineMalformed(String),
+    UnknownRule(usize),
 }
 
 impl From<ParseIntError> for ParseError {
@@ -20,213 +21,225 @@ impl Display for ParseError {
             Self::InputMalformed(v) => write!(f, "Input doesn't consist of 2 parts seperated by an empty line: {v}"),
             Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
             Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
+            Self::UnknownRule(r) => write!(f, "Rule {r} is used, but never defined"),
         }
     }
 }
 
-struct Rules {
-    char_replacements: Vec<(char, usize)>,
-    reductions: BTreeMap<usize, Vec<Vec<usize>>>,
-    beginnings: HashSet<Vec<usize>>,
-    ends: HashSet<Vec<usize>>,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symbol {
+    Terminal(char),
+    Rule(usize),
 }
 
-impl TryFrom<&str> for Rules {
+/// How a message was derived from a rule: which rule produced which part of
+/// it, down to the single characters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseTree {
+    Leaf(char),
+    Node(usize, Vec<ParseTree>),
+}
+
+impl Display for ParseTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Leaf(c) => write!(f, "{c}"),
+            Self::Node(rule, children) => {
+                write!(f, "({rule}")?;
+                children.iter().try_for_each(|child| write!(f, " {child}"))?;
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+// A partially matched alternative: `dot` symbols of it were matched, starting
+// at position `origin` of the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Item {
+    rule: usize,
+    alternative: usize,
+    dot: usize,
+    origin: usize,
+}
+
+// Which rules matched which part of a message, as (rule, start, end).
+type Matches = HashSet<(usize, usize, usize)>;
+
+/// A context-free grammar. Messages are matched with an Earley parser, so
+/// rules may be recursive in any way, and alternatives may be empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grammar {
+    rules: BTreeMap<usize, Vec<Vec<Symbol>>>,
+    nullable: HashSet<usize>,
+}
+
+impl TryFrom<&str> for Grammar {
     type Error = ParseError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        let mut char_replacements = Vec::new();
-        let mut reductions = BTreeMap::new();
-        let beginnings = HashSet::from([Vec::from([42])]);
-        let ends = HashSet::from([Vec::from([31])]);
-        
+        let mut grammar = Self { rules: BTreeMap::new(), nullable: HashSet::new() };
         for line in value.lines() {
-            let components: Vec<_> = line.split_whitespace().collect();
-            if components.len() < 2 {
-                return Err(Self::Error::LineMalformed(line.to_string()));
-            }
-            let rule_idx = components[0][..components[0].len()-1].parse::<usize>()?;
-            if components[1].chars().next() == Some('"') {
-                if let Some(c) = components[1].chars().nth(1) {
-                    char_replacements.push((c, rule_idx));
-                } else {
-                    return Err(Self::Error::LineMalformed(line.to_string()));
-                }
-            } else {
-                let mut variants = Vec::new();
-                let partitions: Vec<_> = components.iter().enumerate().filter(|(_idx, c)| c == &&"|").map(|(idx, _c)| idx).collect();
-                if partitions.is_empty() {
-                    variants.push(components.iter().skip(1).map(|i| i.parse::<usize>()).collect::<Result<Vec<_>, _>>()?);
-                } else {
-                    let mut last_part_idx = 0;
-                    for part_idx in partitions {
-                        variants.push(components.iter().skip(last_part_idx+1).take(part_idx-last_part_idx-1).map(|i| i.parse::<usize>()).collect::<Result<Vec<_>, _>>()?);
-                        last_part_idx = part_idx;
-                    }
-                    variants.push(components.iter().skip(last_part_idx+1).map(|i| i.parse::<usize>()).collect::<Result<Vec<_>, _>>()?);
-                }
-                reductions.insert(rule_idx, variants);
-            }
+            let (idx, alternatives) = parse_rule(line)?;
+            grammar.rules.insert(idx, alternatives);
         }
+        grammar.validate()?;
+        Ok(grammar)
+    }
+}
 
-        Ok(Self { 
-            char_replacements,
-            reductions,
-            beginnings,
-            ends,
-        })
+fn parse_rule(line: &str) -> Result<(usize, Vec<Vec<Symbol>>), ParseError> {
+    let Some((idx, rest)) = line.split_once(':') else {
+        return Err(ParseError::LineMalformed(line.to_string()));
+    };
+    let idx = idx.trim().parse()?;
+    let rest = rest.trim();
+    if let Some(literal) = rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')) {
+        return Ok((idx, vec![literal.chars().map(Symbol::Terminal).collect()]));
     }
+    let alternatives = rest.split('|')
+        .map(|alternative| alternative.split_whitespace().map(|i| Ok(Symbol::Rule(i.parse()?))).collect())
+        .collect::<Result<_, ParseError>>()?;
+    Ok((idx, alternatives))
 }
 
-impl Rules {
-    fn find_beginnings(&mut self) {
-        let mut open_set = self.beginnings.iter().cloned().collect::<Vec<_>>();
-        let mut visited = self.beginnings.clone();
-        let mut beginnings = HashSet::new();
-        while let Some(current) = open_set.pop() {
-            if current.iter().all(|n| self.char_replacements.iter().any(|(_c, i)| n == i)) {
-                beginnings.insert(current);
-            } else {
-                for (idx, rule) in current.iter().enumerate() {
-                    if let Some(expansions) = self.reductions.get(rule) {
-                        for exp in expansions {
-                            let mut next = current[..idx].to_vec();
-                            next.append(&mut exp.to_vec());
-                            next.append(&mut current[idx+1..].to_vec());
-                            if !visited.contains(&next) {
-                                visited.insert(next.to_vec());
-                                open_set.push(next);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        self.beginnings = beginnings;
-    }
-
-    fn find_ends(&mut self) {
-        let mut open_set = self.ends.iter().cloned().collect::<Vec<_>>();
-        let mut visited = self.ends.clone();
-        let mut ends = HashSet::new();
-        while let Some(current) = open_set.pop() {
-            if current.iter().all(|n| self.char_replacements.iter().any(|(_c, i)| n == i)) {
-                ends.insert(current);
-            } else {
-                for (idx, rule) in current.iter().enumerate() {
-                    if let Some(expansions) = self.reductions.get(rule) {
-                        for exp in expansions {
-                            let mut next = current[..idx].to_vec();
-                            next.append(&mut exp.to_vec());
-                            next.append(&mut current[idx+1..].to_vec());
-                            if !visited.contains(&next) {
-                                visited.insert(next.to_vec());
-                                open_set.push(next);
-                            }
-                        }
-                    }
-                }
-            }
+impl Grammar {
+    /// Adds or replaces a single rule, given in the same format as the input.
+    pub fn set_rule(&mut self, line: &str) -> Result<(), ParseError> {
+        let (idx, alternatives) = parse_rule(line)?;
+        self.rules.insert(idx, alternatives);
+        self.validate()
+    }
+
+    // Checks that every rule that's referenced exists, and finds those that
+    // can match an empty message.
+    fn validate(&mut self) -> Result<(), ParseError> {
+        if let Some(missing) = self.rules.values().flatten().flatten()
+            .find_map(|s| match s { Symbol::Rule(r) if !self.rules.contains_key(r) => Some(*r), _ => None }) {
+            return Err(ParseError::UnknownRule(missing));
         }
-        self.ends = ends;
-    }
-
-    // All valid messages have the format 42 42 31 for part 1, or 42+ 42{n} 31{n} for part 2 (where
-    // both n's are equal and greater than zero. Hence we can split the message up and check if it
-    // consists of 2 valid beginning parts (expansions of rule 42) and 1 valid ending (expansions
-    // of rule 31). For part 2 we allow additional beginnings and endings between those parts, so
-    // effectively any number of beginnings and endings (in that order), where the number of
-    // endings is at least 1 and the number of beginnings is at least 1 more than that.
-    fn is_valid(&mut self, message: &str, part_2: bool) -> bool {
-        let target: Vec<usize> = message.chars().map(|c| self.char_replacements.iter().find(|(rule, _idx)| rule == &c).unwrap().1).collect();
-        let mut targets = Vec::new();
-        let beginnings: Vec<_> = self.beginnings.iter().filter(|b| target.starts_with(b)).collect();
-        if beginnings.is_empty() {
-            return false;
-        } else {
-            for beginning in beginnings {
-                targets.push(target[beginning.len()..].to_vec());
+        self.nullable.clear();
+        loop {
+            let new: Vec<_> = self.rules.iter()
+                .filter(|(idx, alternatives)| !self.nullable.contains(idx) && alternatives.iter()
+                    .any(|alt| alt.iter().all(|s| matches!(s, Symbol::Rule(r) if self.nullable.contains(r)))))
+                .map(|(idx, _)| *idx)
+                .collect();
+            if new.is_empty() {
+                return Ok(());
             }
+            self.nullable.extend(new);
         }
-        let mut new_targets = Vec::new();
-        for target in &targets {
-            let beginnings: Vec<_> = self.beginnings.iter().filter(|b| target.starts_with(b)).collect();
-            if beginnings.is_empty() {
-                return false;
-            } else {
-                for beginning in beginnings {
-                    new_targets.push(target[beginning.len()..].to_vec());
-                }
+    }
+
+    fn symbol(&self, item: &Item) -> Option<Symbol> {
+        self.rules[&item.rule][item.alternative].get(item.dot).copied()
+    }
+
+    // Runs the Earley recogniser over `message`, and returns all rules
+    // that matched some part of it.
+    fn recognise(&self, start: usize, message: &[char]) -> Matches {
+        let mut sets: Vec<Vec<Item>> = vec![Vec::new(); message.len() + 1];
+        let mut seen: Vec<HashSet<Item>> = vec![HashSet::new(); message.len() + 1];
+        let mut matches = HashSet::new();
+        let add = |sets: &mut Vec<Vec<Item>>, seen: &mut Vec<HashSet<Item>>, pos: usize, item: Item| {
+            if seen[pos].insert(item) {
+                sets[pos].push(item);
             }
-        }
-        std::mem::swap(&mut targets, &mut new_targets);
-        let endings: Vec<_> = self.ends.iter().filter(|e| target.ends_with(e)).collect();
-        if endings.is_empty() {
-            return false;
-        } else {
-            let mut new_targets = Vec::new();
-            for ending in endings {
-                for target in &targets {
-                    new_targets.push(target[..target.len()-ending.len()].to_vec());
-                }
+        };
+        if let Some(alternatives) = self.rules.get(&start) {
+            for alternative in 0..alternatives.len() {
+                add(&mut sets, &mut seen, 0, Item { rule: start, alternative, dot: 0, origin: 0 });
             }
-            std::mem::swap(&mut targets, &mut new_targets);
         }
-        if part_2 {
-            while let Some(current) = targets.pop() {
-                if current.is_empty() {
-                    return true;
-                }
-                // Ensure we reduce the beginning and end the same number of times to make sure we
-                // reduce the beginnings at least as often as the endings. We can still reduce
-                // the beginning even more in the else case.
-                let endings: Vec<_> = self.ends.iter().filter(|e| current.ends_with(e)).collect();
-                if !endings.is_empty() {
-                    for ending in endings {
-                        let current = &current[..current.len()-ending.len()];
-                        let beginnings: Vec<_> = self.beginnings.iter().filter(|b| current.starts_with(b)).collect();
-                        if !beginnings.is_empty() {
-                            for beginning in beginnings {
-                                targets.push(current[beginning.len()..].to_vec());
-                            }
+        for pos in 0..=message.len() {
+            let mut next = 0;
+            while let Some(&item) = sets[pos].get(next) {
+                next += 1;
+                let advanced = Item { dot: item.dot + 1, ..item };
+                match self.symbol(&item) {
+                    None => {
+                        matches.insert((item.rule, item.origin, pos));
+                        let waiting: Vec<_> = sets[item.origin].iter()
+                            .filter(|other| self.symbol(other) == Some(Symbol::Rule(item.rule)))
+                            .map(|other| Item { dot: other.dot + 1, ..*other })
+                            .collect();
+                        waiting.into_iter().for_each(|other| add(&mut sets, &mut seen, pos, other));
+                    },
+                    Some(Symbol::Rule(rule)) => {
+                        for alternative in 0..self.rules[&rule].len() {
+                            add(&mut sets, &mut seen, pos, Item { rule, alternative, dot: 0, origin: pos });
                         }
-                    }
-                } else {
-                    // We only ever enter this branch if we have (a) exhausted all endings, in
-                    // which case we might as well retry searching for them -- they won't become
-                    // available again since we don't touch the end, or (b) didn't get a fitting
-                    // beginning. In that latter case, we either (ba) don't have a beginning at
-                    // all, so we fail here as well, or (bb) have one without ending. That however
-                    // breaks down into (bba) we will find a chain of valid beginnings, so we
-                    // should pass, or (bbb) we don't, in which case we will fail by removing from
-                    // the front.
-                    let beginnings: Vec<_> = self.beginnings.iter().filter(|b| current.starts_with(b)).collect();
-                    if !beginnings.is_empty() {
-                        for beginning in beginnings {
-                            targets.push(current[beginning.len()..].to_vec());
+                        // Rules that can match nothing might already be
+                        // complete in this set, so skip over them right away.
+                        if self.nullable.contains(&rule) {
+                            add(&mut sets, &mut seen, pos, advanced);
                         }
-                    }
+                    },
+                    Some(Symbol::Terminal(c)) => if message.get(pos) == Some(&c) {
+                        add(&mut sets, &mut seen, pos + 1, advanced);
+                    },
                 }
             }
-            return false;
-        } else {
-            targets.iter().any(|target| target.is_empty())
+        }
+        matches
+    }
+
+    pub fn matches(&self, start: usize, message: &str) -> bool {
+        let message: Vec<_> = message.chars().collect();
+        self.recognise(start, &message).contains(&(start, 0, message.len()))
+    }
+
+    /// One way to derive `message` from rule `start`, if there is any.
+    pub fn parse(&self, start: usize, message: &str) -> Option<ParseTree> {
+        let message: Vec<_> = message.chars().collect();
+        let matches = self.recognise(start, &message);
+        self.tree(start, 0, message.len(), &message, &matches, &mut HashSet::new())
+    }
+
+    // Builds the tree for `rule` matching message[from..to]. `path` holds the
+    // matches we're already inside of, so recursion that doesn't consume
+    // anything can't go around in circles.
+    fn tree(&self, rule: usize, from: usize, to: usize, message: &[char], matches: &Matches, path: &mut Matches) -> Option<ParseTree> {
+        if !matches.contains(&(rule, from, to)) || !path.insert((rule, from, to)) {
+            return None;
+        }
+        let tree = self.rules[&rule].iter()
+            .find_map(|alternative| self.sequence(alternative, from, to, message, matches, path))
+            .map(|children| ParseTree::Node(rule, children));
+        path.remove(&(rule, from, to));
+        tree
+    }
+
+    fn sequence(&self, symbols: &[Symbol], from: usize, to: usize, message: &[char], matches: &Matches, path: &mut Matches) -> Option<Vec<ParseTree>> {
+        let Some((&first, rest)) = symbols.split_first() else {
+            return (from == to).then(Vec::new);
+        };
+        let prepend = |tree, mut children: Vec<ParseTree>| {
+            children.insert(0, tree);
+            children
+        };
+        match first {
+            Symbol::Terminal(c) if message.get(from) == Some(&c) && from < to => self.sequence(rest, from + 1, to, message, matches, path).map(|children| prepend(ParseTree::Leaf(c), children)),
+            Symbol::Terminal(_) => None,
+            Symbol::Rule(rule) => (from..=to).find_map(|mid| {
+                let tree = self.tree(rule, from, mid, message, matches, path)?;
+                self.sequence(rest, mid, to, message, matches, path).map(|children| prepend(tree, children))
+            }),
         }
     }
 }
 
 pub fn run(input: &str) -> Result<(usize, usize), ParseError> {
-    if let Some((rules_str, msg_str)) = input.split_once("\n\n") {
-        let mut rules = Rules::try_from(rules_str)?;
-        rules.find_beginnings();
-        rules.find_ends();
-        let messages: Vec<_> = msg_str.lines().collect();
-        let first = messages.iter().filter(|m| rules.is_valid(m, false)).count();
-        let second = messages.iter().filter(|m| rules.is_valid(m, true)).count();
-        Ok((first, second))
-    } else {
-        Err(ParseError::InputMalformed(input.to_string()))
-    }
+    let Some((rules, messages)) = input.split_once("\n\n") else {
+        return Err(ParseError::InputMalformed(input.to_string()));
+    };
+    let mut grammar = Grammar::try_from(rules)?;
+    let first = messages.lines().filter(|m| grammar.matches(0, m)).count();
+    grammar.set_rule("8: 42 | 42 8")?;
+    grammar.set_rule("11: 42 31 | 42 11 31")?;
+    let second = messages.lines().filter(|m| grammar.matches(0, m)).count();
+    Ok((first, second))
 }
 
 #[cfg(test)]
@@ -244,6 +257,31 @@ mod tests {
         assert_eq!(run(&sample_input), Ok((3, 12)));
     }
 
+    #[test]
+    fn test_grammar() {
+        let grammar = Grammar::try_from("0: 4 1 5\n1: 2 3 | 3 2\n2: 4 4 | 5 5\n3: 4 5 | 5 4\n4: \"a\"\n5: \"b\"").unwrap();
+        assert!(grammar.matches(0, "ababbb"));
+        assert!(!grammar.matches(0, "aaabbb"));
+        assert_eq!(grammar.parse(0, "ababbb").unwrap().to_string(), "(0 (4 a) (1 (3 (5 b) (4 a)) (2 (5 b) (5 b))) (5 b))");
+        assert_eq!(grammar.parse(0, "aaaabbb"), None);
+
+        // Left and right recursion, and empty alternatives.
+        let grammar = Grammar::try_from("0: 0 1 | 1\n1: \"a\"\n2: 1 2 | 3\n3: \"b\"\n4: 1 4 3 |\n5: 5 5 | 5 | 4").unwrap();
+        assert!(grammar.matches(0, "aaaa"));
+        assert_eq!(grammar.parse(0, "aaa").unwrap().to_string(), "(0 (0 (0 (1 a)) (1 a)) (1 a))");
+        assert!(grammar.matches(2, "aaab"));
+        assert!(!grammar.matches(2, "aaba"));
+        assert!(grammar.matches(4, ""));
+        assert!(grammar.matches(4, "aabb"));
+        assert!(!grammar.matches(4, "aab"));
+        assert!(grammar.matches(5, "aabb"));
+        assert_eq!(grammar.parse(4, "ab").unwrap().to_string(), "(4 (1 a) (4) (3 b))");
+        assert!(grammar.parse(5, "ab").is_some());
+
+        assert_eq!(Grammar::try_from("0: 1 2\n1: \"a\""), Err(ParseError::UnknownRule(2)));
+        assert_eq!(Grammar::try_from("0 1 2"), Err(ParseError::LineMalformed("0 1 2".to_string())));
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
@@ -0,0 +1,88 @@
+// Turns packets back into a BITS transmission.
+
+use core::fmt::Display;
+
+use crate::{Packet, PacketValue};
+
+const MAX_SUB_PACKETS: usize = (1 << 11) - 1;
+const MAX_TOTAL_LENGTH: usize = (1 << 15) - 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    TooManySubPackets(usize),
+    VersionTooLarge(u8),
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooManySubPackets(count) => write!(f, "{count} sub-packets don't fit into a single operator"),
+            Self::VersionTooLarge(v) => write!(f, "Version {v} doesn't fit into 3 bits"),
+        }
+    }
+}
+
+fn push_bits(bits: &mut Vec<bool>, value: usize, width: usize) {
+    bits.extend((0..width).rev().map(|idx| (value >> idx) & 1 == 1));
+}
+
+impl Packet {
+    /// Encodes the packet into bits. Operators announce how many sub-packets
+    /// they contain (length type ID 1) whenever that count fits into its 11
+    /// bits, as that header is 4 bits shorter. Otherwise they fall back to the
+    /// total length of their sub-packets (length type ID 0).
+    pub fn encode(&self) -> Result<Vec<bool>, EncodeError> {
+        let mut bits = Vec::new();
+        self.encode_into(&mut bits)?;
+        Ok(bits)
+    }
+
+    fn encode_into(&self, bits: &mut Vec<bool>) -> Result<(), EncodeError> {
+        if self.version > 7 {
+            return Err(EncodeError::VersionTooLarge(self.version));
+        }
+        push_bits(bits, self.version as usize, 3);
+        push_bits(bits, self.value.type_id() as usize, 3);
+        match &self.value {
+            PacketValue::Literal(value) => {
+                let groups = (usize::BITS - value.leading_zeros()).div_ceil(4).max(1);
+                for group in (0..groups).rev() {
+                    bits.push(group > 0);
+                    push_bits(bits, (value >> (4 * group)) & 0xf, 4);
+                }
+            },
+            op => {
+                let packets = op.packets();
+                if packets.len() <= MAX_SUB_PACKETS {
+                    bits.push(true);
+                    push_bits(bits, packets.len(), 11);
+                    for packet in packets {
+                        packet.encode_into(bits)?;
+                    }
+                } else {
+                    let mut contents = Vec::new();
+                    for packet in packets {
+                        packet.encode_into(&mut contents)?;
+                    }
+                    if contents.len() > MAX_TOTAL_LENGTH {
+                        return Err(EncodeError::TooManySubPackets(packets.len()));
+                    }
+                    bits.push(false);
+                    push_bits(bits, contents.len(), 15);
+                    bits.append(&mut contents);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// The hexadecimal transmission, padded with zeros to whole bytes.
+    pub fn to_hex(&self) -> Result<String, EncodeError> {
+        let mut bits = self.encode()?;
+        bits.resize(bits.len().next_multiple_of(8), false);
+        Ok(bits.chunks(4).map(|chunk| {
+            let digit = chunk.iter().fold(0, |acc, &bit| 2 * acc + bit as u32);
+            char::from_digit(digit, 16).unwrap().to_ascii_uppercase()
+        }).collect())
+    }
+}
@@ -1,22 +1,34 @@
 use core::fmt::Display;
 
+mod encode;
+mod sexpr;
+pub use encode::EncodeError;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError {
+    OperandCount(String),
     PacketTooShort(String),
     ParseIntError(char),
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    UnknownOperator(String),
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::OperandCount(op) => write!(f, "Wrong number of operands for {op}"),
             Self::PacketTooShort(v) => write!(f, "Packet is too short: {v}"),
             Self::ParseIntError(c) => write!(f, "Unable to parse {c} into integer"),
+            Self::UnexpectedEnd => write!(f, "Expression ended unexpectedly"),
+            Self::UnexpectedToken(t) => write!(f, "Unexpected token: {t}"),
+            Self::UnknownOperator(op) => write!(f, "Unknown operator: {op}"),
         }
     }
 }
 
-#[derive(Debug)]
-enum PacketValue {
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PacketValue {
     Sum(Vec<Packet>),
     Mul(Vec<Packet>),
     Min(Vec<Packet>),
@@ -41,6 +53,19 @@ impl PacketValue {
         }
     }
 
+    fn type_id(&self) -> u8 {
+        match self {
+            Self::Sum(_) => 0,
+            Self::Mul(_) => 1,
+            Self::Min(_) => 2,
+            Self::Max(_) => 3,
+            Self::Literal(_) => 4,
+            Self::Greater(_) => 5,
+            Self::Less(_) => 6,
+            Self::Equal(_) => 7,
+        }
+    }
+
     fn packets(&self) -> &Vec<Packet> {
         match self {
             PacketValue::Literal(_) => panic!("Tried to access packets of literal {self:?}"),
@@ -55,10 +80,10 @@ impl PacketValue {
     }
 }
 
-#[derive(Debug)]
-struct Packet {
-    version: u8,
-    value: PacketValue,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub version: u8,
+    pub value: PacketValue,
 }
 
 impl TryFrom<&Vec<bool>> for Packet {
@@ -70,6 +95,10 @@ impl TryFrom<&Vec<bool>> for Packet {
 }
 
 impl Packet {
+    pub fn from_hex(hex: &str) -> Result<Self, ParseError> {
+        Self::try_from(&hex_to_bitstream(hex)?)
+    }
+
     fn parse(value: &[bool]) -> Result<(Self, usize), ParseError> {
         if value.len() < 6 {
             return Err(ParseError::PacketTooShort(format!("{value:?}")));
@@ -114,14 +143,14 @@ impl Packet {
         }
     }
 
-    fn sum_version_numbers(&self) -> usize {
+    pub fn sum_version_numbers(&self) -> usize {
         self.version as usize + match &self.value {
             PacketValue::Literal(_) => 0,
             op => op.packets().iter().map(|p| p.sum_version_numbers()).sum(),
         }
     }
 
-    fn evaluate(&self) -> usize {
+    pub fn evaluate(&self) -> usize {
         match &self.value {
             PacketValue::Literal(v) => *v,
             PacketValue::Sum(packets) => packets.iter().map(|p| p.evaluate()).sum(),
@@ -136,8 +165,7 @@ impl Packet {
 }
 
 pub fn run(input: &str) -> Result<(usize, usize), ParseError> {
-    let bits: Vec<_> = hex_to_bitstream(input)?;
-    let packets = Packet::try_from(&bits)?;
+    let packets = Packet::from_hex(input)?;
     let first = packets.sum_version_numbers();
     let second = packets.evaluate();
     Ok((first, second))
@@ -214,6 +242,73 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_encode() {
+        let literal = Packet { version: 6, value: PacketValue::Literal(2021) };
+        assert_eq!(literal.to_hex(), Ok("D2FE28".to_string()));
+        let max: Packet = "(max@7 1@2 2@4 3@1)".parse().unwrap();
+        assert_eq!(max.to_hex(), Ok("EE00D40C823060".to_string()));
+        assert_eq!(Packet::from_hex("EE00D40C823060"), Ok(max));
+
+        let packet: Packet = "(sum 1 (product@3 2 3))".parse().unwrap();
+        assert_eq!(packet.evaluate(), 7);
+        assert_eq!(packet.to_string(), "(sum 1 (product@3 2 3))");
+        assert_eq!(format!("{packet:#}"), "sum (version 0)\n  literal 1 (version 0)\n  product (version 3)\n    literal 2 (version 0)\n    literal 3 (version 0)\n");
+        assert_eq!(Packet::from_hex(&packet.to_hex().unwrap()), Ok(packet));
+
+        // Too many sub-packets to count them, so their length is given instead.
+        let many = Packet { version: 0, value: PacketValue::Sum(vec![Packet { version: 0, value: PacketValue::Literal(1) }; 2500]) };
+        let bits = many.encode().unwrap();
+        assert!(!bits[6]);
+        assert_eq!(Packet::from_hex(&many.to_hex().unwrap()), Ok(many));
+        let too_many = Packet { version: 0, value: PacketValue::Sum(vec![Packet { version: 0, value: PacketValue::Literal(1) }; 3000]) };
+        assert_eq!(too_many.encode(), Err(EncodeError::TooManySubPackets(3000)));
+        assert_eq!(Packet { version: 8, value: PacketValue::Literal(1) }.encode(), Err(EncodeError::VersionTooLarge(8)));
+
+        assert_eq!("(sum 1".parse::<Packet>(), Err(ParseError::UnexpectedEnd));
+        assert_eq!("(gt 1 2 3)".parse::<Packet>(), Err(ParseError::OperandCount("gt".to_string())));
+        assert_eq!("(sum)".parse::<Packet>(), Err(ParseError::OperandCount("sum".to_string())));
+        assert_eq!("(div 1 2)".parse::<Packet>(), Err(ParseError::UnknownOperator("div".to_string())));
+        assert_eq!("(sum 1 x)".parse::<Packet>(), Err(ParseError::UnexpectedToken("x".to_string())));
+        assert_eq!("1@8".parse::<Packet>(), Err(ParseError::UnexpectedToken("1@8".to_string())));
+        assert_eq!("(sum 1) 2".parse::<Packet>(), Err(ParseError::UnexpectedToken("2".to_string())));
+    }
+
+    // A random packet tree no deeper than `depth`, from a xorshift generator.
+    fn random_packet(state: &mut u64, depth: usize) -> Packet {
+        let mut next = |range: u64| {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            *state % range
+        };
+        let version = next(8) as u8;
+        let type_id = if depth == 0 { 4 } else { next(8) as u8 };
+        if type_id == 4 {
+            let bits = next(64);
+            return Packet { version, value: PacketValue::Literal((next(u64::MAX) >> bits) as usize) };
+        }
+        let count = match type_id {
+            5..=7 => 2,
+            _ => 1 + next(4) as usize,
+        };
+        let sub_packets = (0..count).map(|_| random_packet(state, depth - 1)).collect();
+        Packet { version, value: PacketValue::from(type_id, sub_packets) }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut state = 0x2021_1216;
+        for _ in 0..1000 {
+            let packet = random_packet(&mut state, 5);
+            assert_eq!(Packet::from_hex(&packet.to_hex().unwrap()).as_ref(), Ok(&packet));
+            assert_eq!(packet.to_string().parse::<Packet>().as_ref(), Ok(&packet));
+        }
+        let challenge = Packet::from_hex(&read_file("tests/challenge_input")).unwrap();
+        let encoded = challenge.to_hex().unwrap();
+        assert_eq!(Packet::from_hex(&encoded), Ok(challenge));
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
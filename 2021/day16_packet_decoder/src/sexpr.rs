@@ -0,0 +1,130 @@
+// Packets as S-expressions like `(sum 1 (product 2 3))`. Versions other than 0
+// are attached with an `@`, as in `(sum@3 1@5 2)`.
+
+use core::fmt::Display;
+use std::iter::Peekable;
+use std::str::{FromStr, SplitWhitespace};
+
+use crate::{Packet, PacketValue, ParseError};
+
+impl PacketValue {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sum(_) => "sum",
+            Self::Mul(_) => "product",
+            Self::Min(_) => "min",
+            Self::Max(_) => "max",
+            Self::Literal(_) => "literal",
+            Self::Greater(_) => "gt",
+            Self::Less(_) => "lt",
+            Self::Equal(_) => "eq",
+        }
+    }
+}
+
+impl Packet {
+    fn fmt_label(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.value {
+            PacketValue::Literal(v) => write!(f, "{v}")?,
+            ref op => write!(f, "{}", op.name())?,
+        }
+        match self.version {
+            0 => Ok(()),
+            v => write!(f, "@{v}"),
+        }
+    }
+
+    fn fmt_tree(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        write!(f, "{:1$}", "", 2 * depth)?;
+        match &self.value {
+            PacketValue::Literal(v) => writeln!(f, "literal {v} (version {})", self.version),
+            op => {
+                writeln!(f, "{} (version {})", op.name(), self.version)?;
+                op.packets().iter().try_for_each(|p| p.fmt_tree(f, depth + 1))
+            },
+        }
+    }
+}
+
+impl Display for Packet {
+    /// Writes the packet as an S-expression, or, with `{:#}`, as an indented
+    /// tree with one packet per line.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            return self.fmt_tree(f, 0);
+        }
+        match &self.value {
+            PacketValue::Literal(_) => self.fmt_label(f),
+            op => {
+                write!(f, "(")?;
+                self.fmt_label(f)?;
+                op.packets().iter().try_for_each(|p| write!(f, " {p}"))?;
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+// Splits off a trailing `@version`.
+fn versioned(token: &str) -> Result<(&str, u8), ParseError> {
+    match token.split_once('@') {
+        None => Ok((token, 0)),
+        Some((name, version)) => match version.parse() {
+            Ok(v) if v < 8 => Ok((name, v)),
+            _ => Err(ParseError::UnexpectedToken(token.to_string())),
+        },
+    }
+}
+
+fn parse_packet(tokens: &mut Peekable<SplitWhitespace>) -> Result<Packet, ParseError> {
+    match tokens.next() {
+        None => Err(ParseError::UnexpectedEnd),
+        Some("(") => {
+            let (name, version) = versioned(tokens.next().ok_or(ParseError::UnexpectedEnd)?)?;
+            // Comparisons take exactly two operands, everything else at least one.
+            let (build, comparison): (fn(Vec<Packet>) -> PacketValue, bool) = match name {
+                "sum" => (PacketValue::Sum, false),
+                "product" => (PacketValue::Mul, false),
+                "min" => (PacketValue::Min, false),
+                "max" => (PacketValue::Max, false),
+                "gt" => (PacketValue::Greater, true),
+                "lt" => (PacketValue::Less, true),
+                "eq" => (PacketValue::Equal, true),
+                _ => return Err(ParseError::UnknownOperator(name.to_string())),
+            };
+            let mut packets = Vec::new();
+            loop {
+                match tokens.peek() {
+                    None => return Err(ParseError::UnexpectedEnd),
+                    Some(&")") => break,
+                    _ => packets.push(parse_packet(tokens)?),
+                }
+            }
+            tokens.next();
+            if (comparison && packets.len() != 2) || packets.is_empty() {
+                return Err(ParseError::OperandCount(name.to_string()));
+            }
+            let value = build(packets);
+            Ok(Packet { version, value })
+        },
+        Some(token) => {
+            let (number, version) = versioned(token)?;
+            let value = number.parse().map_err(|_| ParseError::UnexpectedToken(token.to_string()))?;
+            Ok(Packet { version, value: PacketValue::Literal(value) })
+        },
+    }
+}
+
+impl FromStr for Packet {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spaced = s.replace('(', " ( ").replace(')', " ) ");
+        let mut tokens = spaced.split_whitespace().peekable();
+        let packet = parse_packet(&mut tokens)?;
+        match tokens.next() {
+            None => Ok(packet),
+            Some(token) => Err(ParseError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
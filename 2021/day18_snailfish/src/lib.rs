@@ -1,5 +1,6 @@
 use core::fmt::Display;
 use std::num::ParseIntError;
+use rayon::prelude::*;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError {
@@ -24,131 +25,225 @@ impl Display for ParseError {
     }
 }
 
-#[derive(Clone, Debug)]
-struct NumberPart {
-	level: u8,
-	value: usize,
+type NodeId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Node {
+    Regular(usize),
+    Pair(NodeId, NodeId),
 }
 
-#[derive(Clone)]
-struct SnailNumber {
-	parts: Vec<NumberPart>
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side { Left, Right }
+
+/// One step of a reduction, and the path from the outermost pair to where it
+/// happened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Explode { path: Vec<Side>, left: usize, right: usize },
+    Split { path: Vec<Side>, value: usize },
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (path, what) = match self {
+            Self::Explode { path, left, right } => (path, format!("explode [{left},{right}]")),
+            Self::Split { path, value } => (path, format!("split {value}")),
+        };
+        let path: String = path.iter().map(|side| match side { Side::Left => 'L', Side::Right => 'R' }).collect();
+        write!(f, "{what} at {path}")
+    }
+}
+
+// A regular number in reading order, with how deeply it is nested and how to
+// get there.
+struct Leaf {
+    id: NodeId,
+    parent: Option<NodeId>,
+    path: Vec<Side>,
+}
+
+/// A snailfish number, with its pairs and regular numbers stored in an arena.
+/// Nodes that get replaced while reducing stay behind until the number is
+/// added to another one, which only copies what's still in use.
+#[derive(Clone, Debug)]
+pub struct SnailNumber {
+    nodes: Vec<Node>,
+    root: NodeId,
 }
 
 impl TryFrom<&str> for SnailNumber {
-	type Error = ParseError;
-
-	fn try_from(input: &str) -> Result<Self, Self::Error> {
-		let mut parts = Vec::new();
-		let mut level = 0;
-		let mut value = 0;
-		let mut assembling_number = false;
-
-		for c in input.chars() {
-			match c {
-				'[' => {
-						if assembling_number {
-							parts.push(NumberPart{ level, value });
-							value = 0;
-							assembling_number = false;
-						}
-						level += 1;
-					},
-				']' => {
-						if assembling_number {
-							parts.push(NumberPart{ level, value });
-							value = 0;
-							assembling_number = false;
-						}
-						level -= 1;
-					},
-				',' => {
-						if assembling_number {
-							parts.push(NumberPart{ level, value });
-							assembling_number = false;
-						}
-						value = 0;
-					},
-				n if n.is_numeric() => {
-						value = value*10 + n.to_digit(10).unwrap() as usize;
-						assembling_number = true;
-					},
-				t => return Err(Self::Error::InvalidToken(t)),
-			}
-		}
-		Ok(Self{ parts, })
-	}
+    type Error = ParseError;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        let mut nodes = Vec::new();
+        // Nodes whose pair hasn't been closed yet.
+        let mut pending: Vec<NodeId> = Vec::new();
+        let mut open = 0;
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '[' => open += 1,
+                ',' => (),
+                ']' => {
+                    let (Some(right), Some(left)) = (pending.pop(), pending.pop()) else {
+                        return Err(Self::Error::LineMalformed(input.to_string()));
+                    };
+                    open -= 1;
+                    nodes.push(Node::Pair(left, right));
+                    pending.push(nodes.len() - 1);
+                },
+                d if d.is_ascii_digit() => {
+                    let mut number = String::from(d);
+                    while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                        number.push(d);
+                    }
+                    nodes.push(Node::Regular(number.parse()?));
+                    pending.push(nodes.len() - 1);
+                },
+                t => return Err(Self::Error::InvalidToken(t)),
+            }
+        }
+        match pending[..] {
+            [root] if open == 0 => Ok(Self { nodes, root }),
+            _ => Err(Self::Error::LineMalformed(input.to_string())),
+        }
+    }
+}
+
+impl Display for SnailNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_node(f, self.root)
+    }
 }
 
 impl SnailNumber {
-    fn add(&mut self, rhs: &mut Self) {
-        self.parts.append(&mut rhs.parts);
-        self.parts.iter_mut().for_each(|part| part.level += 1);
+    fn fmt_node(&self, f: &mut std::fmt::Formatter<'_>, id: NodeId) -> std::fmt::Result {
+        match self.nodes[id] {
+            Node::Regular(value) => write!(f, "{value}"),
+            Node::Pair(left, right) => {
+                write!(f, "[")?;
+                self.fmt_node(f, left)?;
+                write!(f, ",")?;
+                self.fmt_node(f, right)?;
+                write!(f, "]")
+            },
+        }
+    }
+
+    // Copies the subtree at `id` into `nodes`, and returns where it ended up.
+    fn copy_into(&self, id: NodeId, nodes: &mut Vec<Node>) -> NodeId {
+        let node = match self.nodes[id] {
+            Node::Regular(value) => Node::Regular(value),
+            Node::Pair(left, right) => Node::Pair(self.copy_into(left, nodes), self.copy_into(right, nodes)),
+        };
+        nodes.push(node);
+        nodes.len() - 1
+    }
+
+    /// The pair of both numbers, not reduced yet.
+    pub fn add(&self, rhs: &Self) -> Self {
+        let mut nodes = Vec::new();
+        let left = self.copy_into(self.root, &mut nodes);
+        let right = rhs.copy_into(rhs.root, &mut nodes);
+        nodes.push(Node::Pair(left, right));
+        Self { root: nodes.len() - 1, nodes }
     }
 
-	fn reduce(&mut self) {
-        while self.explode() || self.split() {}
-	}
+    fn leaves(&self) -> Vec<Leaf> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![(self.root, None, Vec::new())];
+        while let Some((id, parent, path)) = stack.pop() {
+            match self.nodes[id] {
+                Node::Regular(_) => leaves.push(Leaf { id, parent, path }),
+                Node::Pair(left, right) => {
+                    let mut right_path = path.clone();
+                    right_path.push(Side::Right);
+                    stack.push((right, Some(id), right_path));
+                    let mut left_path = path;
+                    left_path.push(Side::Left);
+                    stack.push((left, Some(id), left_path));
+                },
+            }
+        }
+        leaves
+    }
+
+    fn value(&self, id: NodeId) -> usize {
+        match self.nodes[id] {
+            Node::Regular(value) => value,
+            Node::Pair(..) => unreachable!("Only regular numbers have a value"),
+        }
+    }
 
-	fn explode(&mut self) -> bool {
-		if let Some(idx) = self.parts.iter().position(|p| p.level > 4) {
-			if idx > 0 {
-				self.parts[idx-1].value += self.parts[idx].value;
+    /// Performs the next action needed to reduce the number, if there is
+    /// one left.
+    pub fn step(&mut self) -> Option<Action> {
+        let leaves = self.leaves();
+        // Regular numbers nested inside four pairs are the left half of a
+        // pair that needs to explode.
+        if let Some(idx) = leaves.iter().position(|leaf| leaf.path.len() > 4) {
+            let (left, right) = (self.value(leaves[idx].id), self.value(leaves[idx + 1].id));
+            if let Some(before) = idx.checked_sub(1) {
+                self.nodes[leaves[before].id] = Node::Regular(self.value(leaves[before].id) + left);
             }
-			if idx < self.parts.len()-2 {
-				self.parts[idx+2].value += self.parts[idx+1].value;
+            if let Some(after) = leaves.get(idx + 2) {
+                self.nodes[after.id] = Node::Regular(self.value(after.id) + right);
             }
-            self.parts[idx].level -= 1;
-            self.parts[idx].value = 0;
-            self.parts.remove(idx+1);
-			true
-		} else {
-			false
-		}
-	}
-
-	fn split(&mut self) -> bool {
-		if let Some(idx) = self.parts.iter().position(|p| p.value > 9) {
-            let (old_level, old_value) = (self.parts[idx].level, self.parts[idx].value);
-			self.parts[idx].value /= 2;
-			self.parts[idx].level += 1;
-			self.parts.insert(idx+1, NumberPart{ level: old_level+1, value: (old_value+1)/2 });
-			true
-		} else {
-			false
-		}
-	}
-
-	fn magnitude(&mut self) -> usize {
-		if self.parts.len() == 1 {
-			self.parts[0].value
-		} else {
-			let idx = self.parts.windows(2).position(|w| w[0].level == w[1].level).expect(&format!("Unable to reduce {:?}", self.parts)[..]);
-			self.parts[idx].value = 3*self.parts[idx].value + 2*self.parts[idx+1].value;
-			self.parts[idx].level = self.parts[idx].level.saturating_sub(1);
-			self.parts.remove(idx+1);
-			self.magnitude()
-		}
-	}
+            let pair = leaves[idx].parent.expect("Nested numbers have a parent");
+            self.nodes[pair] = Node::Regular(0);
+            let mut path = leaves[idx].path.clone();
+            path.pop();
+            return Some(Action::Explode { path, left, right });
+        }
+        let leaf = leaves.into_iter().find(|leaf| self.value(leaf.id) > 9)?;
+        let value = self.value(leaf.id);
+        self.nodes.push(Node::Regular(value / 2));
+        self.nodes.push(Node::Regular(value.div_ceil(2)));
+        self.nodes[leaf.id] = Node::Pair(self.nodes.len() - 2, self.nodes.len() - 1);
+        Some(Action::Split { path: leaf.path, value })
+    }
+
+    pub fn reduce(&mut self) {
+        while self.step().is_some() {}
+    }
+
+    /// Reduces the number, and returns every action taken along with what
+    /// the number looked like afterwards.
+    pub fn reduce_trace(&mut self) -> Vec<(Action, String)> {
+        std::iter::from_fn(|| self.step().map(|action| (action, self.to_string()))).collect()
+    }
+
+    pub fn magnitude(&self) -> usize {
+        self.node_magnitude(self.root)
+    }
+
+    fn node_magnitude(&self, id: NodeId) -> usize {
+        match self.nodes[id] {
+            Node::Regular(value) => value,
+            Node::Pair(left, right) => 3 * self.node_magnitude(left) + 2 * self.node_magnitude(right),
+        }
+    }
 }
 
 pub fn run(input: &str) -> Result<(usize, usize), ParseError> {
-    let mut number = SnailNumber::try_from(input.lines().next().unwrap())?;
-    for line in input.lines().skip(1) {
-        number.add(&mut SnailNumber::try_from(line)?);
-        number.reduce();
-    }
-    let first = number.magnitude();
     let numbers: Vec<_> = input.lines().map(SnailNumber::try_from).collect::<Result<Vec<_>, _>>()?;
-    let mut second = 0;
-    (0..numbers.len()).for_each(|a| (0..numbers.len()).for_each(|b| {
-        if a != b {
-            let mut lhs = numbers[a].clone();
-            lhs.add(&mut numbers[b].clone());
-            lhs.reduce();
-            second = second.max(lhs.magnitude());
-        }
-    }));
+    let Some(mut sum) = numbers.first().cloned() else {
+        return Err(ParseError::LineMalformed(input.to_string()));
+    };
+    for number in &numbers[1..] {
+        sum = sum.add(number);
+        sum.reduce();
+    }
+    let first = sum.magnitude();
+    let second = (0..numbers.len()).into_par_iter().flat_map_iter(|a| {
+        let numbers = &numbers;
+        (0..numbers.len()).filter(move |&b| b != a).map(move |b| {
+            let mut sum = numbers[a].add(&numbers[b]);
+            sum.reduce();
+            sum.magnitude()
+        })
+    }).max().unwrap_or_default();
     Ok((first, second))
 }
 
@@ -167,6 +262,31 @@ mod tests {
         assert_eq!(run(&sample_input), Ok((4140, 3993)));
     }
 
+    #[test]
+    fn test_reduce() {
+        let lhs = SnailNumber::try_from("[[[[4,3],4],4],[7,[[8,4],9]]]").unwrap();
+        let rhs = SnailNumber::try_from("[1,1]").unwrap();
+        let mut sum = lhs.add(&rhs);
+        assert_eq!(sum.to_string(), "[[[[[4,3],4],4],[7,[[8,4],9]]],[1,1]]");
+        let trace: Vec<_> = sum.reduce_trace().into_iter().map(|(action, number)| format!("{action}: {number}")).collect();
+        assert_eq!(trace, [
+            "explode [4,3] at LLLL: [[[[0,7],4],[7,[[8,4],9]]],[1,1]]",
+            "explode [8,4] at LRRL: [[[[0,7],4],[15,[0,13]]],[1,1]]",
+            "split 15 at LRL: [[[[0,7],4],[[7,8],[0,13]]],[1,1]]",
+            "split 13 at LRRR: [[[[0,7],4],[[7,8],[0,[6,7]]]],[1,1]]",
+            "explode [6,7] at LRRR: [[[[0,7],4],[[7,8],[6,0]]],[8,1]]",
+        ]);
+        assert_eq!(sum.magnitude(), 1384);
+        // Magnitude doesn't change the number anymore.
+        assert_eq!(sum.magnitude(), 1384);
+        assert_eq!(sum.to_string(), "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]");
+
+        assert_eq!(SnailNumber::try_from("[[1,2],[12,3]]").unwrap().to_string(), "[[1,2],[12,3]]");
+        assert_eq!(SnailNumber::try_from("[1,2").map(|n| n.to_string()), Err(ParseError::LineMalformed("[1,2".to_string())));
+        assert_eq!(SnailNumber::try_from("[1,2,3]").map(|n| n.to_string()), Err(ParseError::LineMalformed("[1,2,3]".to_string())));
+        assert_eq!(SnailNumber::try_from("[1,x]").map(|n| n.to_string()), Err(ParseError::InvalidToken('x')));
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
@@ -0,0 +1,188 @@
+// Exact integer expressions over a single unknown. Everything that doesn't
+// depend on the unknown is folded into a constant while building, so what's
+// left is the path from the unknown up to the root.
+
+use core::fmt::Display;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SolveError {
+    DivisionByZero,
+    Indeterminate,
+    NoSolution,
+    NonIntegral,
+    Overflow,
+    UnknownMissing,
+    UnknownOnBothSides,
+}
+
+impl Display for SolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::Indeterminate => write!(f, "Every value of the unknown solves the equation"),
+            Self::NoSolution => write!(f, "No value of the unknown solves the equation"),
+            Self::NonIntegral => write!(f, "A division doesn't come out even"),
+            Self::Overflow => write!(f, "Numbers got too large"),
+            Self::UnknownMissing => write!(f, "The equation doesn't contain the unknown"),
+            Self::UnknownOnBothSides => write!(f, "The unknown appears on both sides of the equation"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator { Add, Sub, Mul, Div }
+
+impl Operator {
+    fn precedence(self) -> u8 {
+        match self {
+            Self::Add | Self::Sub => 1,
+            Self::Mul | Self::Div => 2,
+        }
+    }
+
+    /// Applies the operator, insisting that divisions come out even.
+    pub fn apply(self, left: i128, right: i128) -> Result<i128, SolveError> {
+        match self {
+            Self::Add => left.checked_add(right).ok_or(SolveError::Overflow),
+            Self::Sub => left.checked_sub(right).ok_or(SolveError::Overflow),
+            Self::Mul => left.checked_mul(right).ok_or(SolveError::Overflow),
+            Self::Div => exact_div(left, right),
+        }
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Add => write!(f, "+"),
+            Self::Sub => write!(f, "-"),
+            Self::Mul => write!(f, "*"),
+            Self::Div => write!(f, "/"),
+        }
+    }
+}
+
+fn exact_div(left: i128, right: i128) -> Result<i128, SolveError> {
+    match right {
+        0 => Err(SolveError::DivisionByZero),
+        _ if left.checked_rem(right).ok_or(SolveError::Overflow)? != 0 => Err(SolveError::NonIntegral),
+        _ => left.checked_div(right).ok_or(SolveError::Overflow),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Value(i128),
+    Unknown(String),
+    Op(Box<Expr>, Operator, Box<Expr>),
+}
+
+impl Expr {
+    /// Combines two expressions, folding them into a single value unless
+    /// one of them contains the unknown.
+    pub fn combine(left: Self, operator: Operator, right: Self) -> Result<Self, SolveError> {
+        match (left, right) {
+            (Self::Value(l), Self::Value(r)) => Ok(Self::Value(operator.apply(l, r)?)),
+            (_, Self::Value(0)) if operator == Operator::Div => Err(SolveError::DivisionByZero),
+            (l, r) => Ok(Self::Op(Box::new(l), operator, Box::new(r))),
+        }
+    }
+
+    pub fn value(&self) -> Option<i128> {
+        match self {
+            Self::Value(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter<'_>, parent: Operator, right: bool) -> std::fmt::Result {
+        match self {
+            Self::Op(_, op, _) if op.precedence() < parent.precedence()
+                || (right && op.precedence() == parent.precedence() && matches!(parent, Operator::Sub | Operator::Div)) => write!(f, "({self})"),
+            _ => write!(f, "{self}"),
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Value(v) => write!(f, "{v}"),
+            Self::Unknown(name) => write!(f, "{name}"),
+            Self::Op(left, op, right) => {
+                left.fmt_operand(f, *op, false)?;
+                write!(f, " {op} ")?;
+                right.fmt_operand(f, *op, true)
+            },
+        }
+    }
+}
+
+/// Two expressions that must be equal, with the unknown on the left.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Equation {
+    left: Expr,
+    right: Expr,
+}
+
+impl Equation {
+    pub fn new(left: Expr, right: Expr) -> Result<Self, SolveError> {
+        match (left.value(), right.value()) {
+            (None, None) => Err(SolveError::UnknownOnBothSides),
+            (Some(_), Some(_)) => Err(SolveError::UnknownMissing),
+            (Some(_), None) => Ok(Self { left: right, right: left }),
+            (None, Some(_)) => Ok(Self { left, right }),
+        }
+    }
+
+    /// Isolates the unknown by undoing the operations on its side one at a
+    /// time, from the outermost one inwards.
+    pub fn solve(&self) -> Result<i128, SolveError> {
+        let mut side = &self.left;
+        let mut target = self.right.value().ok_or(SolveError::UnknownOnBothSides)?;
+        loop {
+            let (left, op, right) = match side {
+                Expr::Value(_) => return Err(SolveError::UnknownMissing),
+                Expr::Unknown(_) => return Ok(target),
+                Expr::Op(left, op, right) => (left, *op, right),
+            };
+            (side, target) = match (left.value(), right.value()) {
+                (None, None) => return Err(SolveError::UnknownOnBothSides),
+                (Some(_), Some(_)) => return Err(SolveError::UnknownMissing),
+                // x op c = target
+                (None, Some(c)) => (&**left, match op {
+                    Operator::Add => Operator::Sub.apply(target, c)?,
+                    Operator::Sub => Operator::Add.apply(target, c)?,
+                    Operator::Mul => invert_mul(target, c)?,
+                    Operator::Div => Operator::Mul.apply(target, c)?,
+                }),
+                // c op x = target
+                (Some(c), None) => (&**right, match op {
+                    Operator::Add => Operator::Sub.apply(target, c)?,
+                    Operator::Sub => Operator::Sub.apply(c, target)?,
+                    Operator::Mul => invert_mul(target, c)?,
+                    Operator::Div => match (c, target) {
+                        (0, 0) => return Err(SolveError::Indeterminate),
+                        (_, 0) => return Err(SolveError::NoSolution),
+                        _ => exact_div(c, target)?,
+                    },
+                }),
+            };
+        }
+    }
+}
+
+// Solves x * factor = product.
+fn invert_mul(product: i128, factor: i128) -> Result<i128, SolveError> {
+    match (factor, product) {
+        (0, 0) => Err(SolveError::Indeterminate),
+        (0, _) => Err(SolveError::NoSolution),
+        _ => exact_div(product, factor),
+    }
+}
+
+impl Display for Equation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.left, self.right)
+    }
+}
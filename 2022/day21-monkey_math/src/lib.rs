@@ -1,12 +1,18 @@
 use core::fmt::Display;
 use std::num::ParseIntError;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+mod equation;
+pub use equation::{Equation, Expr, Operator, SolveError};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError<'a> {
+    Cycle(&'a str),
     LineMalformed(&'a str),
     MissingRoot,
     ParseIntError(std::num::ParseIntError),
+    UnknownMonkey(&'a str),
+    UnknownOperator(&'a str),
 }
 
 impl From<ParseIntError> for ParseError<'_> {
@@ -15,119 +21,142 @@ impl From<ParseIntError> for ParseError<'_> {
     }
 }
 
+/// Anything that can go wrong from reading the monkeys to solving for `humn`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error<'a> {
+    Parse(ParseError<'a>),
+    Solve(SolveError),
+}
+
+impl<'a> From<ParseError<'a>> for Error<'a> {
+    fn from(value: ParseError<'a>) -> Self {
+        Self::Parse(value)
+    }
+}
+
+impl From<SolveError> for Error<'_> {
+    fn from(value: SolveError) -> Self {
+        Self::Solve(value)
+    }
+}
+
 impl Display for ParseError<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::Cycle(name) => write!(f, "Monkey {name} depends on its own number"),
             Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
             Self::MissingRoot => write!(f, "Input didn't contain a root monkey"),
             Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::UnknownMonkey(name) => write!(f, "Unknown monkey: {name}"),
+            Self::UnknownOperator(op) => write!(f, "Unknown operator: {op}"),
         }
     }
 }
 
-
-#[derive(Clone)]
-enum Operator { Add, Sub, Mul, Div }
-
-#[derive(Clone)]
-struct Operation {
-    left: String,
-    operator: Operator,
-    right: String,
-} 
-
-impl Operation {
-    fn from(left: String, operator: String, right: String) -> Self {
-        Self {
-            left,
-            operator: match &operator[..] {
-                    "+" => Operator::Add,
-                    "-" => Operator::Sub,
-                    "*" => Operator::Mul,
-                    "/" => Operator::Div,
-                    _ => panic!("Unknown Operator: {operator}"),
-                },
-            right,
-        }
-    }
-
-    fn perform(&self, monkeys: &HashMap<String, Monkey>) -> f64 {
-        let left = monkeys.get(&self.left).unwrap().get_number(monkeys);
-        let right = monkeys.get(&self.right).unwrap().get_number(monkeys);
-        match self.operator {
-            Operator::Add => left + right,
-            Operator::Sub => left - right,
-            Operator::Mul => left * right,
-            Operator::Div => left / right,
+impl Display for Error<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "{e}"),
+            Self::Solve(e) => write!(f, "Unable to solve: {e}"),
         }
     }
 }
 
-#[derive(Clone)]
-struct Monkey {
-    number: Option<f64>,
-    operation: Operation,
+enum Job<'a> {
+    Number(i128),
+    Operation(&'a str, Operator, &'a str),
 }
 
-impl Monkey {
-    fn get_number(&self, monkeys: &HashMap<String, Monkey>) -> f64 {
-        if let Some(number) = self.number {
-            number
-        } else {
-            self.operation.perform(monkeys)
-        }
-    }
+struct Monkeys<'a> {
+    jobs: HashMap<&'a str, Job<'a>>,
 }
 
-fn try_parse(input: &str) -> Result<HashMap<String, Monkey>, ParseError> {
-    let mut monkeys = HashMap::new();
-    for line in input.lines() {
-            let components = line.split(' ').collect::<Vec<&str>>();
-            let name = components[0][..components[0].len()-1].to_string();
-            let (number, operation) = match components.len() {
-                2 => (Some(components[1].parse().unwrap()), Operation { left: name.to_string(), operator: Operator::Add, right: "none".to_string() }),
-                4 => (None, Operation::from(components[1].to_string(), components[2].to_string(), components[3].to_string())),
+impl<'a> TryFrom<&'a str> for Monkeys<'a> {
+    type Error = ParseError<'a>;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        let mut jobs = HashMap::new();
+        for line in value.lines() {
+            let Some((name, job)) = line.split_once(": ") else {
+                return Err(ParseError::LineMalformed(line));
+            };
+            let components: Vec<_> = job.split(' ').collect();
+            let job = match components[..] {
+                [number] => Job::Number(number.parse()?),
+                [left, operator, right] => Job::Operation(left, match operator {
+                    "+" => Operator::Add,
+                    "-" => Operator::Sub,
+                    "*" => Operator::Mul,
+                    "/" => Operator::Div,
+                    _ => return Err(ParseError::UnknownOperator(operator)),
+                }, right),
                 _ => return Err(ParseError::LineMalformed(line)),
             };
-            monkeys.insert(name, Monkey { number, operation });
+            jobs.insert(name, job);
         }
-    Ok(monkeys)
+        Ok(Self { jobs })
+    }
 }
 
-fn guess_number(left: Monkey, rigth: Monkey, last_guess: f64, monkeys: &mut HashMap<String, Monkey>) -> isize {
-    monkeys.insert("humn".to_string(), Monkey { number: Some(last_guess), operation: Operation { left: "none".to_string(), operator: Operator::Add, right: "none".to_string() } });
-    let diff0 = rigth.get_number(monkeys) - left.get_number(monkeys);
-
-    if diff0 == 0.0 {
-        return last_guess as isize;
+impl<'a> Monkeys<'a> {
+    fn job(&self, name: &'a str) -> Result<&Job<'a>, ParseError<'a>> {
+        self.jobs.get(name).ok_or(ParseError::UnknownMonkey(name))
     }
-    monkeys.insert("humn".to_string(), Monkey { number: Some(last_guess+1.0), operation: Operation { left: "none".to_string(), operator: Operator::Add, right: "none".to_string() } });
-    let diff1 = rigth.get_number(monkeys) - left.get_number(monkeys);
-    
-    if diff1 == 0.0 {
-        return last_guess as isize + 1;
+
+    // The expression for what `name` yells, with `unknown` left as a variable.
+    fn expression(&self, name: &'a str, unknown: Option<&str>, path: &mut HashSet<&'a str>) -> Result<Expr, Error<'a>> {
+        if Some(name) == unknown {
+            return Ok(Expr::Unknown(name.to_string()));
+        }
+        match self.job(name)? {
+            Job::Number(n) => Ok(Expr::Value(*n)),
+            Job::Operation(left, operator, right) => {
+                if !path.insert(name) {
+                    return Err(ParseError::Cycle(name).into());
+                }
+                let left = self.expression(left, unknown, path)?;
+                let right = self.expression(right, unknown, path)?;
+                path.remove(name);
+                Ok(Expr::combine(left, *operator, right)?)
+            },
+        }
     }
 
-    if diff0 == diff1 {
-        return  guess_number(left, rigth, last_guess-1.0, monkeys);
+    fn number(&self, name: &'a str) -> Result<i128, Error<'a>> {
+        let expression = self.expression(name, None, &mut HashSet::new())?;
+        Ok(expression.value().expect("Expressions without unknowns are always folded"))
     }
 
-    let mut next_guess = (last_guess + diff0/(diff0-diff1)).round();
-    if next_guess == last_guess {
-        next_guess -= 1.0;
+    // What `unknown` needs to yell for both sides of `root` to be equal.
+    fn equation(&self, root: &'a str, unknown: &str) -> Result<Equation, Error<'a>> {
+        let Job::Operation(left, _, right) = self.job(root)? else {
+            return Err(SolveError::UnknownMissing.into());
+        };
+        let mut path = HashSet::from([root]);
+        let left = self.expression(left, Some(unknown), &mut path)?;
+        let right = self.expression(right, Some(unknown), &mut path)?;
+        Ok(Equation::new(left, right)?)
     }
-    guess_number(left, rigth, next_guess, monkeys)
 }
 
-pub fn run(input: &str) -> Result<(isize, isize), ParseError> {
-    let mut monkeys = try_parse(input)?;
-    let root = monkeys.get(&"root".to_string()).ok_or(ParseError::MissingRoot)?;
-    let rl = monkeys.get(&root.operation.left).unwrap().clone();
-    let rr = monkeys.get(&root.operation.right).unwrap().clone();
+/// The equation the human has to solve, simplified as far as possible.
+pub fn equation(input: &str) -> Result<Equation, Error<'_>> {
+    let monkeys = Monkeys::try_from(input)?;
+    if !monkeys.jobs.contains_key("root") {
+        return Err(ParseError::MissingRoot.into());
+    }
+    monkeys.equation("root", "humn")
+}
 
-    let first = root.get_number(&monkeys) as isize;
-    let second = guess_number(rl, rr, 0.0, &mut monkeys);
-    Ok((first, second))
+pub fn run(input: &str) -> Result<(isize, isize), Error<'_>> {
+    let monkeys = Monkeys::try_from(input)?;
+    if !monkeys.jobs.contains_key("root") {
+        return Err(ParseError::MissingRoot.into());
+    }
+    let first = monkeys.number("root")?;
+    let second = monkeys.equation("root", "humn")?.solve()?;
+    let to_isize = |n: i128| isize::try_from(n).map_err(|_| Error::Solve(SolveError::Overflow));
+    Ok((to_isize(first)?, to_isize(second)?))
 }
 
 #[cfg(test)]
@@ -143,6 +172,25 @@ mod tests {
     fn test_sample() {
         let sample_input = read_file("tests/sample_input");
         assert_eq!(run(&sample_input), Ok((152, 301)));
+        assert_eq!(equation(&sample_input).unwrap().to_string(), "(4 + 2 * (humn - 3)) / 4 = 150");
+    }
+
+    #[test]
+    fn test_equation() {
+        let solve = |input| equation(input).and_then(|e| Ok(e.solve()?));
+        // The unknown on the right, and on the right of the operators.
+        assert_eq!(solve("root: a + b\na: 10\nb: c / d\nc: 30\nd: e - humn\ne: 7\nhumn: 1"), Ok(4));
+        assert_eq!(equation("root: a + b\na: 10\nb: c - humn\nc: 30\nhumn: 1").unwrap().to_string(), "30 - humn = 10");
+        assert_eq!(solve("root: a + b\na: humn * b\nb: 2\nhumn: 1"), Ok(1));
+        assert_eq!(solve("root: a + b\na: humn * c\nb: 3\nc: 2\nhumn: 1"), Err(Error::Solve(SolveError::NonIntegral)));
+        assert_eq!(solve("root: a + b\na: humn * c\nb: 3\nc: 0\nhumn: 1"), Err(Error::Solve(SolveError::NoSolution)));
+        assert_eq!(solve("root: a + b\na: humn + c\nb: c * humn\nc: 0\nhumn: 1"), Err(Error::Solve(SolveError::UnknownOnBothSides)));
+        assert_eq!(solve("root: a + b\na: humn + humn\nb: 4\nhumn: 1"), Err(Error::Solve(SolveError::UnknownOnBothSides)));
+        assert_eq!(solve("root: a + b\na: 1\nb: 4\nhumn: 1"), Err(Error::Solve(SolveError::UnknownMissing)));
+        assert_eq!(run("root: a / b\na: 7\nb: 2\nhumn: 1"), Err(Error::Solve(SolveError::NonIntegral)));
+        assert_eq!(run("root: a + b\na: b * humn\nb: a - humn\nhumn: 1"), Err(Error::Parse(ParseError::Cycle("a"))));
+        assert_eq!(run("root: a % b"), Err(Error::Parse(ParseError::UnknownOperator("%"))));
+        assert_eq!(run("root: a + b\na: 1"), Err(Error::Parse(ParseError::UnknownMonkey("b"))));
     }
 
     #[test]
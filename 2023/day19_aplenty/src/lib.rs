@@ -1,18 +1,22 @@
 use core::fmt::Display;
 use std::num::ParseIntError;
-use std::collections::HashMap;
-use std::cmp::Ordering;
 
-const MIN: usize = 1;
-const MAX: usize = 4000;
+pub mod workflows;
+use workflows::{Part, System};
+
+const MIN: i64 = 1;
+const MAX: i64 = 4000;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError<'a> {
-    InvalidCategory(char),
+    Cycle(Vec<String>),
+    DuplicateWorkflow(&'a str),
 	InvalidComparison(char),
     LineMalformed(&'a str),
+    MissingAttribute(String),
     ParseIntError(std::num::ParseIntError),
     RuleMalformed(&'a str),
+    UnknownWorkflow(&'a str),
 	WrongNewLineCount,
 }
 
@@ -22,189 +26,68 @@ impl From<ParseIntError> for ParseError<'_> {
     }
 }
 
+/// Anything that can go wrong from reading the workflows to counting the
+/// accepted combinations.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error<'a> {
+    Parse(ParseError<'a>),
+    VolumeOverflow,
+}
+
+impl<'a> From<ParseError<'a>> for Error<'a> {
+    fn from(value: ParseError<'a>) -> Self {
+        Self::Parse(value)
+    }
+}
+
 impl Display for ParseError<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::InvalidCategory(c) => write!(f, "Parts may only consist of categories x, m, a, and s. Found \"{c}\" instead."),
-            Self::InvalidComparison(c) => write!(f, "Comparisons can only be <, or >. Found {c} instead."),
+            Self::Cycle(w) => write!(f, "Workflows may send parts around in a cycle: {}", w.join(" -> ")),
+            Self::DuplicateWorkflow(w) => write!(f, "Workflow {w} is defined more than once."),
+            Self::InvalidComparison(c) => write!(f, "Comparisons can only be <, <=, >, >=, or =. Found a stray {c}."),
             Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
+            Self::MissingAttribute(a) => write!(f, "Part has no rating for {a}"),
             Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
             Self::RuleMalformed(v) => write!(f, "Rule is malformed: {v}"),
+            Self::UnknownWorkflow(w) => write!(f, "No workflow called {w} exists."),
             Self::WrongNewLineCount => write!(f, "Input does not consist of two parts separated by an empty line."),
         }
     }
 }
 
-struct Rule {
-	category: usize,
-	comparison: Ordering,
-	value: usize,
-	true_id: usize,
-}
-
-impl Rule {
-    fn applies_to(&self, part: &Part) -> bool {
-        part.components[self.category].cmp(&self.value) == self.comparison
-    }
-}
-
-struct Workflow {
-	id: usize,
-	rules: Vec<Rule>,
-}
-
-impl Workflow {
-    fn next(&self, part: &Part) -> usize {
-        for rule in &self.rules {
-            if rule.applies_to(part) {
-                return rule.true_id;
-            }
+impl Display for Error<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "{e}"),
+            Self::VolumeOverflow => write!(f, "There are too many accepted combinations to count in 128 bits"),
         }
-        self.rules.last().unwrap().true_id
     }
 }
 
-struct Part {
-	components: [usize; 4],
-}
-
-impl<'a> TryFrom<&'a str> for Part {
-    type Error = ParseError<'a>;
-
-    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
-		let components: Vec<_> = value.split(['{', '=', ',', '}']).collect();
-		if components.len() != 10 {
-			return Err(Self::Error::LineMalformed(value));
-		}
-		let x = components[2].parse()?;
-		let m = components[4].parse()?;
-		let a = components[6].parse()?;
-		let s = components[8].parse()?;
-		
-		Ok(Self{ components: [x, m, a, s], })
-    }
-}
-
-pub fn run(input: &str) -> Result<(usize, usize), ParseError> {
+pub fn run(input: &str) -> Result<(i64, u128), Error<'_>> {
     let input_parts: Vec<_> = input.split("\n\n").collect();
 	if input_parts.len() != 2 {
-		return Err(ParseError::WrongNewLineCount);
+		return Err(ParseError::WrongNewLineCount.into());
 	}
-	let workflows = parse_workflows(input_parts[0])?;
+	let mut system = System::parse(input_parts[0], "in")?;
 	let parts: Vec<_> = input_parts[1].lines().map(Part::try_from).collect::<Result<Vec<_>, ParseError>>()?;
-    let first = parts.iter().filter(|part| {
-                    let mut queue = 0; 
-                    loop { 
-                        queue = workflows[queue].next(part);
-                        if queue < 3 {
-                            break;
-                        }
-                    }
-                    queue == 1
-            }).map(|part| part.components.iter().sum::<usize>()).sum();
-    let second = find_ranges(&workflows, 0, &[(MIN, MAX); 4]).iter()
-						.map(|range| range.iter().map(|(lower, upper)| upper+1-lower).product::<usize>())
-						.sum();
-    Ok((first, second))
-}
-
-fn find_ranges(workflows: &[Workflow], current_rule: usize, in_ranges: &[(usize, usize); 4]) -> Vec<[(usize, usize); 4]> {
-	if in_ranges.iter().any(|(lower, upper)| lower > upper) {
-		return Vec::new();
-	}
-	match current_rule {
-		1 => vec![*in_ranges],
-		2 => Vec::new(),
-		_ => {
-			let mut res = Vec::new();
-			let mut ranges_left = *in_ranges;
-			workflows[current_rule].rules.iter().for_each(|rule| {
-				let cat = rule.category;
-				let cmp = rule.comparison;
-				let val = rule.value;
-				let mut out_ranges = ranges_left;
-				match cmp {
-					Ordering::Less => out_ranges[cat].1 = out_ranges[cat].1.min(val-1),
-					Ordering::Greater => out_ranges[cat].0 = out_ranges[cat].0.max(val+1),
-					Ordering::Equal => unreachable!("Equality is not implemented"),
-				};
-				find_ranges(workflows, rule.true_id, &out_ranges).iter().for_each(|range| {
-					res.push(*range);
-				});
-				match cmp {
-					Ordering::Less => ranges_left[cat].0 = ranges_left[cat].0.max(val),
-					Ordering::Greater => ranges_left[cat].1 = ranges_left[cat].1.min(val),
-					Ordering::Equal => unreachable!("Equality is not implemented"),
-				};
-			});
-			res
-		},
-	}
-}
-
-fn parse_workflows(input: &str) -> Result<Vec<Workflow>, ParseError> {
-	let mut ids = HashMap::from([("in", 0), ("A", 1), ("R", 2)]);
-	let mut workflows = Vec::from([Workflow{id: 1, rules: Vec::new()}, Workflow{id: 2, rules: Vec::new()},]);
-	for line in input.lines() {
-		let components: Vec<_> = line.split(['{', ':', ',', '}']).collect();
-		let name = components[0];
-		let id = match ids.get(name) {
-			Some(i) => *i,
-			None => {
-					let l = ids.len();
-					ids.insert(name, l);
-					l
-				},
-		};
-        let mut rules = Vec::new();
-        for c in components[1..components.len()-2].chunks(2) {
-            let (condition, dest) = (c[0], c[1]);
-            if condition.len() < 3 {
-                return Err(ParseError::RuleMalformed(condition));
-            }
-            let category = match condition.chars().next() {
-                Some('x') => Ok(0),
-                Some('m') => Ok(1),
-                Some('a') => Ok(2),
-                Some('s') => Ok(3),
-                Some(e) => Err(ParseError::InvalidCategory(e)),
-                None => Err(ParseError::RuleMalformed(condition)),
-            }?;
-            let comparison = match condition.chars().nth(1) {
-                Some('<') => Ok(Ordering::Less),
-                Some('>') => Ok(Ordering::Greater),
-                Some(e) => Err(ParseError::InvalidComparison(e)),
-                None => Err(ParseError::RuleMalformed(condition)),
-            }?;
-            let value = condition[2..].parse()?;
-            let true_id = match ids.get(dest) {
-                Some(i) => *i,
-                None => {
-                    let l = ids.len();
-                    ids.insert(dest, l);
-                    l
-                },
-            };
-            rules.push(Rule{ category, comparison, value, true_id });
+    let mut first = 0;
+    for part in &parts {
+        if system.accepts(part)? {
+            first += part.total();
         }
-        let default = components[components.len()-2];
-        let true_id = match ids.get(default) {
-            Some(i) => *i,
-            None => {
-                    let l = ids.len();
-                    ids.insert(default, l);
-                    l
-                },
-        };
-		// Last rule is default. Since all components are guaranteed to have values in [1..=4000], this comparison is always true.
-        rules.push(Rule{ category: 0, comparison: Ordering::Less, value: usize::MAX, true_id });
-        workflows.push(Workflow { id, rules, });
-	}
-	workflows.sort_by_key(|workflow| workflow.id);
-	Ok(workflows)
+    }
+    // Ratings the workflows never look at still count towards the combinations.
+    for part in &parts {
+        for name in part.names() {
+            system.add_attribute(name);
+        }
+    }
+    let second = system.accepted_volume(&vec![(MIN, MAX); system.attributes().len()]).ok_or(Error::VolumeOverflow)?;
+    Ok((first, second))
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -223,6 +106,48 @@ mod tests {
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");
-        assert_eq!(run(&challenge_input), Ok((446517, 0)));
+        assert_eq!(run(&challenge_input), Ok((446517, 130090458884662)));
+    }
+
+    #[test]
+    fn test_workflows() {
+        let input = "in{a<=10:lo,a>=90:hi,b=5:A,R}\nlo{a=3:A,R}\nhi{b<0:A,R}\ndead{a>5:A,R}";
+        let system = System::parse(input, "in").unwrap();
+        assert_eq!(system.attributes(), ["a", "b"]);
+
+        let part = |line| Part::try_from(line).unwrap();
+        assert_eq!(system.accepts(&part("{a=3,b=7}")), Ok(true));
+        assert_eq!(system.accepts(&part("{b=5,a=50}")), Ok(true));
+        assert_eq!(system.accepts(&part("{a=50,b=4}")), Ok(false));
+        assert_eq!(system.accepts(&part("{a=3}")), Err(ParseError::MissingAttribute("b".to_string())));
+
+        let domain = vec![(1, 100); 2];
+        let mut accepted = system.accepted(&domain);
+        accepted.sort();
+        assert_eq!(accepted, [vec![(3, 3), (1, 100)], vec![(11, 89), (5, 5)]]);
+        assert_eq!(system.accepted_volume(&domain), Some(179));
+        assert_eq!(system.analyse(&domain), workflows::Analysis {
+            unreachable: vec!["dead".to_string()],
+            always_rejecting: vec!["hi".to_string()],
+        });
+
+        let mut system = System::parse("in{x>0:A,R}", "in").unwrap();
+        system.add_attribute("y");
+        system.add_attribute("z");
+        assert_eq!(system.accepted_volume(&vec![(1, 1 << 40); 3]), Some(1 << 120));
+        assert_eq!(system.accepted_volume(&vec![(1, 1 << 43); 3]), None);
+        assert_eq!(system.accepted_volume(&vec![(i64::MIN, i64::MAX); 3]), None);
+        let mut everything = System::parse("in{x>0:A,A}", "in").unwrap();
+        everything.add_attribute("y");
+        assert_eq!(everything.accepted_volume(&vec![(i64::MIN, i64::MAX); 2]), None);
+        assert_eq!(everything.accepted_volume(&vec![(i64::MIN, i64::MAX - 1); 2]), Some((u64::MAX as u128).pow(2)));
+        assert!(system.to_dot().contains("\"in\" -> \"A\" [label=\"x>0\"];"));
+
+        let cycle = ["in", "b", "c", "in"].map(String::from).to_vec();
+        assert_eq!(System::parse("in{a<5:b,A}\nb{c}\nc{in}", "in"), Err(ParseError::Cycle(cycle)));
+        assert_eq!(System::parse("in{a<-9223372036854775808:A,R}", "in"), Err(ParseError::RuleMalformed("a<-9223372036854775808")));
+        assert_eq!(System::parse("in{a>9223372036854775807:A,R}", "in"), Err(ParseError::RuleMalformed("a>9223372036854775807")));
+        assert_eq!(System::parse("in{a<5:nowhere,A}", "in"), Err(ParseError::UnknownWorkflow("nowhere")));
+        assert_eq!(System::parse("in{a<<5:A,R}", "in"), Err(ParseError::InvalidComparison('<')));
     }
 }
@@ -0,0 +1,357 @@
+// A decision tree over parts with any number of named ratings. Each workflow
+// checks its rules in order and sends the part on to wherever the first
+// matching rule points. Sets of parts are handled as hyper-rectangles of
+// rating ranges, which the rules split into the parts they match and those
+// they don't.
+
+use core::fmt::Display;
+use std::collections::HashMap;
+
+use crate::ParseError;
+
+/// Inclusive ranges of every rating, in the order of `System::attributes`.
+pub type Rect = Vec<(i64, i64)>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison { Less, LessEq, Greater, GreaterEq, Equal }
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Less => write!(f, "<"),
+            Self::LessEq => write!(f, "<="),
+            Self::Greater => write!(f, ">"),
+            Self::GreaterEq => write!(f, ">="),
+            Self::Equal => write!(f, "="),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub attribute: usize,
+    pub comparison: Comparison,
+    pub value: i64,
+}
+
+impl Condition {
+    pub fn matches(&self, rating: i64) -> bool {
+        match self.comparison {
+            Comparison::Less => rating < self.value,
+            Comparison::LessEq => rating <= self.value,
+            Comparison::Greater => rating > self.value,
+            Comparison::GreaterEq => rating >= self.value,
+            Comparison::Equal => rating == self.value,
+        }
+    }
+
+    /// Splits `rect` into the part that matches (if any), and up to two
+    /// parts that don't.
+    fn split(&self, rect: &Rect) -> (Option<Rect>, Vec<Rect>) {
+        let (lo, hi) = rect[self.attribute];
+        let v = self.value;
+        // Ranges bounded by a value beyond what i64 holds are empty.
+        let up_to = |bound: Option<i64>| bound.map_or((1, 0), |bound| (lo, hi.min(bound)));
+        let from = |bound: Option<i64>| bound.map_or((1, 0), |bound| (lo.max(bound), hi));
+        let (below, above) = (v.checked_sub(1), v.checked_add(1));
+        let (matching, rest) = match self.comparison {
+            Comparison::Less => (up_to(below), vec![from(Some(v))]),
+            Comparison::LessEq => (up_to(Some(v)), vec![from(above)]),
+            Comparison::Greater => (from(above), vec![up_to(Some(v))]),
+            Comparison::GreaterEq => (from(Some(v)), vec![up_to(below)]),
+            Comparison::Equal => ((lo.max(v), hi.min(v)), vec![up_to(below), from(above)]),
+        };
+        let with = |range: (i64, i64)| (range.0 <= range.1).then(|| {
+            let mut rect = rect.clone();
+            rect[self.attribute] = range;
+            rect
+        });
+        (with(matching), rest.into_iter().filter_map(with).collect())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Accept,
+    Reject,
+    Workflow(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Workflow {
+    pub name: String,
+    pub rules: Vec<(Condition, Target)>,
+    pub default: Target,
+}
+
+/// A part with named ratings, like `{x=787,m=2655,a=1222,s=2876}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Part<'a> {
+    ratings: Vec<(&'a str, i64)>,
+}
+
+impl<'a> TryFrom<&'a str> for Part<'a> {
+    type Error = ParseError<'a>;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        let Some(inner) = value.strip_prefix('{').and_then(|v| v.strip_suffix('}')) else {
+            return Err(Self::Error::LineMalformed(value));
+        };
+        let ratings = inner.split(',').map(|rating| match rating.split_once('=') {
+            Some((name, v)) => Ok((name, v.parse()?)),
+            None => Err(Self::Error::LineMalformed(value)),
+        }).collect::<Result<_, _>>()?;
+        Ok(Self { ratings })
+    }
+}
+
+impl Part<'_> {
+    pub fn rating(&self, name: &str) -> Option<i64> {
+        self.ratings.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.ratings.iter().map(|(name, _)| *name)
+    }
+
+    pub fn total(&self) -> i64 {
+        self.ratings.iter().map(|(_, v)| v).sum()
+    }
+}
+
+/// Workflows that can never do anything useful, found by `System::analyse`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Analysis {
+    /// Workflows that no part within the domain ever reaches.
+    pub unreachable: Vec<String>,
+    /// Workflows that reject every part within the domain sent to them.
+    pub always_rejecting: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct System {
+    attributes: Vec<String>,
+    workflows: Vec<Workflow>,
+    start: usize,
+}
+
+fn parse_condition(condition: &str) -> Result<(&str, Comparison, i64), ParseError<'_>> {
+    let Some(op_start) = condition.find(['<', '>', '=']) else {
+        return Err(ParseError::RuleMalformed(condition));
+    };
+    let (attribute, rest) = condition.split_at(op_start);
+    let (comparison, value) = match rest.as_bytes() {
+        [b'<', b'=', ..] => (Comparison::LessEq, &rest[2..]),
+        [b'>', b'=', ..] => (Comparison::GreaterEq, &rest[2..]),
+        [b'<', ..] => (Comparison::Less, &rest[1..]),
+        [b'>', ..] => (Comparison::Greater, &rest[1..]),
+        _ => (Comparison::Equal, &rest[1..]),
+    };
+    if attribute.is_empty() || !attribute.chars().all(char::is_alphanumeric) {
+        return Err(ParseError::RuleMalformed(condition));
+    }
+    if let Some(c) = value.chars().find(|c| matches!(c, '<' | '>' | '=')) {
+        return Err(ParseError::InvalidComparison(c));
+    }
+    let value: i64 = value.parse()?;
+    // Splitting ranges needs the neighbours of the value too.
+    if value.checked_sub(1).is_none() || value.checked_add(1).is_none() {
+        return Err(ParseError::RuleMalformed(condition));
+    }
+    Ok((attribute, comparison, value))
+}
+
+impl System {
+    /// Reads workflows like `px{a<2006:qkq,m>2090:A,rfg}`, one per line.
+    /// Parts enter at the workflow called `start`.
+    pub fn parse<'a>(input: &'a str, start: &'a str) -> Result<Self, ParseError<'a>> {
+        let mut ids: HashMap<&str, usize> = HashMap::new();
+        let mut bodies = Vec::new();
+        for line in input.lines() {
+            let Some((name, body)) = line.strip_suffix('}').and_then(|l| l.split_once('{')) else {
+                return Err(ParseError::LineMalformed(line));
+            };
+            if ids.insert(name, bodies.len()).is_some() {
+                return Err(ParseError::DuplicateWorkflow(name));
+            }
+            bodies.push((name, body));
+        }
+        let target = |name: &'a str| match name {
+            "A" => Ok(Target::Accept),
+            "R" => Ok(Target::Reject),
+            _ => ids.get(name).map(|&id| Target::Workflow(id)).ok_or(ParseError::UnknownWorkflow(name)),
+        };
+
+        let mut system = Self { attributes: Vec::new(), workflows: Vec::new(), start: 0 };
+        for (name, body) in bodies {
+            let mut steps: Vec<_> = body.split(',').collect();
+            let default = target(steps.pop().unwrap_or_default())?;
+            let rules = steps.into_iter().map(|rule| {
+                let (condition, destination) = rule.split_once(':').ok_or(ParseError::RuleMalformed(rule))?;
+                let (attribute, comparison, value) = parse_condition(condition)?;
+                let condition = Condition { attribute: system.add_attribute(attribute), comparison, value };
+                Ok((condition, target(destination)?))
+            }).collect::<Result<_, ParseError>>()?;
+            system.workflows.push(Workflow { name: name.to_string(), rules, default });
+        }
+        system.start = *ids.get(start).ok_or(ParseError::UnknownWorkflow(start))?;
+        if let Some(cycle) = system.find_cycle() {
+            return Err(ParseError::Cycle(cycle.into_iter().map(|id| system.workflows[id].name.clone()).collect()));
+        }
+        Ok(system)
+    }
+
+    /// Makes sure there's an attribute called `name`, and returns its index.
+    pub fn add_attribute(&mut self, name: &str) -> usize {
+        self.attribute(name).unwrap_or_else(|| {
+            self.attributes.push(name.to_string());
+            self.attributes.len() - 1
+        })
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<usize> {
+        self.attributes.iter().position(|a| a == name)
+    }
+
+    pub fn attributes(&self) -> &[String] {
+        &self.attributes
+    }
+
+    pub fn workflows(&self) -> &[Workflow] {
+        &self.workflows
+    }
+
+    fn successors(&self, id: usize) -> impl Iterator<Item = usize> + '_ {
+        let workflow = &self.workflows[id];
+        workflow.rules.iter().map(|(_, t)| t).chain([&workflow.default]).filter_map(|t| match t {
+            Target::Workflow(w) => Some(*w),
+            _ => None,
+        })
+    }
+
+    // Returns the workflows around some cycle, starting and ending with the
+    // same one, if there is a cycle.
+    fn find_cycle(&self) -> Option<Vec<usize>> {
+        // 0: not visited yet, 1: on the current path, 2: done
+        let mut state = vec![0_u8; self.workflows.len()];
+        for root in 0..self.workflows.len() {
+            if state[root] != 0 {
+                continue;
+            }
+            let mut stack = vec![(root, self.successors(root).collect::<Vec<_>>())];
+            state[root] = 1;
+            while let Some((id, next)) = stack.last_mut() {
+                match next.pop() {
+                    Some(n) if state[n] == 1 => {
+                        let start = stack.iter().position(|&(id, _)| id == n).unwrap_or_default();
+                        return Some(stack[start..].iter().map(|&(id, _)| id).chain([n]).collect());
+                    },
+                    Some(n) if state[n] == 0 => {
+                        state[n] = 1;
+                        stack.push((n, self.successors(n).collect()));
+                    },
+                    Some(_) => (),
+                    None => {
+                        state[*id] = 2;
+                        stack.pop();
+                    },
+                }
+            }
+        }
+        None
+    }
+
+    pub fn accepts<'a>(&self, part: &Part) -> Result<bool, ParseError<'a>> {
+        let ratings = self.attributes.iter()
+            .map(|a| part.rating(a).ok_or_else(|| ParseError::MissingAttribute(a.clone())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut current = self.start;
+        loop {
+            let workflow = &self.workflows[current];
+            let target = workflow.rules.iter()
+                .find(|(condition, _)| condition.matches(ratings[condition.attribute]))
+                .map_or(workflow.default, |(_, target)| *target);
+            match target {
+                Target::Accept => return Ok(true),
+                Target::Reject => return Ok(false),
+                Target::Workflow(next) => current = next,
+            }
+        }
+    }
+
+    // Sends `domain` through the workflows, starting at `from`. Returns the
+    // accepted rectangles, and which workflows saw any parts at all.
+    fn propagate(&self, from: usize, domain: &Rect) -> (Vec<Rect>, Vec<bool>) {
+        let mut accepted = Vec::new();
+        let mut reached = vec![false; self.workflows.len()];
+        let mut pending = vec![(from, domain.clone())];
+        while let Some((id, rect)) = pending.pop() {
+            reached[id] = true;
+            let workflow = &self.workflows[id];
+            let mut send = |target: Target, rect: Rect| match target {
+                Target::Accept => accepted.push(rect),
+                Target::Reject => (),
+                Target::Workflow(next) => pending.push((next, rect)),
+            };
+            let mut remaining = vec![rect];
+            for (condition, target) in &workflow.rules {
+                let mut rest = Vec::new();
+                for rect in remaining {
+                    let (matching, others) = condition.split(&rect);
+                    if let Some(matching) = matching {
+                        send(*target, matching);
+                    }
+                    rest.extend(others);
+                }
+                remaining = rest;
+            }
+            remaining.into_iter().for_each(|rect| send(workflow.default, rect));
+        }
+        (accepted, reached)
+    }
+
+    /// Every accepted part within `domain`, as disjoint hyper-rectangles.
+    pub fn accepted(&self, domain: &Rect) -> Vec<Rect> {
+        self.propagate(self.start, domain).0
+    }
+
+    /// How many distinct parts within `domain` are accepted, or `None` if
+    /// that doesn't fit into a `u128`.
+    pub fn accepted_volume(&self, domain: &Rect) -> Option<u128> {
+        self.accepted(domain).iter().try_fold(0_u128, |total, rect| {
+            let volume = rect.iter().try_fold(1_u128, |volume, &(lo, hi)| volume.checked_mul((hi as i128 - lo as i128 + 1) as u128))?;
+            total.checked_add(volume)
+        })
+    }
+
+    pub fn analyse(&self, domain: &Rect) -> Analysis {
+        let (_, reached) = self.propagate(self.start, domain);
+        let unreachable = self.workflows.iter().zip(reached)
+            .filter(|(_, reached)| !reached)
+            .map(|(w, _)| w.name.clone())
+            .collect();
+        let always_rejecting = (0..self.workflows.len())
+            .filter(|&id| self.propagate(id, domain).0.is_empty())
+            .map(|id| self.workflows[id].name.clone())
+            .collect();
+        Analysis { unreachable, always_rejecting }
+    }
+
+    /// The workflows as a Graphviz DOT graph, with one edge per rule.
+    pub fn to_dot(&self) -> String {
+        let node = |target: &Target| match target {
+            Target::Accept => "A".to_string(),
+            Target::Reject => "R".to_string(),
+            Target::Workflow(id) => self.workflows[*id].name.clone(),
+        };
+        let mut dot = String::from("digraph workflows {\n    \"A\" [shape=doublecircle];\n    \"R\" [shape=box];\n");
+        for workflow in &self.workflows {
+            for (condition, target) in &workflow.rules {
+                let label = format!("{}{}{}", self.attributes[condition.attribute], condition.comparison, condition.value);
+                dot += &format!("    \"{}\" -> \"{}\" [label=\"{label}\"];\n", workflow.name, node(target));
+            }
+            dot += &format!("    \"{}\" -> \"{}\" [style=dashed];\n", workflow.name, node(&workflow.default));
+        }
+        dot + "}\n"
+    }
+}
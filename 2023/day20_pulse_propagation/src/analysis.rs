@@ -0,0 +1,174 @@
+// Pressing the button until `rx` receives a low pulse takes far too long, but
+// the networks in front of it are built from binary counters:
+//
+//     broadcaster -> f0 -> f1 -> ... -> fk
+//
+// Each flip-flop in such a chain is one bit, as it flips whenever the one
+// before it turns off. The bits set in the counter's period feed a conjunction,
+// the hub. Once they are all on, the hub sends a low pulse to f0 and to every
+// bit that is not set, which carries the counter over to 0. On its way to
+// `rx`, that pulse is turned high by an inverter, and the conjunction in front
+// of `rx` only sends a low pulse once all counters roll over during the same
+// press.
+
+use core::fmt::Display;
+
+use crate::{ModuleType, Network, BROADCASTER, RX};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StructureError {
+    MultipleInputs(String),
+    NoInput(String),
+    NotConjunction(String),
+    NotCounter(String),
+    NotInverter(String),
+    PeriodOverflow,
+}
+
+impl Display for StructureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MultipleInputs(m) => write!(f, "{m} should have a single input"),
+            Self::NoInput(m) => write!(f, "Nothing sends pulses to {m}"),
+            Self::NotConjunction(m) => write!(f, "{m} should be a conjunction"),
+            Self::NotCounter(m) => write!(f, "The flip-flops around {m} don't form a binary counter"),
+            Self::NotInverter(m) => write!(f, "{m} should be a conjunction with a single input, inverting it"),
+            Self::PeriodOverflow => write!(f, "The counters only roll over together after more presses than fit into a usize"),
+        }
+    }
+}
+
+/// One of the sub-circuits feeding `rx`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Counter {
+    /// The conjunction reading the counter.
+    pub hub: String,
+    /// The conjunction passing the hub's pulses on towards `rx`.
+    pub inverter: String,
+    /// The flip-flops, least significant bit first.
+    pub bits: Vec<String>,
+    /// Every how many presses the counter rolls over.
+    pub period: usize,
+}
+
+impl Network<'_> {
+    fn inputs(&self) -> Vec<Vec<usize>> {
+        let mut inputs = vec![Vec::new(); self.modules.len()];
+        for module in &self.modules {
+            for &output in &module.outputs {
+                if !inputs[output].contains(&module.id) {
+                    inputs[output].push(module.id);
+                }
+            }
+        }
+        inputs
+    }
+
+    fn is_conjunction(&self, id: usize) -> bool {
+        matches!(self.modules[id].module_type, ModuleType::Conjunction(_))
+    }
+
+    fn is_flip_flop(&self, id: usize) -> bool {
+        matches!(self.modules[id].module_type, ModuleType::FlipFlop(_))
+    }
+
+    /// Splits the modules feeding `rx` into binary counters, or explains why
+    /// that isn't possible.
+    pub fn counters(&self) -> Result<Vec<Counter>, StructureError> {
+        let inputs = self.inputs();
+        let name = |id: usize| self.names[id].to_string();
+        let collector = match inputs[RX][..] {
+            [] => return Err(StructureError::NoInput(name(RX))),
+            [id] if self.is_conjunction(id) => id,
+            [id] => return Err(StructureError::NotConjunction(name(id))),
+            _ => return Err(StructureError::MultipleInputs(name(RX))),
+        };
+        if inputs[collector].is_empty() {
+            return Err(StructureError::NoInput(name(collector)));
+        }
+        inputs[collector].iter().map(|&inverter| {
+            let hub = match inputs[inverter][..] {
+                [hub] if self.is_conjunction(inverter) && self.modules[inverter].outputs == [collector] => hub,
+                _ => return Err(StructureError::NotInverter(name(inverter))),
+            };
+            if !self.is_conjunction(hub) {
+                return Err(StructureError::NotConjunction(name(hub)));
+            }
+            self.counter(hub, inverter, &inputs)
+        }).collect()
+    }
+
+    fn counter(&self, hub: usize, inverter: usize, inputs: &[Vec<usize>]) -> Result<Counter, StructureError> {
+        let not_counter = || StructureError::NotCounter(self.names[hub].to_string());
+        let hub_outputs = &self.modules[hub].outputs;
+        let (fed, others): (Vec<usize>, Vec<usize>) = hub_outputs.iter().partition(|&&id| self.is_flip_flop(id));
+        if others != [inverter] || !inputs[hub].iter().all(|&id| self.is_flip_flop(id)) {
+            return Err(not_counter());
+        }
+        let first = match self.modules[BROADCASTER].outputs.iter()
+            .filter(|id| fed.contains(id) || inputs[hub].contains(id))
+            .collect::<Vec<_>>()[..]
+        {
+            [&first] => first,
+            _ => return Err(not_counter()),
+        };
+
+        let (mut bits, mut period, mut feeding, mut prev, mut current) = (Vec::new(), 0, 0, BROADCASTER, Some(first));
+        while let Some(id) = current {
+            let bit = bits.len();
+            if !self.is_flip_flop(id) || bit + 1 >= usize::BITS as usize || !inputs[id].contains(&prev) || inputs[id].iter().any(|&i| i != prev && i != hub) {
+                return Err(not_counter());
+            }
+            let feeds = self.modules[id].outputs.contains(&hub);
+            // The first bit is always set, and gets the hub's pulse that
+            // carries the counter over. Other bits are reset by it if they
+            // aren't set.
+            let valid = if bit == 0 { feeds && fed.contains(&id) } else { feeds != fed.contains(&id) };
+            if !valid {
+                return Err(not_counter());
+            }
+            if feeds {
+                period |= 1 << bit;
+                feeding += 1;
+            }
+            current = match self.modules[id].outputs.iter().filter(|&&o| o != hub).collect::<Vec<_>>()[..] {
+                [] => None,
+                [&next] => Some(next),
+                _ => return Err(not_counter()),
+            };
+            bits.push(self.names[id].to_string());
+            prev = id;
+        }
+        // The most significant bit must be set, and the hub mustn't be wired
+        // to any flip-flop outside of the chain.
+        if !self.modules[prev].outputs.contains(&hub) || feeding != inputs[hub].len() || bits.len() + 1 - feeding != fed.len() {
+            return Err(not_counter());
+        }
+        Ok(Counter { hub: self.names[hub].to_string(), inverter: self.names[inverter].to_string(), bits, period })
+    }
+
+    /// The module graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let inputs = self.inputs();
+        let mut dot = String::from("digraph modules {\n");
+        for module in &self.modules {
+            let name = self.names[module.id];
+            if module.outputs.is_empty() && inputs[module.id].is_empty() {
+                continue;
+            }
+            let (label, shape) = match module.module_type {
+                ModuleType::FlipFlop(_) => (format!("%{name}"), "box"),
+                ModuleType::Conjunction(_) => (format!("&{name}"), "ellipse"),
+                ModuleType::Broadcast => (name.to_string(), "doubleoctagon"),
+                ModuleType::Sink => (name.to_string(), "doublecircle"),
+            };
+            dot += &format!("    \"{name}\" [label=\"{label}\", shape={shape}];\n");
+        }
+        for module in &self.modules {
+            for &output in &module.outputs {
+                dot += &format!("    \"{}\" -> \"{}\";\n", self.names[module.id], self.names[output]);
+            }
+        }
+        dot + "}\n"
+    }
+}
@@ -1,10 +1,18 @@
 use core::fmt::Display;
 use std::collections::HashMap;
 
+pub mod analysis;
+use analysis::StructureError;
+
+// Part 2 falls back to pressing the button this often if the network isn't
+// built from counters.
+const SIMULATION_LIMIT: usize = 100_000;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError<'a> {
 	InvalidType(char),
     LineMalformed(&'a str),
+    Structure(StructureError),
 }
 
 impl Display for ParseError<'_> {
@@ -12,6 +20,7 @@ impl Display for ParseError<'_> {
         match self {
             Self::InvalidType(c) => write!(f, "Module types can only be %, &, or broadcaster. Found {c} instead."),
             Self::LineMalformed(v) => write!(f, "Line is malformed: {v}"),
+            Self::Structure(e) => write!(f, "Unable to tell when rx receives a low pulse: {e}"),
         }
     }
 }
@@ -24,6 +33,8 @@ enum ModuleType {
 	FlipFlop(Signal),
 	Conjunction(HashMap<usize, Signal>),
 	Broadcast,
+	// Modules that are only ever sent pulses to, like `output` and `rx`.
+	Sink,
 }
 
 #[derive(Clone)]
@@ -33,41 +44,56 @@ struct Module {
 	outputs: Vec<usize>,
 }
 
-pub fn run(input: &str) -> Result<(usize, usize), ParseError> {
-    let mut modules = try_parse_modules(input)?;
-    let mut results = Vec::new();
-	let (mut low_count, mut high_count, mut rx_idx) = (0, 0, 0);
-    let rx_trigger = modules.iter().find(|m| m.outputs.contains(&1)).map(|m| m.id).unwrap();
-    let trigger_triggers: Vec<_> = modules.iter().filter(|m| m.outputs.contains(&rx_trigger)).map(|m| m.id).collect();
-    let mut trigger_trigger_indexes = vec![0; trigger_triggers.len()];
-
-	for press in 0.. {
-		let this = push_button(&mut modules, &trigger_triggers);
-        if press < 1000 {
-            low_count += this.0;
-            high_count += this.1;
-            results.push((low_count, high_count));
-        }
-        this.2.iter().enumerate().filter(|(_idx, res)| **res).for_each(|(idx, _res)| {
-            if trigger_trigger_indexes[idx] == 0 {
-                trigger_trigger_indexes[idx] = press+1;
-                if trigger_trigger_indexes.iter().all(|count| count > &0) {
-                    rx_idx = trigger_trigger_indexes.iter().cloned().reduce(lcm).unwrap();
-                }
-            }
-        });
-        if press >= 1000 && rx_idx > 0 {
-            break;
-        }
-	}
+const RX: usize = 1;
+const BROADCASTER: usize = 2;
+
+#[derive(Clone)]
+pub struct Network<'a> {
+    modules: Vec<Module>,
+    names: Vec<&'a str>,
+}
+
+impl<'a> TryFrom<&'a str> for Network<'a> {
+    type Error = ParseError<'a>;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        let (modules, names) = try_parse_modules(value)?;
+        Ok(Self { modules, names })
+    }
+}
+
+impl Network<'_> {
+    /// How many low and high pulses are sent in total during `presses` button
+    /// presses, starting with every module in its initial state.
+    pub fn pulse_counts(&self, presses: usize) -> (usize, usize) {
+        let mut modules = self.modules.clone();
+        (0..presses).map(|_| push_button(&mut modules)).fold((0, 0), |(low, high), this| (low + this.0, high + this.1))
+    }
+
+    /// The first button press delivering a low pulse to `rx`, found by
+    /// simulating at most `limit` presses.
+    pub fn presses_until_rx(&self, limit: usize) -> Option<usize> {
+        let mut modules = self.modules.clone();
+        (1..=limit).find(|_| push_button(&mut modules).2)
+    }
+}
+
+pub fn run(input: &str) -> Result<(usize, usize), ParseError<'_>> {
+    let network = Network::try_from(input)?;
+    let (low_count, high_count) = network.pulse_counts(1000);
 	let first = low_count*high_count;
 
-    let second = rx_idx;
+    let second = match network.counters() {
+        Ok(counters) => counters.iter()
+            .try_fold(1, |presses, counter| lcm(presses, counter.period))
+            .ok_or(ParseError::Structure(StructureError::PeriodOverflow))?,
+        Err(e) => network.presses_until_rx(SIMULATION_LIMIT).ok_or(ParseError::Structure(e))?,
+    };
     Ok((first, second))
 }
 
-fn lcm(lhs: usize, rhs: usize) -> usize {
-    lhs * rhs / gcd(lhs, rhs)
+fn lcm(lhs: usize, rhs: usize) -> Option<usize> {
+    (lhs / gcd(lhs, rhs)).checked_mul(rhs)
 }
 
 fn gcd(lhs: usize, rhs: usize) -> usize {
@@ -78,9 +104,9 @@ fn gcd(lhs: usize, rhs: usize) -> usize {
     a
 }
 
-fn try_parse_modules(input: &str) -> Result<Vec<Module>, ParseError> {
-	let mut ids = HashMap::from([("output", 0), ("rx", 1), ("roadcaster", 2)]);
-	let mut modules = Vec::from([Module{ id: 0, module_type: ModuleType::Broadcast, outputs: Vec::new() }, Module{ id: 1, module_type: ModuleType::Broadcast, outputs: Vec::new()}]);
+fn try_parse_modules(input: &str) -> Result<(Vec<Module>, Vec<&str>), ParseError<'_>> {
+	let mut ids = HashMap::from([("output", 0), ("rx", RX), ("roadcaster", BROADCASTER)]);
+	let mut modules = Vec::from([Module{ id: 0, module_type: ModuleType::Sink, outputs: Vec::new() }, Module{ id: RX, module_type: ModuleType::Sink, outputs: Vec::new()}]);
 	for line in input.lines() {
 		let components: Vec<_> = line.split([' ', ',']).collect();
 		if components.len() < 3 || components[0].len() < 2 {
@@ -114,6 +140,10 @@ fn try_parse_modules(input: &str) -> Result<Vec<Module>, ParseError> {
 				}).collect();
 		modules.push(Module{ id, module_type, outputs, });
 	}
+	// Modules that only appear as destinations don't do anything.
+	let mut defined = vec![false; ids.len()];
+	modules.iter().for_each(|module| defined[module.id] = true);
+	modules.extend(defined.iter().enumerate().filter(|(_id, defined)| !**defined).map(|(id, _)| Module{ id, module_type: ModuleType::Sink, outputs: Vec::new() }));
 	modules.sort_by_key(|module| module.id);
 	for idx in 0..modules.len() {
 		if matches!(modules[idx].module_type, ModuleType::Conjunction(_)) {
@@ -121,23 +151,31 @@ fn try_parse_modules(input: &str) -> Result<Vec<Module>, ParseError> {
 			modules[idx].module_type = ModuleType::Conjunction(init);
 		}
 	}
-	Ok(modules)
+	let mut names = vec![""; ids.len()];
+	ids.into_iter().for_each(|(name, id)| names[id] = name);
+	names[BROADCASTER] = "broadcaster";
+	Ok((modules, names))
 }
 
-fn push_button(modules: &mut [Module], watch_list: &[usize]) -> (usize, usize, Vec<bool>) {
-	let (mut send_low, mut send_high) = (Vec::from([(2, 2)]), Vec::new());
+// Returns the number of low and high pulses sent, and whether `rx` received a
+// low one.
+fn push_button(modules: &mut [Module]) -> (usize, usize, bool) {
+	let (mut send_low, mut send_high) = (Vec::from([(BROADCASTER, BROADCASTER)]), Vec::new());
 	let (mut low_count, mut high_count) = (0, 0);
-    let mut watch_results = vec![false; watch_list.len()];
+    let mut rx_low = false;
 	while !send_low.is_empty() || !send_high.is_empty() {
 		low_count += send_low.len();
 		high_count += send_high.len();
+        rx_low |= send_low.iter().any(|(_from, to)| *to == RX);
 		(send_low, send_high) = tick(modules, &send_low, &send_high);
-        watch_list.iter().enumerate().for_each(|(idx, id)| if send_high.iter().any(|(from, _to)| from == id) { watch_results[idx] = true; });
 	}
-	(low_count, high_count, watch_results)
+	(low_count, high_count, rx_low)
 }
 
-fn tick(modules: &mut [Module], low_to_send: &[(usize, usize)], high_to_send: &[(usize, usize)]) -> (Vec<(usize, usize)>, Vec<(usize, usize)>) {
+// (from, to) pairs of modules.
+type Pulses = Vec<(usize, usize)>;
+
+fn tick(modules: &mut [Module], low_to_send: &[(usize, usize)], high_to_send: &[(usize, usize)]) -> (Pulses, Pulses) {
 	let (mut next_low, mut next_high) = (Vec::new(), Vec::new());
 	low_to_send.iter().for_each(|(from_idx, to_idx)| {
 		let next = send_low(*from_idx, *to_idx, modules);
@@ -160,6 +198,7 @@ fn send_low(from_idx: usize, to_idx: usize, modules: &mut [Module]) -> (Vec<usiz
     let curr = modules[to_idx].clone();
     match curr.module_type {
         ModuleType::Broadcast => (curr.outputs.to_vec(), Signal::Low),
+        ModuleType::Sink => (Vec::new(), Signal::Low),
         ModuleType::FlipFlop(Signal::Low) => {
             modules[to_idx].module_type = ModuleType::FlipFlop(Signal::High);
             (curr.outputs.to_vec(), Signal::High)
@@ -181,7 +220,7 @@ fn send_high(from_idx: usize, to_idx: usize, modules: &mut [Module]) -> (Vec<usi
     let curr = modules[to_idx].clone();
     match curr.module_type {
         ModuleType::Broadcast => (curr.outputs.to_vec(), Signal::High),
-        ModuleType::FlipFlop(_) => (Vec::new(), Signal::High),
+        ModuleType::FlipFlop(_) | ModuleType::Sink => (Vec::new(), Signal::High),
         ModuleType::Conjunction(inputs) => {
             let mut new = inputs;
             new.insert(from_idx, Signal::High);
@@ -218,4 +257,56 @@ mod tests {
         let challenge_input = read_file("tests/challenge_input");
         assert_eq!(run(&challenge_input), Ok((836127690, 240914003753369)));
     }
+
+    #[test]
+    fn test_analysis() {
+        let challenge_input = read_file("tests/challenge_input");
+        let counters = Network::try_from(&challenge_input[..]).unwrap().counters().unwrap();
+        let mut hubs: Vec<_> = counters.iter().map(|counter| &counter.hub[..]).collect();
+        hubs.sort();
+        assert_eq!(hubs, ["fv", "gk", "rt", "sl"]);
+        assert!(counters.iter().all(|counter| counter.bits.len() == 12));
+
+        // Counters rolling over every 5 and 3 presses.
+        let counters_input = "broadcaster -> a0, b0
+%a0 -> a1, ha
+%a1 -> a2
+%a2 -> ha
+&ha -> a0, a1, ia
+&ia -> c
+%b0 -> b1, hb
+%b1 -> hb
+&hb -> b0, ib
+&ib -> c
+&c -> rx";
+        let network = Network::try_from(counters_input).unwrap();
+        assert_eq!(network.counters(), Ok(vec![
+            analysis::Counter { hub: "ha".to_string(), inverter: "ia".to_string(), bits: vec!["a0".to_string(), "a1".to_string(), "a2".to_string()], period: 5 },
+            analysis::Counter { hub: "hb".to_string(), inverter: "ib".to_string(), bits: vec!["b0".to_string(), "b1".to_string()], period: 3 },
+        ]));
+        assert_eq!(network.presses_until_rx(100), Some(15));
+        let dot = network.to_dot();
+        assert!(dot.starts_with("digraph modules {\n"));
+        assert!(dot.contains("    \"ha\" [label=\"&ha\", shape=ellipse];\n"));
+        assert!(dot.contains("    \"a0\" -> \"a1\";\n"));
+
+        let broken = counters_input.replace("%a1 -> a2", "%a1 -> a2, ha");
+        assert_eq!(Network::try_from(&broken[..]).unwrap().counters(), Err(StructureError::NotCounter("ha".to_string())));
+
+        let sample_input = read_file("tests/sample_input");
+        assert_eq!(Network::try_from(&sample_input[..]).unwrap().counters(), Err(StructureError::NotInverter("a".to_string())));
+        assert_eq!(run("broadcaster -> a\n&a -> rx"), Err(ParseError::Structure(StructureError::NotInverter("broadcaster".to_string()))));
+
+        // Counters of 40 and 41 set bits only roll over together after about
+        // 2^81 presses.
+        let counter = |name: &str, bits: usize| {
+            let mut lines: Vec<_> = (0..bits - 1).map(|bit| format!("%{name}{bit} -> {name}{}, h{name}", bit + 1)).collect();
+            lines.push(format!("%{name}{} -> h{name}\n&h{name} -> {name}0, i{name}\n&i{name} -> c", bits - 1));
+            lines.join("\n")
+        };
+        let wide = format!("broadcaster -> a0, b0\n{}\n{}\n&c -> rx", counter("a", 40), counter("b", 41));
+        let periods: Vec<_> = Network::try_from(&wide[..]).unwrap().counters().unwrap().iter().map(|counter| counter.period).collect();
+        assert_eq!(periods, [(1 << 40) - 1, (1 << 41) - 1]);
+        assert_eq!(run(&wide), Err(ParseError::Structure(StructureError::PeriodOverflow)));
+    }
 }
@@ -0,0 +1,143 @@
+// Human readable listings for the 3-bit computer, and the way back.
+//
+// Source format: one instruction per line, `mnemonic [operand]`. Combo
+// operands may be given as `0`-`3` or as a register name (`A`, `B`, `C`),
+// literal operands as `0`-`7`. `bxc` ignores its operand, so it may be
+// omitted. Everything after a `;` is a comment, and an address prefix like
+// `12:` (as printed by `disassemble()`) is skipped, so listings can be fed
+// straight back into `assemble()`.
+
+use core::fmt::Display;
+use std::num::ParseIntError;
+
+const MNEMONICS: [&str; 8] = ["adv", "bxl", "bst", "jnz", "bxc", "out", "bdv", "cdv"];
+const COMBO: [bool; 8] = [true, false, true, false, false, true, true, true];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AsmError<'a> {
+    InvalidOperand(usize, &'a str),
+    InvalidValue(usize, u8),
+    MissingOperand(usize),
+    OddLength,
+    ParseIntError(ParseIntError),
+    ReservedOperand(usize),
+    TrailingToken(usize, &'a str),
+    UnknownMnemonic(usize, &'a str),
+}
+
+impl From<ParseIntError> for AsmError<'_> {
+    fn from(value: ParseIntError) -> Self {
+        Self::ParseIntError(value)
+    }
+}
+
+impl Display for AsmError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidOperand(line, op) => write!(f, "Invalid operand in line {line}: {op}"),
+            Self::InvalidValue(pos, v) => write!(f, "Value at position {pos} is not a 3-bit number: {v}"),
+            Self::MissingOperand(line) => write!(f, "Missing operand in line {line}"),
+            Self::OddLength => write!(f, "Program must consist of pairs of instructions and operands"),
+            Self::ParseIntError(e) => write!(f, "Unable to parse into integer: {e}"),
+            Self::ReservedOperand(pos) => write!(f, "Reserved combo operand 7 at position {pos}"),
+            Self::TrailingToken(line, t) => write!(f, "Unexpected token in line {line}: {t}"),
+            Self::UnknownMnemonic(line, m) => write!(f, "Unknown instruction in line {line}: {m}"),
+        }
+    }
+}
+
+fn combo_name(operand: u8) -> String {
+    match operand {
+        0..=3 => operand.to_string(),
+        _ => ((b'A' + operand - 4) as char).to_string(),
+    }
+}
+
+fn describe(instruction: u8, operand: u8) -> String {
+    let combo = combo_name(operand);
+    match instruction {
+        0 => format!("A = A >> {combo}"),
+        1 => format!("B = B ^ {operand}"),
+        2 => format!("B = {combo} % 8"),
+        3 => format!("if A != 0 goto {operand}"),
+        4 => "B = B ^ C".to_string(),
+        5 => format!("output {combo} % 8"),
+        6 => format!("B = A >> {combo}"),
+        _ => format!("C = A >> {combo}"),
+    }
+}
+
+/// Turns a comma-separated program into a listing with one instruction per
+/// line, its address, decoded operand and what it does.
+pub fn disassemble(program: &str) -> Result<String, AsmError<'_>> {
+    let values = program
+        .trim()
+        .split(',')
+        .map(|n| n.trim().parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(pos) = values.iter().position(|&v| v > 7) {
+        return Err(AsmError::InvalidValue(pos, values[pos]));
+    }
+    if !values.len().is_multiple_of(2) {
+        return Err(AsmError::OddLength);
+    }
+    let width = values.len().saturating_sub(1).to_string().len();
+    values.chunks(2).enumerate().map(|(idx, pair)| {
+        let (instruction, operand) = (pair[0], pair[1]);
+        let ip = 2 * idx;
+        if COMBO[instruction as usize] && operand == 7 {
+            return Err(AsmError::ReservedOperand(ip + 1));
+        }
+        let code = match instruction {
+            4 if operand == 0 => "bxc".to_string(),
+            _ if COMBO[instruction as usize] => format!("{} {}", MNEMONICS[instruction as usize], combo_name(operand)),
+            _ => format!("{} {operand}", MNEMONICS[instruction as usize]),
+        };
+        Ok(format!("{ip:>width$}: {code:<8} ; {}", describe(instruction, operand)))
+    }).collect::<Result<Vec<_>, _>>().map(|lines| lines.join("\n"))
+}
+
+/// Turns mnemonic source (see the top of this file) into the comma-separated
+/// program the computer expects.
+pub fn assemble(source: &str) -> Result<String, AsmError<'_>> {
+    let mut program = Vec::new();
+    for (idx, line) in source.lines().enumerate() {
+        let line_number = idx + 1;
+        let code = line.split(';').next().unwrap_or_default();
+        let code = match code.split_once(':') {
+            Some((address, rest)) if address.trim().parse::<usize>().is_ok() => rest,
+            _ => code,
+        };
+        let mut tokens = code.split_whitespace();
+        let Some(mnemonic) = tokens.next() else {
+            continue;
+        };
+        let instruction = MNEMONICS
+            .iter()
+            .position(|&m| m.eq_ignore_ascii_case(mnemonic))
+            .ok_or(AsmError::UnknownMnemonic(line_number, mnemonic))?;
+        let operand = match (tokens.next(), instruction) {
+            (None, 4) => 0,
+            (None, _) => return Err(AsmError::MissingOperand(line_number)),
+            (Some(op), _) if COMBO[instruction] => match op {
+                "A" | "a" => 4,
+                "B" | "b" => 5,
+                "C" | "c" => 6,
+                _ => match op.parse::<u8>() {
+                    Ok(n) if n < 4 => n,
+                    _ => return Err(AsmError::InvalidOperand(line_number, op)),
+                },
+            },
+            (Some(op), _) => match op.parse::<u8>() {
+                Ok(n) if n < 8 => n,
+                _ => return Err(AsmError::InvalidOperand(line_number, op)),
+            },
+        };
+        if let Some(token) = tokens.next() {
+            return Err(AsmError::TrailingToken(line_number, token));
+        }
+        program.push(instruction.to_string());
+        program.push(operand.to_string());
+    }
+    Ok(program.join(","))
+}
@@ -1,6 +1,13 @@
 use core::fmt::Display;
 use std::num::ParseIntError;
 
+mod asm;
+mod symbolic;
+pub use asm::{assemble, disassemble, AsmError};
+pub use symbolic::SolveError;
+
+const VERIFY_STEP_LIMIT: usize = 1_000_000;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError {
     ParseIntError(std::num::ParseIntError),
@@ -9,12 +16,64 @@ pub enum ParseError {
     RegisterNotSet(u8),
 }
 
+/// Anything that can go wrong from reading the input to finding register A.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Parse(ParseError),
+    Solve(SolveError),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AssumptionViolation {
+    AdvCount(usize),
+    AdvOperand(u8),
+    CarriedRegister(char),
+    ExtraJump(usize),
+    NoFinalJump,
+    OddLength,
+    OutAfterAdv,
+    OutCount(usize),
+}
+
 impl From<ParseIntError> for ParseError {
     fn from(value: ParseIntError) -> Self {
         Self::ParseIntError(value)
     }
 }
 
+impl From<ParseError> for Error {
+    fn from(value: ParseError) -> Self {
+        Self::Parse(value)
+    }
+}
+
+impl From<SolveError> for Error {
+    fn from(value: SolveError) -> Self {
+        Self::Solve(value)
+    }
+}
+
+impl From<AssumptionViolation> for Error {
+    fn from(value: AssumptionViolation) -> Self {
+        Self::Solve(SolveError::OctalAssumption(value))
+    }
+}
+
+impl Display for AssumptionViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AdvCount(n) => write!(f, "The loop must divide A exactly once, but it contains {n} adv instructions"),
+            Self::AdvOperand(op) => write!(f, "The loop must divide A by 8, but adv uses combo operand {op}"),
+            Self::CarriedRegister(r) => write!(f, "Register {r} is read before it is written, so it carries state between iterations"),
+            Self::ExtraJump(ip) => write!(f, "Found a jump at position {ip} before the end of the program"),
+            Self::NoFinalJump => write!(f, "The program must end in \"jnz 0\""),
+            Self::OddLength => write!(f, "The program must consist of pairs of instructions and operands"),
+            Self::OutAfterAdv => write!(f, "The loop must output before dividing A"),
+            Self::OutCount(n) => write!(f, "The loop must output exactly once, but it contains {n} out instructions"),
+        }
+    }
+}
+
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -26,6 +85,15 @@ impl Display for ParseError {
     }
 }
 
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "{e}"),
+            Self::Solve(e) => write!(f, "{e}"),
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 struct Computer {
     opcode: Vec<u8>,
@@ -73,8 +141,12 @@ impl Computer {
         }
     }
 
+    fn divide(&self, operand: usize) -> usize {
+        u32::try_from(self.combo(operand)).ok().and_then(|shift| self.registers[0].checked_shr(shift)).unwrap_or(0)
+    }
+
     fn adv(&mut self, operand: usize) {     
-        self.registers[0] >>= self.combo(operand);
+        self.registers[0] = self.divide(operand);
     }
 
     fn bxl(&mut self, operand: usize) {            
@@ -102,61 +174,154 @@ impl Computer {
     }
     
     fn bdv(&mut self, operand: usize) {
-        self.registers[1] = self.registers[0] >> self.combo(operand);
+        self.registers[1] = self.divide(operand);
     }
     
     fn cdv(&mut self, operand: usize) {
-        self.registers[2] = self.registers[0] >> self.combo(operand);
+        self.registers[2] = self.divide(operand);
     }
     
+    fn step(&mut self) {
+        let instruction = self.opcode[self.instruction_ptr];
+        let operand = self.opcode[self.instruction_ptr+1] as usize;
+        match instruction {
+            0 => self.adv(operand),
+            1 => self.bxl(operand),
+            2 => self.bst(operand),
+            3 => self.jnz(operand),
+            4 => self.bxc(),
+            5 => self.out(operand),
+            6 => self.bdv(operand),
+            7 => self.cdv(operand),
+            _ => unreachable!()
+        }
+        if instruction != 3 {
+            self.instruction_ptr += 2;
+        }
+    }
+
     fn run(&mut self) {
         while self.instruction_ptr < self.opcode.len()-1 {
-            let instruction = self.opcode[self.instruction_ptr];
-            let operand = self.opcode[self.instruction_ptr+1] as usize;
+            self.step();
+        }
+    }
+
+    fn produces(&self, a: usize, target: &[u8]) -> bool {
+        let mut computer = self.clone();
+        computer.registers[0] = a;
+        for _ in 0..VERIFY_STEP_LIMIT {
+            if computer.instruction_ptr >= computer.opcode.len()-1 {
+                return computer.output == target;
+            }
+            computer.step();
+            if !target.starts_with(&computer.output) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn check_octal_assumption(&self) -> Result<(), AssumptionViolation> {
+        let program = &self.opcode;
+        if !program.len().is_multiple_of(2) {
+            return Err(AssumptionViolation::OddLength);
+        }
+        if program[program.len()-2..] != [3, 0] {
+            return Err(AssumptionViolation::NoFinalJump);
+        }
+        let body: Vec<_> = program[..program.len()-2].chunks(2).map(|pair| (pair[0], pair[1])).collect();
+        if let Some(idx) = body.iter().position(|&(instruction, _)| instruction == 3) {
+            return Err(AssumptionViolation::ExtraJump(2*idx));
+        }
+        let adv: Vec<_> = body.iter().enumerate().filter(|(_, (instruction, _))| *instruction == 0).collect();
+        if adv.len() != 1 {
+            return Err(AssumptionViolation::AdvCount(adv.len()));
+        }
+        let (adv_idx, &(_, adv_operand)) = adv[0];
+        if adv_operand != 3 {
+            return Err(AssumptionViolation::AdvOperand(adv_operand));
+        }
+        let out: Vec<_> = body.iter().enumerate().filter(|(_, (instruction, _))| *instruction == 5).collect();
+        if out.len() != 1 {
+            return Err(AssumptionViolation::OutCount(out.len()));
+        }
+        if out[0].0 > adv_idx {
+            return Err(AssumptionViolation::OutAfterAdv);
+        }
+
+        // B and C must be derived from A anew in every iteration, so whatever
+        // happens first to each of them must be a write.
+        let mut written = [false; 3];
+        for &(instruction, operand) in &body {
+            let mut reads = Vec::new();
+            if [0, 2, 5, 6, 7].contains(&instruction) && (5..=6).contains(&operand) {
+                reads.push(operand as usize - 4);
+            }
             match instruction {
-                0 => self.adv(operand),
-                1 => self.bxl(operand),
-                2 => self.bst(operand),
-                3 => self.jnz(operand),
-                4 => self.bxc(),
-                5 => self.out(operand),
-                6 => self.bdv(operand),
-                7 => self.cdv(operand),
-                _ => unreachable!()
+                1 => reads.push(1),
+                4 => reads.extend([1, 2]),
+                _ => (),
             }
-            if instruction != 3 {
-                self.instruction_ptr += 2;
+            if let Some(&r) = reads.iter().find(|&&r| !written[r]) {
+                return Err(AssumptionViolation::CarriedRegister((b'A' + r as u8) as char));
             }
+            match instruction {
+                1 | 2 | 6 => written[1] = true,
+                7 => written[2] = true,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    // Only valid if the program passes `check_octal_assumption()`.
+    fn octal_search(&self) -> Option<usize> {
+        let mut possible_starts = Vec::from([0]);
+        let mut next_possible_starts: Vec<usize>;
+        for idx in (0..self.opcode.len()).rev() {
+            next_possible_starts = possible_starts
+                .iter()
+                .flat_map(|&start| (start << 3 .. (start + 1) << 3).filter(|&a| {
+                    let mut computer = self.clone();
+                    computer.registers[0] = a;
+                    computer.run();
+                    computer.output == computer.opcode[idx..]
+                })).collect();
+            std::mem::swap(&mut possible_starts, &mut next_possible_starts);
         }
+        possible_starts.iter().min().copied()
     }
 }
 
-pub fn run(input: &str) -> Result<(String, usize), ParseError> {
+pub fn run(input: &str) -> Result<(String, usize), Error> {
     let computer = Computer::try_from(input)?;
     let mut computer_1 = computer.clone();
     computer_1.run();
     let first = computer_1.output.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",");
 
-    // For part 2, we take advantage of the fact that both inputs are 
+    // For part 2, we can take advantage of the fact that both inputs are 
     // structured in a way that makes every nth last output only dependent 
-    // on the first n octal digits of A, excluding leading zeros.
-    let mut possible_starts = Vec::from([0]);
-    let mut next_possible_starts: Vec<usize>;
-    for idx in (0..computer.opcode.len()).rev() {
-        next_possible_starts = possible_starts
-            .iter()
-            .flat_map(|&start| (start << 3 .. (start + 1) << 3).filter(|&a| {
-                let mut computer = computer.clone();
-                computer.registers[0] = a;
-                computer.run();
-                computer.output == computer.opcode[idx..]
-            })).collect();
-        std::mem::swap(&mut possible_starts, &mut next_possible_starts);
-    }
-    let second = *possible_starts.iter().min().unwrap();
+    // on the first n octal digits of A, excluding leading zeros. If the
+    // program doesn't look like that, solve it symbolically instead.
+    let second = match computer.check_octal_assumption() {
+        Ok(()) => computer.octal_search(),
+        Err(_) => symbolic::solve(&computer, &computer.opcode)?.first().copied(),
+    }.ok_or(SolveError::NoSolution)?;
     Ok((first, second))
 }
 
+/// Checks whether part 2 may be solved one octal digit of A at a time.
+pub fn check_octal_assumption(input: &str) -> Result<(), Error> {
+    Ok(Computer::try_from(input)?.check_octal_assumption()?)
+}
+
+/// Returns every initial value of register A (in ascending order) for which
+/// the program outputs exactly `output` and halts.
+pub fn register_a_candidates(input: &str, output: &[u8]) -> Result<Vec<usize>, Error> {
+    let computer = Computer::try_from(input)?;
+    Ok(symbolic::solve(&computer, output)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -177,4 +342,66 @@ mod tests {
         let challenge_input = read_file("tests/challenge_input");
         assert_eq!(run(&challenge_input), Ok(("7,4,2,5,1,4,6,0,4".to_string(), 164278764924605)));
     }
+
+    #[test]
+    fn test_symbolic() {
+        let sample_input = read_file("tests/sample_input");
+        assert_eq!(register_a_candidates(&sample_input, &[0, 3, 5, 4, 3, 0]), Ok((117440..117448).collect()));
+        assert_eq!(register_a_candidates(&sample_input, &[5, 7, 3, 0]), Ok((2024..2032).collect()));
+
+        let challenge_input = read_file("tests/challenge_input");
+        let program = [2, 4, 1, 1, 7, 5, 1, 5, 4, 1, 5, 5, 0, 3, 3, 0];
+        let candidates = register_a_candidates(&challenge_input, &program).unwrap();
+        assert_eq!(candidates.first(), Some(&164278764924605));
+        assert_eq!(register_a_candidates(&challenge_input, &[7, 4, 2, 5, 1, 4, 6, 0, 4]).map(|c| c.contains(&17323786)), Ok(true));
+    }
+
+    #[test]
+    fn test_octal_assumption() {
+        assert_eq!(check_octal_assumption(&read_file("tests/challenge_input")), Ok(()));
+
+        // B is never reset, so every output depends on all previous ones.
+        let carried = "Register A: 0\nRegister B: 0\nRegister C: 0\n\nProgram: 1,5,5,5,0,3,3,0";
+        assert_eq!(check_octal_assumption(carried), Err(Error::Solve(SolveError::OctalAssumption(AssumptionViolation::CarriedRegister('B')))));
+        assert_eq!(register_a_candidates(carried, &[5, 0, 5]), Ok((64..512).collect()));
+        assert_eq!(run(carried), Err(Error::Solve(SolveError::NoSolution)));
+
+        let shifted = "Register A: 0\nRegister B: 0\nRegister C: 0\n\nProgram: 0,1,5,4,3,0";
+        assert_eq!(check_octal_assumption(shifted), Err(Error::Solve(SolveError::OctalAssumption(AssumptionViolation::AdvOperand(1)))));
+        assert_eq!(register_a_candidates(shifted, &[1, 0]), Ok(vec![2, 3]));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let expected = " 0: bst A    ; B = A % 8
+ 2: bxl 1    ; B = B ^ 1
+ 4: cdv B    ; C = A >> B
+ 6: bxl 5    ; B = B ^ 5
+ 8: bxc 1    ; B = B ^ C
+10: out B    ; output B % 8
+12: adv 3    ; A = A >> 3
+14: jnz 0    ; if A != 0 goto 0";
+        let program = "2,4,1,1,7,5,1,5,4,1,5,5,0,3,3,0";
+        assert_eq!(disassemble(program), Ok(expected.to_string()));
+        assert_eq!(assemble(expected), Ok(program.to_string()));
+        assert_eq!(disassemble("0,7"), Err(AsmError::ReservedOperand(1)));
+        assert_eq!(disassemble("0,3,8,1"), Err(AsmError::InvalidValue(2, 8)));
+    }
+
+    #[test]
+    fn test_assemble() {
+        assert_eq!(assemble("adv 3\n\n  out a ; print\nbxc\njnz 0"), Ok("0,3,5,4,4,0,3,0".to_string()));
+        assert_eq!(assemble("adv 4"), Err(AsmError::InvalidOperand(1, "4")));
+        assert_eq!(assemble("bxl 8"), Err(AsmError::InvalidOperand(1, "8")));
+        assert_eq!(assemble("out"), Err(AsmError::MissingOperand(1)));
+        assert_eq!(assemble("out A B"), Err(AsmError::TrailingToken(1, "B")));
+        assert_eq!(assemble("adv 3\nmul 2"), Err(AsmError::UnknownMnemonic(2, "mul")));
+
+        // The quine from the example, built from source and solved symbolically.
+        let quine = assemble("adv 3\nout A\njnz 0").unwrap();
+        let input = format!("Register A: 0\nRegister B: 0\nRegister C: 0\n\nProgram: {quine}");
+        let program: Vec<u8> = quine.split(',').map(|n| n.parse().unwrap()).collect();
+        assert_eq!(register_a_candidates(&input, &program).map(|c| c.first().copied()), Ok(Some(117440)));
+        assert_eq!(run(&input).map(|(_, second)| second), Ok(117440));
+    }
 }
@@ -0,0 +1,334 @@
+// Symbolic execution of the 3-bit computer.
+//
+// Every bit of every register is tracked as an affine form over GF(2) in the
+// (unknown) bits of the initial value of register A. None of the instructions
+// ever need anything but XOR, truncation and shifts, so the only places where
+// we have to commit to concrete values are shift amounts and the zero test of
+// `jnz`. At those points we fork the execution and record the choice as a
+// linear equation. Every `out` adds three more equations. Whatever survives
+// until the program halts describes a (usually tiny) affine space of
+// candidates, each of which is then confirmed on the concrete interpreter.
+
+use core::fmt::Display;
+use std::collections::BTreeSet;
+use std::ops::BitXor;
+
+use crate::{AssumptionViolation, Computer};
+
+const WIDTH: usize = usize::BITS as usize;
+const STEP_LIMIT: usize = 1_000_000;
+const MAX_FREE_BITS: u32 = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SolveError {
+    InvalidInstruction(usize),
+    InvalidOperand(usize),
+    NoSolution,
+    OctalAssumption(AssumptionViolation),
+    StepLimit,
+    Underdetermined(u32),
+}
+
+impl Display for SolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidInstruction(ip) => write!(f, "Invalid instruction at position {ip}"),
+            Self::InvalidOperand(ip) => write!(f, "Invalid combo operand 7 at position {ip}"),
+            Self::NoSolution => write!(f, "No value of register A makes the program output itself"),
+            Self::OctalAssumption(v) => write!(f, "Program doesn't consume A one octal digit at a time: {v}"),
+            Self::StepLimit => write!(f, "Gave up after exploring {STEP_LIMIT} symbolic steps"),
+            Self::Underdetermined(free) => write!(f, "The output leaves {free} bits of register A undetermined (at most {MAX_FREE_BITS} are supported)"),
+        }
+    }
+}
+
+/// An affine form over GF(2): the XOR of the A bits in `vars`, XOR `constant`.
+#[derive(Clone, Copy, Default)]
+struct Bit {
+    vars: usize,
+    constant: bool,
+}
+
+impl BitXor for Bit {
+    type Output = Self;
+
+    fn bitxor(self, rhs: Self) -> Self::Output {
+        Self {
+            vars: self.vars ^ rhs.vars,
+            constant: self.constant ^ rhs.constant,
+        }
+    }
+}
+
+impl Bit {
+    fn var(idx: usize) -> Self {
+        Self { vars: 1 << idx, constant: false }
+    }
+
+    fn constant(value: bool) -> Self {
+        Self { vars: 0, constant: value }
+    }
+}
+
+type Word = [Bit; WIDTH];
+
+fn constant_word(value: usize) -> Word {
+    std::array::from_fn(|idx| Bit::constant(value >> idx & 1 == 1))
+}
+
+fn shift_right(word: &Word, amount: usize) -> Word {
+    let mut res = [Bit::default(); WIDTH];
+    if amount < WIDTH {
+        res[..WIDTH-amount].copy_from_slice(&word[amount..]);
+    }
+    res
+}
+
+/// A system of linear equations (each row XORs to 0) kept in reduced row
+/// echelon form. `rows[p]` is the row whose pivot is variable `p`, and no
+/// pivot variable appears in any other row.
+#[derive(Clone)]
+struct Equations {
+    rows: [Bit; WIDTH],
+    pivots: usize,
+}
+
+impl Default for Equations {
+    fn default() -> Self {
+        Self {
+            rows: [Bit::default(); WIDTH],
+            pivots: 0,
+        }
+    }
+}
+
+impl Equations {
+    fn reduce(&self, mut bit: Bit) -> Bit {
+        let mut pivots = bit.vars & self.pivots;
+        while pivots != 0 {
+            let p = pivots.trailing_zeros() as usize;
+            bit = bit ^ self.rows[p];
+            pivots &= pivots - 1;
+        }
+        bit
+    }
+
+    fn eval(&self, bit: Bit) -> Option<bool> {
+        let reduced = self.reduce(bit);
+        if reduced.vars == 0 {
+            Some(reduced.constant)
+        } else {
+            None
+        }
+    }
+
+    /// Adds the equation `bit == value`. Returns `false` if that contradicts
+    /// what we already know.
+    fn assume(&mut self, bit: Bit, value: bool) -> bool {
+        let row = self.reduce(bit ^ Bit::constant(value));
+        if row.vars == 0 {
+            return !row.constant;
+        }
+        let p = row.vars.trailing_zeros() as usize;
+        let mut pivots = self.pivots;
+        while pivots != 0 {
+            let q = pivots.trailing_zeros() as usize;
+            if self.rows[q].vars & 1 << p != 0 {
+                self.rows[q] = self.rows[q] ^ row;
+            }
+            pivots &= pivots - 1;
+        }
+        self.rows[p] = row;
+        self.pivots |= 1 << p;
+        true
+    }
+
+    fn free_bits(&self) -> u32 {
+        self.pivots.count_zeros()
+    }
+
+    fn solutions(&self) -> impl Iterator<Item = usize> + '_ {
+        let free: Vec<_> = (0..WIDTH).filter(|&idx| self.pivots & 1 << idx == 0).collect();
+        (0..1_usize << free.len()).map(move |assignment| {
+            let mut value = free.iter().enumerate().fold(0, |acc, (bit, &var)| acc | (assignment >> bit & 1) << var);
+            let mut pivots = self.pivots;
+            while pivots != 0 {
+                let p = pivots.trailing_zeros() as usize;
+                let row = self.rows[p];
+                let others = (row.vars & !(1 << p) & value).count_ones() & 1 == 1;
+                if row.constant ^ others {
+                    value |= 1 << p;
+                }
+                pivots &= pivots - 1;
+            }
+            value
+        })
+    }
+}
+
+#[derive(Clone)]
+struct State {
+    ip: usize,
+    registers: [Word; 3],
+    equations: Equations,
+    output_len: usize,
+}
+
+impl State {
+    fn combo(&self, operand: u8) -> Result<Word, SolveError> {
+        match operand {
+            0..=3 => Ok(constant_word(operand as usize)),
+            4..=6 => Ok(self.registers[operand as usize - 4]),
+            _ => Err(SolveError::InvalidOperand(self.ip + 1)),
+        }
+    }
+
+    /// All concrete values `word` can take, each with the equations that pin
+    /// it down. Anything from `WIDTH` upwards shifts out every bit, so those
+    /// are lumped together as `WIDTH`.
+    fn concretize(&self, word: &Word) -> Vec<(usize, Equations)> {
+        let bits = WIDTH.trailing_zeros() as usize;
+        let mut res = Vec::new();
+        let mut equations = self.equations.clone();
+        for &bit in word[bits..].iter() {
+            match equations.eval(bit) {
+                Some(true) => {
+                    res.push((WIDTH, equations));
+                    return res;
+                },
+                Some(false) => (),
+                None => {
+                    let mut one = equations.clone();
+                    one.assume(bit, true);
+                    res.push((WIDTH, one));
+                    equations.assume(bit, false);
+                },
+            }
+        }
+        let mut partial = vec![(0, equations)];
+        for (idx, &bit) in word[..bits].iter().enumerate() {
+            partial = partial.into_iter().flat_map(|(value, equations)| match equations.eval(bit) {
+                Some(true) => vec![(value | 1 << idx, equations)],
+                Some(false) => vec![(value, equations)],
+                None => {
+                    let mut one = equations.clone();
+                    one.assume(bit, true);
+                    let mut zero = equations;
+                    zero.assume(bit, false);
+                    vec![(value | 1 << idx, one), (value, zero)]
+                },
+            }).collect();
+        }
+        res.append(&mut partial);
+        res
+    }
+
+    fn step(mut self, program: &[u8], target: &[u8]) -> Result<Vec<Self>, SolveError> {
+        let instruction = program[self.ip];
+        let operand = program[self.ip + 1];
+        match instruction {
+            0 | 6 | 7 => {
+                let amount = self.combo(operand)?;
+                let dest = if instruction == 0 { 0 } else { instruction as usize - 5 };
+                Ok(self.concretize(&amount).into_iter().map(|(amount, equations)| {
+                    let mut next = self.clone();
+                    next.registers[dest] = shift_right(&self.registers[0], amount);
+                    next.equations = equations;
+                    next.ip += 2;
+                    next
+                }).collect())
+            },
+            1 => {
+                (0..3).filter(|idx| operand >> idx & 1 == 1).for_each(|idx| self.registers[1][idx] = self.registers[1][idx] ^ Bit::constant(true));
+                self.ip += 2;
+                Ok(vec![self])
+            },
+            2 => {
+                let value = self.combo(operand)?;
+                self.registers[1] = [Bit::default(); WIDTH];
+                self.registers[1][..3].copy_from_slice(&value[..3]);
+                self.ip += 2;
+                Ok(vec![self])
+            },
+            3 => {
+                let a = self.registers[0];
+                let known: Vec<_> = a.iter().map(|&bit| self.equations.eval(bit)).collect();
+                if known.contains(&Some(true)) {
+                    self.ip = operand as usize;
+                    return Ok(vec![self]);
+                }
+                if known.iter().all(|bit| bit.is_some()) {
+                    self.ip += 2;
+                    return Ok(vec![self]);
+                }
+                let mut zero = self.clone();
+                zero.ip += 2;
+                self.ip = operand as usize;
+                if a.iter().all(|&bit| zero.equations.assume(bit, false)) {
+                    Ok(vec![self, zero])
+                } else {
+                    Ok(vec![self])
+                }
+            },
+            4 => {
+                for idx in 0..WIDTH {
+                    self.registers[1][idx] = self.registers[1][idx] ^ self.registers[2][idx];
+                }
+                self.ip += 2;
+                Ok(vec![self])
+            },
+            5 => {
+                let value = self.combo(operand)?;
+                let Some(&expected) = target.get(self.output_len) else {
+                    return Ok(Vec::new());
+                };
+                if (0..3).any(|idx| !self.equations.assume(value[idx], expected >> idx & 1 == 1)) {
+                    return Ok(Vec::new());
+                }
+                self.output_len += 1;
+                self.ip += 2;
+                Ok(vec![self])
+            },
+            _ => Err(SolveError::InvalidInstruction(self.ip)),
+        }
+    }
+}
+
+/// Finds every initial value of register A for which `computer` outputs
+/// exactly `target` and halts.
+pub(crate) fn solve(computer: &Computer, target: &[u8]) -> Result<Vec<usize>, SolveError> {
+    if target.iter().any(|&n| n > 7) {
+        return Ok(Vec::new());
+    }
+    let program = &computer.opcode;
+    let mut stack = vec![State {
+        ip: 0,
+        registers: [
+            std::array::from_fn(Bit::var),
+            constant_word(computer.registers[1]),
+            constant_word(computer.registers[2]),
+        ],
+        equations: Equations::default(),
+        output_len: 0,
+    }];
+    let mut candidates = BTreeSet::new();
+    let mut steps = 0;
+    while let Some(state) = stack.pop() {
+        if state.ip + 1 >= program.len() {
+            if state.output_len == target.len() {
+                let free = state.equations.free_bits();
+                if free > MAX_FREE_BITS {
+                    return Err(SolveError::Underdetermined(free));
+                }
+                candidates.extend(state.equations.solutions());
+            }
+            continue;
+        }
+        steps += 1;
+        if steps > STEP_LIMIT {
+            return Err(SolveError::StepLimit);
+        }
+        stack.extend(state.step(program, target)?);
+    }
+    Ok(candidates.into_iter().filter(|&a| computer.produces(a, target)).collect())
+}
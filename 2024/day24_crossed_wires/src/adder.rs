@@ -0,0 +1,171 @@
+// Checks a device against the structure of a ripple-carry adder. Bit 0 is a
+// half adder:
+//     z00 = x00 XOR y00
+//     c00 = x00 AND y00
+// and every other bit i is a full adder, taking the carry c of bit i-1:
+//     s   = xi XOR yi
+//     zi  = s XOR c
+//     ci  = (xi AND yi) OR (s AND c)
+// The last carry is the most significant output bit. Each kind of gate in
+// there has a fixed place, both in what it reads and in which gates read it,
+// so every gate can be checked on its own by looking only at its neighbours.
+
+use core::fmt::Display;
+use std::collections::HashMap;
+
+use crate::{Device, Gate};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op { And, Or, Xor }
+
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::And => write!(f, "AND"),
+            Self::Or => write!(f, "OR"),
+            Self::Xor => write!(f, "XOR"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// Reads one bit of x and one of y, but not of the same index, or only one of them.
+    MismatchedInputs,
+    /// An output bit other than the most significant one, but not computed by the final XOR of its bit.
+    OutputNotSum(Op),
+    /// The most significant output bit, but not the final carry.
+    OutputNotCarry(Op),
+    /// Computes a sum bit, but doesn't drive an output.
+    SumNotOutput,
+    /// Should be read by exactly these gates, but is read by the others.
+    WrongReaders { expected: Vec<Op>, actual: Vec<Op> },
+    /// Computes the sum of bit `sum`, but drives output bit `output`.
+    WrongBit { output: usize, sum: usize },
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |ops: &[Op]| if ops.is_empty() { "nothing".to_string() } else { ops.iter().map(Op::to_string).collect::<Vec<_>>().join(", ") };
+        match self {
+            Self::MismatchedInputs => write!(f, "doesn't combine x and y of the same bit"),
+            Self::OutputNotSum(op) => write!(f, "drives an output bit, but is an {op} gate instead of the XOR computing the sum"),
+            Self::OutputNotCarry(op) => write!(f, "drives the most significant output bit, but is an {op} gate instead of the last carry"),
+            Self::SumNotOutput => write!(f, "computes a sum bit, but doesn't drive an output"),
+            Self::WrongReaders { expected, actual } => write!(f, "should be read by {}, but is read by {}", list(expected), list(actual)),
+            Self::WrongBit { output, sum } => write!(f, "drives output bit {output}, but computes the sum of bit {sum}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation<'a> {
+    pub wire: &'a str,
+    pub reason: Reason,
+}
+
+impl Display for Violation<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.wire, self.reason)
+    }
+}
+
+// The register and bit index of an input wire like `x07`.
+fn input_bit(wire: &str) -> Option<(char, usize)> {
+    let register = wire.chars().next().filter(|c| matches!(c, 'x' | 'y'))?;
+    Some((register, wire[1..].parse().ok()?))
+}
+
+// The bit of a gate reading xi and yi.
+fn gate_bit(gate: Option<&Gate>) -> Option<usize> {
+    let Some(Gate::And(a, b) | Gate::Or(a, b) | Gate::Xor(a, b)) = gate else {
+        return None;
+    };
+    match (input_bit(a)?, input_bit(b)?) {
+        ((r_a, i_a), (r_b, i_b)) if r_a != r_b && i_a == i_b => Some(i_a),
+        _ => None,
+    }
+}
+
+impl<'a> Device<'a> {
+    // Which bit the final XOR reading `a` and `b` adds up: that of the half
+    // sum `xi XOR yi` it reads, or else one above the incoming carry. That
+    // carry is either `x00 AND y00`, or an OR of the AND of its own bit.
+    fn sum_bit(&self, a: &str, b: &str) -> Option<usize> {
+        let half_sum = |wire: &str| match self.gates.get(wire) {
+            gate @ Some(Gate::Xor(..)) => gate_bit(gate),
+            _ => None,
+        };
+        let carry = |wire: &str| match self.gates.get(wire) {
+            gate @ Some(Gate::And(..)) => gate_bit(gate).filter(|&bit| bit == 0),
+            Some(Gate::Or(l, r)) => [l, r].iter().find_map(|w| match self.gates.get(*w) {
+                gate @ Some(Gate::And(..)) => gate_bit(gate),
+                _ => None,
+            }),
+            _ => None,
+        };
+        half_sum(a).or_else(|| half_sum(b)).or_else(|| carry(a).or_else(|| carry(b)).map(|bit| bit + 1))
+    }
+
+    /// Checks every gate against the place it should have in a ripple-carry
+    /// adder, and returns those that don't fit, sorted by the wire they drive.
+    /// Returns `None` if the inputs and outputs don't have the shape of an
+    /// adder in the first place.
+    pub fn adder_violations(&self) -> Option<Vec<Violation<'a>>> {
+        let bits = self.x_bits;
+        if bits == 0 || self.y_bits != bits || self.output_gates != bits + 1 {
+            return None;
+        }
+        let gates: Vec<_> = self.gates.iter().filter_map(|(&wire, gate)| match *gate {
+            Gate::And(a, b) => Some((wire, Op::And, a, b)),
+            Gate::Or(a, b) => Some((wire, Op::Or, a, b)),
+            Gate::Xor(a, b) => Some((wire, Op::Xor, a, b)),
+            Gate::Value(_) => None,
+        }).collect();
+        let mut readers: HashMap<&str, Vec<Op>> = HashMap::new();
+        for &(_, op, a, b) in &gates {
+            readers.entry(a).or_default().push(op);
+            readers.entry(b).or_default().push(op);
+        }
+        readers.values_mut().for_each(|ops| ops.sort_by_key(|op| *op as u8));
+        let check_readers = |wire: &str, expected: &[Op]| {
+            let actual = readers.get(wire).cloned().unwrap_or_default();
+            (actual != expected).then(|| Reason::WrongReaders { expected: expected.to_vec(), actual })
+        };
+        let last_output = Self::output_gate(bits);
+
+        let mut violations: Vec<_> = gates.iter().filter_map(|&(wire, op, a, b)| {
+            let output = wire.strip_prefix('z').and_then(|idx| idx.parse::<usize>().ok());
+            // Gates reading x and y, and the bit they belong to.
+            let input = match (input_bit(a), input_bit(b)) {
+                (None, None) => None,
+                (Some((r_a, i_a)), Some((r_b, i_b))) if r_a != r_b && i_a == i_b => Some(i_a),
+                _ => return Some(Violation { wire, reason: Reason::MismatchedInputs }),
+            };
+            let reason = match (op, input) {
+                _ if wire == last_output && op != Op::Or && !(bits == 1 && op == Op::And) => Some(Reason::OutputNotCarry(op)),
+                _ if wire == last_output => None,
+                (Op::Xor, Some(0)) if wire != "z00" && output.is_some() => Some(Reason::OutputNotSum(op)),
+                (Op::Xor, Some(0)) if wire != "z00" => Some(Reason::SumNotOutput),
+                (Op::Xor, Some(0)) => None,
+                (_, _) if output.is_some() && (op != Op::Xor || input.is_some()) => Some(Reason::OutputNotSum(op)),
+                (Op::Xor, None) if output.is_none() => Some(Reason::SumNotOutput),
+                // Bits of the sum can be crossed with each other, even though
+                // they are all computed the same way.
+                (Op::Xor, None) => {
+                    let output = output.unwrap_or_default();
+                    self.sum_bit(a, b).filter(|&sum| sum != output).map(|sum| Reason::WrongBit { output, sum })
+                },
+                // The half sum of a full adder goes on to the final XOR, and
+                // to the AND checking for a carry.
+                (Op::Xor, Some(_)) => check_readers(wire, &[Op::And, Op::Xor]),
+                // Carries go into the next bit the same way.
+                (Op::And, Some(0)) | (Op::Or, _) => check_readers(wire, &[Op::And, Op::Xor]),
+                (Op::And, _) => check_readers(wire, &[Op::Or]),
+            };
+            reason.map(|reason| Violation { wire, reason })
+        }).collect();
+        violations.sort_by_key(|v| v.wire);
+        Some(violations)
+    }
+}
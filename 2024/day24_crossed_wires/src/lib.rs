@@ -1,5 +1,8 @@
 use core::fmt::Display;
-use std::{collections::{HashMap, HashSet}, num::ParseIntError};
+use std::{collections::HashMap, num::ParseIntError};
+
+mod adder;
+pub use adder::{Op, Reason, Violation};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum ParseError<'a> {
@@ -37,11 +40,13 @@ enum Gate<'a> {
 }
 
 #[derive(Clone)]
-struct Device<'a> {
+pub struct Device<'a> {
     gates: HashMap<&'a str, Gate<'a>>,
     output_gates: usize,
     x: usize,
     y: usize,
+    x_bits: usize,
+    y_bits: usize,
 }
 
 impl<'a> TryFrom<&'a str> for Device<'a> {
@@ -53,11 +58,18 @@ impl<'a> TryFrom<&'a str> for Device<'a> {
             let mut output_gates = 0;
             let mut x = 0;
             let mut y = 0;
+            let mut x_bits = 0;
+            let mut y_bits = 0;
 
             for line in input.lines() {
                 if let Some((name, val)) = line.split_once(": ") {
                     let input = name.chars().next();
                     let idx = name[1..].parse::<usize>()?;
+                    match input {
+                        Some('x') => x_bits = x_bits.max(idx+1),
+                        Some('y') => y_bits = y_bits.max(idx+1),
+                        _ => (),
+                    }
                     match (input, val) {
                         (_, "0") => (),
                         (Some('x'), "1") => x |= 1 << idx,
@@ -101,6 +113,8 @@ impl<'a> TryFrom<&'a str> for Device<'a> {
                 output_gates,
                 x,
                 y,
+                x_bits,
+                y_bits,
             })
         } else {
             Err(Self::Error::InputMalformed)
@@ -162,29 +176,6 @@ impl<'a> Device<'a> {
         (0..self.output_gates).map(|idx| self.eval_output_gate(idx, true) << idx).sum()
     }
 
-    fn get_dependent_gates(&self, name: &'a str) -> HashSet<&'a str> {
-        match self.gates.get(name) {
-            Some(Gate::And(a, b)) | Some(Gate::Or(a, b )) | Some(Gate::Xor(a, b)) => 
-            HashSet::from([name]).union(&self.get_dependent_gates(a)).cloned().collect::<HashSet<_>>().union(&self.get_dependent_gates(b)).cloned().collect(),
-            Some(Gate::Value(_)) => HashSet::from([name]),
-            None => HashSet::new(),
-        }
-    }
-
-    fn is_loop_free(&self, name: &str, previous: &HashSet<&str>) -> bool {
-        match self.gates.get(name) {
-            None | Some(Gate::Value(_)) => true,
-            Some(Gate::And(a, b)) | Some(Gate::Or(a, b )) | Some(Gate::Xor(a, b)) => {
-                if previous.contains(a) || previous.contains(b) {
-                    return false;
-                }
-                let mut previous = previous.clone();
-                previous.insert(a);
-                previous.insert(b);
-                self.is_loop_free(a, &previous) && self.is_loop_free(b, &previous)
-            }
-        }
-    }
     fn output_gate(idx: usize) -> String {
         if idx < 10 {
             format!("z0{idx}")
@@ -192,147 +183,15 @@ impl<'a> Device<'a> {
             format!("z{idx}")
         }
     }
-
-    /// Determines if the `idx`th least significant bit of z can be traced to input bits (x and y)
-    /// on all paths (returning `true`), or if they form a loop (returning `false`).
-    fn output_is_loop_free(&self, idx: usize) -> bool {
-        self.is_loop_free(&Self::output_gate(idx)[..], &HashSet::new())
-    }
-
-    /// Checks if the rightmost `z_idx` bits of z behave like an adder. Returns `None`, if they do,
-    /// and `Some(idx)` otherwise, where `idx` is the first bit (from the right), which differs.
-    fn check_until(&mut self, z_idx: usize) -> Option<usize> {
-        let tests_0 = [(0, 0), (0, 1), (1, 0), (1, 1)];
-        if tests_0.iter().any(|(l, r)| {
-            self.x = *l;
-            self.y = *r;
-            !self.output_is_loop_free(0) ||
-            self.eval_output_gate(0, false) != l ^ r
-        }) {
-            return Some(0);
-        }
-        if z_idx == 0 {
-            return None;
-        }
-        let tests = [
-            (0, 0, 0, 0), (0, 1, 0, 0), (1, 0, 0, 0), (1, 1, 0, 0),
-            (0, 0, 0, 1), (0, 1, 0, 1), (1, 0, 0, 1), (1, 1, 0, 1),
-            (0, 0, 1, 0), (0, 1, 1, 0), (1, 0, 1, 0), (1, 1, 1, 0),
-            (0, 0, 1, 1), (0, 1, 1, 1), (1, 0, 1, 1), (1, 1, 1, 1),
-        ];
-        (1..=z_idx).find(|&z_idx| {
-            !self.output_is_loop_free(z_idx) ||
-            tests.iter().any(|(l, r, prev_l, prev_r)| {
-                self.x = ((*l << 1) + *prev_l) << (z_idx - 1);
-                self.y = ((*r << 1) + *prev_r) << (z_idx - 1);
-                self.eval_output_gate(z_idx, false) != l ^ r ^ (prev_l & prev_r)
-            })
-        })
-    }
-
-    /// Try swapping all combinations of two gates, where at least one of them is contained in
-    /// `must_include` and none of them in `swapped_before` and determine if their swap results in
-    /// the rightmost `z_idx` bits of z are correct. Returns an unsorted Vec of all such pairs.
-    fn try_swaps(&'a mut self, z_idx: usize, must_include: &[&'a str], swapped_before: &[&'a str]) -> Vec<[String; 2]> {
-        let mut res = Vec::new();
-        // We need to clone these so the borrow checker won't complain about concurrent borrows in the
-        // swap loops.
-        let all_inputs: Vec<_> = self.gates.keys().cloned().collect();
-        // Try only switching one pair of gates and hope we find a solution this way (works for my
-        // input).
-        for &gate_1 in must_include {
-            if swapped_before.contains(&gate_1) {
-                continue;
-            }
-            // The unwrap()s below are safe because the constructor made sure all gates exist.
-            let inputs_1 = *self.gates.get(gate_1).unwrap();
-            for &gate_2 in all_inputs.iter().filter(|&&gate_2| 
-                gate_2 != gate_1 && 
-                !swapped_before.contains(&gate_2))
-            {
-                let inputs_2 = *self.gates.get(gate_2).unwrap();
-                *self.gates.get_mut(gate_1).unwrap() = inputs_2;
-                *self.gates.get_mut(gate_2).unwrap() = inputs_1;
-                if self.check_until(z_idx).is_none() {
-                    res.push([gate_1.into(), gate_2.into()]);
-                }
-                *self.gates.get_mut(gate_1).unwrap() = inputs_1;
-                *self.gates.get_mut(gate_2).unwrap() = inputs_2;
-            }
-        }
-        res
-    }
-
-    /// Find the necessary (up to 8) swaps to turn this device into a binary adder.
-    /// `swapped_before` contains the swaps already established (this should be an empty array at
-    /// first). Returns a `Vec` of the affected gates, sorted alphabetically.
-    fn swap_gates(&mut self, swapped_before: &[String]) -> Vec<String> {
-        let mut swaps_performed = swapped_before.to_vec();
-        loop {
-            if let Some(next_error) = self.check_until(self.output_gates-2) {
-                let output_name = Self::output_gate(next_error);
-                let swapped_before: Vec<_> = swaps_performed.iter().map(|s: &String| &s[..]).collect();
-                let must_include: Vec<&str> = self.get_dependent_gates(&output_name[..]).into_iter().collect();
-                let mut next = self.clone();
-                let new_possible_swaps = next.try_swaps(next_error, &must_include, &swapped_before);
-                match new_possible_swaps.len() {
-                    // The unwrap()s below are safe because the constructor made sure all gates exist.
-                    0 => return Vec::new(),     // If we found no solution, return early
-                    1 => {
-                        // We found one solution. Continue with it.
-                        for swap in new_possible_swaps[0].chunks(2) {
-                            let (gate_1, gate_2) = (swap[0].to_string(), swap[1].to_string());
-                            let inputs_1 = *self.gates.get(&gate_1[..]).unwrap();
-                            *self.gates.get_mut(&gate_1[..]).unwrap() = *self.gates.get(&gate_2[..]).unwrap();
-                            *self.gates.get_mut(&gate_2[..]).unwrap() = inputs_1;
-                            swaps_performed.push(gate_1);
-                            swaps_performed.push(gate_2);
-                        }
-                    },
-                    _ => {
-                        // We found more than one solution. 
-                        // Spawn a new Device for each and try them all.
-                        for swaps in new_possible_swaps {
-                            if swaps.len() + swaps_performed.len() > 8 {
-                                continue;
-                            }
-                            let mut next = self.clone();
-                            let mut swapped_before = swaps_performed.to_vec();
-                            for swap in swaps.chunks(2) {
-                                let (gate_1, gate_2) = (swap[0].to_string(), swap[1].to_string());
-                                let inputs_1 = *self.gates.get(&gate_1[..]).unwrap();
-                                *next.gates.get_mut(&gate_1[..]).unwrap() = *self.gates.get(&gate_2[..]).unwrap();
-                                *next.gates.get_mut(&gate_2[..]).unwrap() = inputs_1;
-                                swapped_before.push(gate_1);
-                                swapped_before.push(gate_2);
-                            }
-                            let res = next.swap_gates(&swapped_before);
-                            if res.len() <= 8 {
-                                return res;
-                            }
-                        }
-                        return Vec::new();
-                    }
-                }
-            } else if swaps_performed.len() <= 8 {
-                swaps_performed.sort();
-                return swaps_performed;
-            } else {
-                return Vec::new();
-            }
-        }
-    }
 }
 
-pub fn run(input: &str) -> Result<(usize, String), ParseError> {
-    let mut device_1 = Device::try_from(input)?;
-    let mut device_2 = device_1.clone();
-    let first = device_1.eval();
-    let second = if device_2.output_gates > 13 {
-        device_2.swap_gates(&[]).join(",")
-    } else {
-        String::new()
-    };
+pub fn run(input: &str) -> Result<(usize, String), ParseError<'_>> {
+    let mut device = Device::try_from(input)?;
+    // Devices that aren't even shaped like an adder have nothing to fix.
+    let second = device.adder_violations()
+        .map(|violations| violations.iter().map(|v| v.wire).collect::<Vec<_>>().join(","))
+        .unwrap_or_default();
+    let first = device.eval();
     Ok((first, second))
 }
 
@@ -351,6 +210,50 @@ mod tests {
         assert_eq!(run(&sample_input), Ok((2024, "".to_string())));
     }
 
+    #[test]
+    fn test_adder() {
+        // A correct 3 bit adder, with bits 1 and 2 of the sum swapped.
+        let adder = "x00: 0\nx01: 0\nx02: 0\ny00: 0\ny01: 0\ny02: 0\n\n\
+            x00 XOR y00 -> z00\nx00 AND y00 -> c00\n\
+            x01 XOR y01 -> s01\ns01 XOR c00 -> z02\nx01 AND y01 -> a01\ns01 AND c00 -> b01\na01 OR b01 -> c01\n\
+            x02 XOR y02 -> s02\ns02 XOR c01 -> z01\nx02 AND y02 -> a02\ns02 AND c01 -> b02\na02 OR b02 -> z03";
+        let device = Device::try_from(adder).unwrap();
+        let violations: Vec<_> = device.adder_violations().unwrap().iter().map(Violation::to_string).collect();
+        assert_eq!(violations, [
+            "z01: drives output bit 1, but computes the sum of bit 2",
+            "z02: drives output bit 2, but computes the sum of bit 1",
+        ]);
+
+        let adder = adder.replace("-> z02", "-> z2").replace("-> z01", "-> z02").replace("-> z2", "-> z01");
+        assert_eq!(Device::try_from(&adder[..]).unwrap().adder_violations(), Some(Vec::new()));
+
+        // The sum of bit 2 and one half of its carry swap their outputs.
+        let broken = adder.replace("s02 XOR c01 -> z02", "s02 XOR c01 -> b02").replace("s02 AND c01 -> b02", "s02 AND c01 -> z02");
+        let device = Device::try_from(&broken[..]).unwrap();
+        let violations: Vec<_> = device.adder_violations().unwrap().iter().map(Violation::to_string).collect();
+        assert_eq!(violations, [
+            "b02: computes a sum bit, but doesn't drive an output",
+            "z02: drives an output bit, but is an AND gate instead of the XOR computing the sum",
+        ]);
+
+        let broken = adder.replace("x01 AND y01 -> a01", "x01 AND y02 -> a01").replace("x02 XOR y02 -> s02", "x02 XOR y02 -> t02");
+        let device = Device::try_from(&broken[..]).unwrap();
+        let violations: Vec<_> = device.adder_violations().unwrap().iter().map(Violation::to_string).collect();
+        assert_eq!(violations, [
+            "a01: doesn't combine x and y of the same bit",
+            "t02: should be read by AND, XOR, but is read by nothing",
+        ]);
+
+        // Not shaped like an adder at all.
+        let sample_input = read_file("tests/sample_input");
+        assert_eq!(Device::try_from(&sample_input[..]).unwrap().adder_violations(), None);
+
+        let challenge_input = read_file("tests/challenge_input");
+        let violations: Vec<_> = Device::try_from(&challenge_input[..]).unwrap().adder_violations().unwrap().iter().map(Violation::to_string).collect();
+        assert_eq!(violations.len(), 8);
+        assert!(violations.contains(&"z09: drives an output bit, but is an OR gate instead of the XOR computing the sum".to_string()));
+    }
+
     #[test]
     fn test_challenge() {
         let challenge_input = read_file("tests/challenge_input");